@@ -1,10 +1,16 @@
+use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
 
 use clap::Parser;
 use clap::Subcommand;
 
+use tblang::fmt;
 use tbrepl::repl;
+use tbrepl::repl::Emit;
 
 const ABOUT: &str = "the table language";
 const USAGE: &str = "table <OPTIONS> [FILE | SUBCOMMAND ...]";
@@ -19,6 +25,9 @@ struct Cli {
     cmd: Option<Cmd>,
     /// Script file to run
     file: Option<String>,
+    /// Directory to search for modules, after the script's own directory
+    #[clap(long = "path", value_name = "DIR", global = true, multiple_occurrences = true)]
+    paths: Vec<String>,
 }
 
 #[derive(Subcommand)]
@@ -34,39 +43,96 @@ enum Cmd {
     Run {
         /// Script file to run
         file: String,
+        /// Print a stage of compilation instead of running: tokens, ast or bytecode
+        #[clap(long, value_name = "STAGE")]
+        emit: Option<Emit>,
+    },
+    /// Format source files in place
+    Fmt {
+        /// Source files to format
+        #[clap(required = true)]
+        files: Vec<String>,
+        /// Only check that the files are formatted, failing if any would change
+        #[clap(long)]
+        check: bool,
     },
 }
 
 fn main() {
     let cli = Cli::parse();
-    match cli.cmd {
-        Some(cmd) => handle(&cmd),
+    match &cli.cmd {
+        Some(cmd) => handle(cmd, &cli),
         None => handle_default(&cli),
     }
 }
 
 fn handle_default(cli: &Cli) {
     if let Some(file) = &cli.file {
-        run_script(file);
+        run_script(file, None, &cli.paths);
     } else {
-        run_repl();
+        run_repl(&cli.paths);
     }
 }
 
-fn handle(cmd: &Cmd) {
+fn handle(cmd: &Cmd, cli: &Cli) {
     match cmd {
-        Cmd::Cli => run_repl(),
+        Cmd::Cli => run_repl(&cli.paths),
         Cmd::Build { file } => run_build(file),
-        Cmd::Run { file } => run_script(file),
+        Cmd::Run { file, emit } => run_script(file, *emit, &cli.paths),
+        Cmd::Fmt { files, check } => run_fmt(files, *check),
     }
 }
 
+/// Modules are searched for relative to `root` first, then in the directories from `--path`.
+fn search_paths(root: &Path, paths: &[String]) -> Vec<PathBuf> {
+    let mut dirs = vec![root.to_owned()];
+    dirs.extend(paths.iter().map(PathBuf::from));
+    dirs
+}
+
 fn run_build(_file: &str) {
     println!("work-in-progress");
 }
 
-fn run_script(file: &str) {
-    let mut file = match File::open(file) {
+/// Formats each file, or with `check` reports the files that are not formatted. Exits with an
+/// error status if any file could not be formatted or, with `check`, would change.
+fn run_fmt(files: &[String], check: bool) {
+    let mut failed = false;
+    for path in files {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("[E] {}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = match fmt::format(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("[E] {}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            failed = true;
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("[E] {}: {}", path, e);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn run_script(path: &str, emit: Option<Emit>, paths: &[String]) {
+    let mut file = match File::open(path) {
         Ok(f) => f,
         Err(e) => {
             eprintln!("[E] {}", e);
@@ -80,14 +146,26 @@ fn run_script(file: &str) {
         return;
     }
 
-    match repl::run(&source) {
+    let root = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let paths = search_paths(root, paths);
+    if let Some(stage) = emit {
+        match repl::emit(&source, stage, &paths) {
+            Ok(out) => print!("{}", out),
+            Err(e) => eprintln!("[E] {}", e),
+        }
+        return;
+    }
+    match repl::run(&source, &paths) {
         Ok(_) => {}
         Err(e) => eprintln!("[E] {}", e),
     }
 }
 
-fn run_repl() {
-    match repl::start() {
+fn run_repl(paths: &[String]) {
+    match repl::start(&search_paths(Path::new("."), paths)) {
         Ok(_) => {}
         Err(e) => eprintln!("[E] {}", e),
     }
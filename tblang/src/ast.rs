@@ -1,48 +1,210 @@
+use std::collections::HashMap;
+use std::fmt;
+
 use crate::token::TKind;
 use crate::token::TKind::*;
 
-pub enum Item {
-    // Mod,
-    // Use,
-    // Def,
-    // Impl,
-    // Alias,
-    // Trait,
-    //Fn(FnDef),
-    // Const,
-    //Let(LetBind),
+/// Identifies an item or expression, unique among the nodes of one parse.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub u32);
+
+/// Prints the id as `#3`.
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Information that a pass attaches to nodes, such as their types or what their names resolve
+/// to, kept apart from the tree.
+pub type NodeMap<T> = HashMap<NodeId, T>;
+
+/// The part of the source a node was parsed from, as a range of byte offsets along with the
+/// line and column it starts on. Nodes that were not parsed from source have an empty span on
+/// line 0.
+#[derive(Copy, Clone, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    /// The span from the start of this one to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+}
+
+/// Prints the position and byte range of a span, e.g. `3:5 20..27`.
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{} {}..{}", self.line, self.col, self.start, self.end)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Item {
+    pub id: NodeId,
+    pub span: Span,
+    pub kind: ItemKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ItemKind {
+    Mod(ModDef),
+    Use(UseDef),
+    Def(TyDef),
+    Enum(EnumDef),
+    Impl(ImplDef),
+    Alias(AliasDef),
+    Trait(TraitDef),
+    Fn(FnDef),
+    Const(ConstDef),
+    Let(LetBind),
     Expr(Expr),
 }
 
+impl ItemKind {
+    /// The name an item binds, used to export it from a module with `pub`.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            ItemKind::Mod(def) => Some(&def.name),
+            ItemKind::Use(def) => def.path.last().map(|name| name.as_str()),
+            ItemKind::Def(def) => Some(&def.name),
+            ItemKind::Enum(def) => Some(&def.name),
+            ItemKind::Alias(def) => Some(&def.name),
+            ItemKind::Trait(def) => Some(&def.name),
+            ItemKind::Fn(def) => Some(&def.name),
+            ItemKind::Const(def) => Some(&def.name),
+            ItemKind::Impl(_) | ItemKind::Let(_) | ItemKind::Expr(_) => None,
+        }
+    }
+}
+
+/// The items of a source file or `mod` block, along with the names it marks as `pub`.
+#[derive(Debug, PartialEq)]
 pub struct Ast {
     pub nodes: Vec<Item>,
+    pub exports: Vec<String>,
+}
+
+impl Default for Ast {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ast {
     pub fn new() -> Self {
-        Self { nodes: Vec::new() }
+        Self {
+            nodes: Vec::new(),
+            exports: Vec::new(),
+        }
     }
 }
 
-// struct FnDef {
-//     name: String,
-//     params: Vec<FnParam>,
-//     body: Vec<Item>,
-//     ret: Option<TyHint>,
-// }
-//
-// struct FnParam {
-//     name: String,
-//     ty: Option<TyHint>,
-// }
-//
-// struct LetBind {
-//     name: String,
-//     init: Expr,
-//     ty: Option<TyHint>,
-// }
-
-#[derive(PartialEq)]
+/// An inline module, e.g. `mod util { pub fn twice(x) { x * 2 } }`.
+#[derive(Debug, PartialEq)]
+pub struct ModDef {
+    pub name: String,
+    pub body: Ast,
+}
+
+/// An import of a module or one of its items, e.g. `use geometry.shapes` or
+/// `use geometry.shapes.area`.
+#[derive(Debug, PartialEq)]
+pub struct UseDef {
+    pub path: Vec<String>,
+}
+
+/// A function definition. The body is only absent for method signatures declared in a trait.
+/// `doc` holds the text of the `##` comments directly above it, one line per comment.
+#[derive(Debug, PartialEq)]
+pub struct FnDef {
+    pub name: String,
+    pub doc: Option<String>,
+    pub params: Vec<FnParam>,
+    pub body: Option<Vec<Item>>,
+    pub ret: Option<TyHint>,
+}
+
+/// A function parameter. A default value must be a constant expression, and parameters with
+/// defaults come after those without. A variadic parameter (`...rest`) comes last and collects
+/// any remaining arguments into a tuple.
+#[derive(Debug, PartialEq)]
+pub struct FnParam {
+    pub name: String,
+    pub ty: Option<TyHint>,
+    pub default: Option<Expr>,
+    pub variadic: bool,
+}
+
+/// A type annotation naming a built-in type, a user-defined type or an alias.
+#[derive(Debug, PartialEq)]
+pub struct TyHint {
+    pub name: String,
+}
+
+/// A type alias, e.g. `alias Meters = real`.
+#[derive(Debug, PartialEq)]
+pub struct AliasDef {
+    pub name: String,
+    pub ty: TyHint,
+}
+
+/// A constant whose initializer is evaluated at compile time, e.g. `const LIMIT = 2 ^ 10`.
+#[derive(Debug, PartialEq)]
+pub struct ConstDef {
+    pub name: String,
+    pub doc: Option<String>,
+    pub ty: Option<TyHint>,
+    pub init: Expr,
+}
+
+/// A record type definition, e.g. `def Circle(r)`.
+#[derive(Debug, PartialEq)]
+pub struct TyDef {
+    pub name: String,
+    pub doc: Option<String>,
+    pub fields: Vec<String>,
+}
+
+/// A sum type definition, e.g. `enum Shape { Circle(r), Rect(w, h) }`. Variants reuse `TyDef`
+/// for their name and fields.
+#[derive(Debug, PartialEq)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<TyDef>,
+}
+
+/// A trait declaration. Methods with a body provide the default implementation.
+#[derive(Debug, PartialEq)]
+pub struct TraitDef {
+    pub name: String,
+    pub methods: Vec<FnDef>,
+}
+
+/// Methods attached to a type, either inherent (`impl Circle`) or for a trait
+/// (`impl Shape for Circle`).
+#[derive(Debug, PartialEq)]
+pub struct ImplDef {
+    pub trait_name: Option<String>,
+    pub ty_name: String,
+    pub methods: Vec<FnDef>,
+}
+
+/// A binding that always declares new names, shadowing any earlier locals of the same name,
+/// e.g. `let (q, r) = divmod(a, b)`.
+#[derive(Debug, PartialEq)]
+pub struct LetBind {
+    pub pat: Pattern,
+    pub init: Expr,
+    pub ty: Option<TyHint>,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum RelOp {
     Lt,
     Gt,
@@ -61,24 +223,146 @@ impl RelOp {
             TkGtEq => RelOp::Ge,
             TkEqEq => RelOp::Eq,
             TkNotEq => RelOp::Ne,
-            _ => panic!(),
+            kind => unreachable!("{:?} is not a relational operator", kind),
+        }
+    }
+}
+
+/// The arithmetic operator of a compound assignment such as `+=`.
+#[derive(Debug, PartialEq)]
+pub enum AssignOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+impl AssignOp {
+    pub fn from(tkind: TKind) -> Self {
+        match tkind {
+            TkPlusEq => AssignOp::Add,
+            TkMinusEq => AssignOp::Sub,
+            TkStarEq => AssignOp::Mul,
+            TkSlashEq => AssignOp::Div,
+            TkPercentEq => AssignOp::Rem,
+            TkCaretEq => AssignOp::Pow,
+            kind => unreachable!("{:?} is not an assignment operator", kind),
         }
     }
 }
 
-pub enum Expr {
+#[derive(Debug, PartialEq)]
+pub struct Expr {
+    pub id: NodeId,
+    pub span: Span,
+    pub kind: ExprKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExprKind {
     Int(i32),
     Real(f64),
     Bool(bool),
+    Str(String),
     Ident(String),
-    Call(Box<Expr>, Vec<Expr>),
+    Tuple(Vec<Expr>),
+    Block(Vec<Item>),
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, usize),
+    /// A nil-safe field access or method call receiver, `a?.b`, which is unit if `a` is.
+    SafeField(Box<Expr>, String),
+    /// A nil-safe tuple index, `t?.0`.
+    SafeIndex(Box<Expr>, usize),
+    Call(Box<Expr>, Vec<Arg>),
+    /// The `?` operator, which unwraps `Ok` and `Some` and returns `Err` and `None` from the
+    /// enclosing function. It must be parenthesized before a field access, since `a?.b` is a
+    /// nil-safe access.
+    Propagate(Box<Expr>),
     Power(Box<Expr>, Box<Expr>),
     Negate(Box<Expr>),
+    BitNot(Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
     Rem(Box<Expr>, Box<Expr>),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
+    Shl(Box<Expr>, Box<Expr>),
+    Shr(Box<Expr>, Box<Expr>),
+    /// A logical shift right, filling with zeros instead of the sign bit.
+    UShr(Box<Expr>, Box<Expr>),
+    BitAnd(Box<Expr>, Box<Expr>),
+    BitXor(Box<Expr>, Box<Expr>),
+    BitOr(Box<Expr>, Box<Expr>),
+    /// `a ?? b`, which evaluates to `b` only if `a` is unit.
+    Coalesce(Box<Expr>, Box<Expr>),
     Relation(Box<Expr>, Vec<(RelOp, Expr)>),
-    Assign(String, Box<Expr>),
+    /// A range of integers, which includes its end when the flag is set as in `a..=b`.
+    Range(Box<Expr>, Box<Expr>, bool),
+    Assign(Box<Pattern>, Box<Expr>),
+    SetField(Box<Expr>, String, Box<Expr>),
+    SetIndex(Box<Expr>, usize, Box<Expr>),
+    /// A compound assignment to a name, field or index, whose target is only evaluated once.
+    OpAssign(AssignOp, Box<Expr>, Box<Expr>),
+    Match(Box<Expr>, Vec<MatchArm>),
+    /// Suspends the enclosing function, which makes it a generator, handing out a value.
+    Yield(Box<Expr>),
+    Try(Box<TryCatch>),
+    /// A loop binding each element of an iterable to a pattern, e.g. `for (k, v) in pairs {}`.
+    For(Box<Pattern>, Box<Expr>, Vec<Item>),
+}
+
+/// An argument in a call, either positional, named as in `plot(xs, width: 120)` or spread
+/// into positional arguments as in `plot(...args)`.
+#[derive(Debug, PartialEq)]
+pub enum Arg {
+    Pos(Expr),
+    Named(String, Expr),
+    Spread(Expr),
+}
+
+impl Arg {
+    pub fn value(&self) -> &Expr {
+        match self {
+            Arg::Pos(value) => value,
+            Arg::Named(_, value) => value,
+            Arg::Spread(value) => value,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Arg::Named(name, _) => Some(name),
+            Arg::Pos(_) | Arg::Spread(_) => None,
+        }
+    }
+}
+
+/// A `try` expression, which evaluates to its body or, if the body raises an error, to the
+/// catch block with the error bound to a pattern. The finally block runs however the expression
+/// is left, and at least one of the two blocks is present.
+#[derive(Debug, PartialEq)]
+pub struct TryCatch {
+    pub body: Vec<Item>,
+    pub catch: Option<(Pattern, Vec<Item>)>,
+    pub finally: Option<Vec<Item>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MatchArm {
+    pub pat: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    /// A bare name, which is either a fieldless variant or a new binding.
+    Ident(String),
+    /// A literal expression compared for equality.
+    Lit(Expr),
+    Variant(String, Vec<Pattern>),
+    Tuple(Vec<Pattern>),
 }
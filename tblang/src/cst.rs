@@ -0,0 +1,365 @@
+use crate::ast::Ast;
+use crate::lower;
+use crate::parse::Parser;
+use crate::token::TKind;
+use crate::token::Token;
+
+/// A lossless syntax tree. Every token of the source is kept, with the whitespace, comments and
+/// newlines before it attached as its trivia, so printing the tokens in order gives back the
+/// source exactly. The parser builds a typed node for every item, expression and pattern, and
+/// the `Ast` is lowered from these nodes. An item that fails to parse becomes an error node
+/// holding its tokens, so that any source can be represented.
+pub struct Cst {
+    pub root: Node,
+}
+
+/// An inner node of the tree. Its text is the text of its children in order.
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Element>,
+    /// Why the tokens of an error node failed to parse.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NodeKind {
+    /// The items of the source, followed by the end-of-file token.
+    Root,
+    /// An item that failed to parse, from its first token to where parsing picked up again.
+    Error,
+    Mod,
+    Use,
+    Fn,
+    Def,
+    Enum,
+    Trait,
+    Impl,
+    Alias,
+    Const,
+    Let,
+    /// An expression used as an item.
+    ExprItem,
+    /// An enum variant, with its field names.
+    Variant,
+    /// The field names of a type or variant in parentheses.
+    Names,
+    Params,
+    Param,
+    /// A type annotation, along with the `:` or `->` before it.
+    Hint,
+    Block,
+    Literal,
+    Name,
+    /// An expression in parentheses.
+    Paren,
+    Tuple,
+    Unary,
+    /// An arithmetic, bitwise or `??` operation.
+    Binary,
+    Range,
+    Pipe,
+    /// A chain of comparisons, such as `a < b <= c`.
+    Relation,
+    /// An assignment, either plain or compound, to a name, field, index or tuple of names.
+    Assign,
+    Call,
+    Args,
+    Arg,
+    /// A field access or tuple index, either plain or nil-safe.
+    Field,
+    Propagate,
+    Match,
+    Arm,
+    For,
+    Yield,
+    Try,
+    Catch,
+    Finally,
+    /// A name pattern, or `_`.
+    IdentPat,
+    TuplePat,
+    /// A pattern in parentheses.
+    ParenPat,
+    VariantPat,
+    /// A literal pattern, which may be a negative number.
+    LitPat,
+}
+
+pub enum Element {
+    Node(Node),
+    Token(CstToken),
+}
+
+/// A token along with its position in the source and the trivia before it.
+pub struct CstToken {
+    pub kind: TKind,
+    pub text: String,
+    pub offset: usize,
+    pub line: usize,
+    pub col: usize,
+    pub trivia: Vec<Trivia>,
+}
+
+/// Whitespace, a comment, a doc comment or a newline.
+pub struct Trivia {
+    pub kind: TKind,
+    pub text: String,
+}
+
+impl Cst {
+    pub fn parse(src: &str) -> Cst {
+        Parser::new(src).cst()
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for token in self.root.tokens() {
+            token.write(&mut text);
+        }
+        text
+    }
+
+    /// Lowers the tree to the abstract syntax tree, failing with the first error in the source.
+    pub fn ast(&self) -> Result<Ast, String> {
+        lower::lower(&self.root)
+    }
+}
+
+impl Node {
+    /// The tokens under this node, in source order.
+    pub fn tokens(&self) -> Vec<&CstToken> {
+        let mut tokens = Vec::new();
+        for child in &self.children {
+            match child {
+                Element::Node(node) => tokens.extend(node.tokens()),
+                Element::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    /// The source text of the node, without the trivia before its first token.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (idx, token) in self.tokens().into_iter().enumerate() {
+            match idx {
+                0 => text.push_str(&token.text),
+                _ => token.write(&mut text),
+            }
+        }
+        text
+    }
+
+    /// The child nodes of this node.
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Node(node) => Some(node),
+            Element::Token(_) => None,
+        })
+    }
+
+    /// The tokens directly under this node, leaving out those of its child nodes.
+    pub fn child_tokens(&self) -> impl Iterator<Item = &CstToken> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Token(token) => Some(token),
+            Element::Node(_) => None,
+        })
+    }
+
+    /// The first child node of the given kind.
+    pub fn child(&self, kind: NodeKind) -> Option<&Node> {
+        self.nodes().find(|node| node.kind == kind)
+    }
+
+    /// Whether a token of the given kind is directly under this node.
+    pub fn has_token(&self, kind: TKind) -> bool {
+        self.child_tokens().any(|token| token.kind == kind)
+    }
+
+    pub fn first_token(&self) -> Option<&CstToken> {
+        self.children.iter().find_map(|child| match child {
+            Element::Node(node) => node.first_token(),
+            Element::Token(token) => Some(token),
+        })
+    }
+
+    pub fn last_token(&self) -> Option<&CstToken> {
+        self.children.iter().rev().find_map(|child| match child {
+            Element::Node(node) => node.last_token(),
+            Element::Token(token) => Some(token),
+        })
+    }
+}
+
+impl CstToken {
+    pub(crate) fn new(token: &Token, trivia: Vec<Trivia>) -> Self {
+        Self {
+            kind: token.kind,
+            text: token.lexeme().to_owned(),
+            offset: token.offset,
+            line: token.line,
+            col: token.col,
+            trivia,
+        }
+    }
+
+    /// Writes the trivia and then the text of the token.
+    fn write(&self, out: &mut String) {
+        for trivia in &self.trivia {
+            out.push_str(&trivia.text);
+        }
+        out.push_str(&self.text);
+    }
+}
+
+impl Trivia {
+    pub(crate) fn new(token: &Token) -> Self {
+        Self { kind: token.kind, text: token.lexeme().to_owned() }
+    }
+}
+
+/// Builds a tree bottom-up. Tokens are added as they are read, and a node is made once all of
+/// its children are, from the elements added since a checkpoint taken before its first one.
+pub(crate) struct Builder {
+    children: Vec<Element>,
+}
+
+impl Builder {
+    pub(crate) fn new() -> Self {
+        Self { children: Vec::new() }
+    }
+
+    /// The position the next element will be added at.
+    pub(crate) fn checkpoint(&self) -> usize {
+        self.children.len()
+    }
+
+    pub(crate) fn token(&mut self, token: CstToken) {
+        self.children.push(Element::Token(token));
+    }
+
+    /// Wraps the elements added since the checkpoint into a node.
+    pub(crate) fn wrap(&mut self, checkpoint: usize, kind: NodeKind) {
+        let children = self.children.split_off(checkpoint);
+        self.children.push(Element::Node(Node { kind, children, error: None }));
+    }
+
+    /// Wraps the elements added since the checkpoint into an error node.
+    pub(crate) fn wrap_error(&mut self, checkpoint: usize, error: String) {
+        let children = self.children.split_off(checkpoint);
+        self.children.push(Element::Node(Node { kind: NodeKind::Error, children, error: Some(error) }));
+    }
+
+    pub(crate) fn finish(self) -> Cst {
+        Cst { root: Node { kind: NodeKind::Root, children: self.children, error: None } }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use super::Cst;
+    use super::NodeKind;
+    use crate::parse::Parser;
+    use crate::token::TKind::*;
+
+    /// The scripts of the integration test suite, which cover most of the syntax.
+    pub(crate) fn suite_files() -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/suite")];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = "## Adds one.\nfn inc(x) {  # add\n    x + 1 #[ block\n ]#\n}\n\nprintln(inc(1),\n  2)\n";
+        let cst = Cst::parse(src);
+        assert_eq!(cst.text(), src);
+        let items = cst.root.nodes().collect::<Vec<_>>();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].kind, NodeKind::Fn);
+        assert_eq!(items[0].text(), "fn inc(x) {  # add\n    x + 1 #[ block\n ]#\n}");
+        assert_eq!(items[1].kind, NodeKind::ExprItem);
+        assert_eq!(items[1].text(), "println(inc(1),\n  2)");
+        let fn_token = items[0].first_token().unwrap();
+        assert_eq!(fn_token.trivia.iter().map(|trivia| trivia.kind).collect::<Vec<_>>(), [TkDoc, TkNLine]);
+        let comments = cst.root.tokens().into_iter().flat_map(|token| &token.trivia).filter(|trivia| trivia.kind == TkComment);
+        assert_eq!(comments.count(), 2);
+    }
+
+    #[test]
+    fn typed_nodes() {
+        let cst = Cst::parse("impl P {\n    fn get(self) { x.y = -1; self }\n}");
+        let item = cst.root.nodes().next().unwrap();
+        assert_eq!(item.kind, NodeKind::Impl);
+        let method = item.child(NodeKind::Fn).unwrap();
+        assert_eq!(method.text(), "fn get(self) { x.y = -1; self }");
+        let body = method.child(NodeKind::Block).unwrap();
+        let kinds = body.nodes().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [NodeKind::ExprItem, NodeKind::ExprItem]);
+        let assign = body.nodes().next().unwrap().child(NodeKind::Assign).unwrap();
+        let kinds = assign.nodes().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [NodeKind::Field, NodeKind::Unary]);
+    }
+
+    #[test]
+    fn offsets() {
+        let cst = Cst::parse("a = 1 # one\nb");
+        let offsets = cst.root.tokens().into_iter().map(|token| token.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, [0, 2, 4, 12, 13]);
+    }
+
+    #[test]
+    fn error_nodes() {
+        let src = "x = 1\ny = (2 +\n";
+        let cst = Cst::parse(src);
+        assert_eq!(cst.text(), src);
+        let items = cst.root.nodes().collect::<Vec<_>>();
+        let kinds = items.iter().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [NodeKind::ExprItem, NodeKind::Error]);
+        assert_eq!(items[1].error.as_deref(), Some("reached end-of-file"));
+        assert_eq!(cst.ast().err().unwrap(), "reached end-of-file");
+
+        let cst = Cst::parse("fn f() {\n    a = ]\n    b\n}\nz = 3 4\nw = 5");
+        let items = cst.root.nodes().collect::<Vec<_>>();
+        let kinds = items.iter().map(|node| node.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [NodeKind::Fn, NodeKind::Error, NodeKind::ExprItem]);
+        let body = items[0].child(NodeKind::Block).unwrap();
+        let error = body.nodes().next().unwrap();
+        assert_eq!(error.kind, NodeKind::Error);
+        assert_eq!(error.text(), "a = ]");
+        assert_eq!(error.error.as_deref(), Some("unrecognized character ']'"));
+        assert_eq!(items[1].text(), "z = 3 4");
+        assert_eq!(cst.ast().err().unwrap(), "unrecognized character ']'");
+
+        let cst = Cst::parse("fn f() {\n    a = (\n}\n");
+        assert_eq!(cst.root.nodes().next().unwrap().kind, NodeKind::Error);
+        assert_eq!(cst.ast().err().unwrap(), "expected an expression");
+    }
+
+    #[test]
+    fn suite_round_trip() {
+        for path in suite_files() {
+            let src = fs::read_to_string(&path).unwrap();
+            let cst = Cst::parse(&src);
+            assert_eq!(cst.text(), src, "{}", path.display());
+            assert!(cst.root.nodes().all(|node| node.kind != NodeKind::Error), "{}", path.display());
+            let ast = cst.ast().unwrap();
+            assert_eq!(ast.nodes.len(), Parser::new(&src).ast().unwrap().nodes.len());
+            assert_eq!(ast.nodes.len(), cst.root.nodes().count());
+        }
+    }
+}
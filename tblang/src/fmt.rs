@@ -0,0 +1,285 @@
+use crate::ast::Expr;
+use crate::ast::Item;
+use crate::ast::NodeId;
+use crate::ast::Span;
+use crate::cst::Cst;
+use crate::cst::CstToken;
+use crate::cst::Element;
+use crate::cst::Node;
+use crate::token::TKind;
+use crate::token::TKind::*;
+use crate::visit_mut::walk_expr;
+use crate::visit_mut::walk_item;
+use crate::visit_mut::VisitorMut;
+
+const INDENT: &str = "    ";
+
+/// Formats source into the canonical style:
+///
+/// - operators are surrounded by single spaces, except for `.`, `?.` and ranges, and unary
+///   operators are attached to their operand
+/// - statements separated by `;` go on lines of their own, and blocks that held them are split
+///   over several lines
+/// - lines are indented by four spaces per open block or parenthesis, and by one more level
+///   when they continue an expression from the previous line
+/// - runs of blank lines are collapsed into one, and trailing whitespace is removed
+///
+/// Comments are kept, as are line breaks, along with the spacing before a comment at the end
+/// of a line so that aligned comments stay aligned.
+pub fn format(src: &str) -> Result<String, String> {
+    let cst = Cst::parse(src);
+    let mut ast = cst.ast()?;
+    let mut tokens = Vec::new();
+    flatten(&cst.root, &mut tokens);
+    let out = print(&words(&tokens));
+    // Formatting must not change what the source means, only how it looks.
+    let mut formatted = Cst::parse(&out).ast().map_err(|err| format!("formatting produced invalid source: {}", err))?;
+    Erase.visit_items(&mut ast.nodes)?;
+    Erase.visit_items(&mut formatted.nodes)?;
+    if formatted != ast {
+        return Err("formatting changed the meaning of the source".to_owned());
+    }
+    Ok(out)
+}
+
+/// Resets the ids and spans of a tree, which differ between parses of the same items, so that
+/// trees can be compared by what they mean.
+struct Erase;
+
+impl VisitorMut for Erase {
+    fn visit_item(&mut self, item: &mut Item) -> Result<(), String> {
+        item.id = NodeId(0);
+        item.span = Span::default();
+        walk_item(self, item)
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) -> Result<(), String> {
+        expr.id = NodeId(0);
+        expr.span = Span::default();
+        walk_expr(self, expr)
+    }
+}
+
+/// Collects the tokens under a node in order, along with whether each is a brace of a block
+/// that holds `;` separators.
+fn flatten<'a>(node: &'a Node, out: &mut Vec<(&'a CstToken, bool)>) {
+    let split = node.has_token(TkLbrace) && node.has_token(TkSemi);
+    for child in &node.children {
+        match child {
+            Element::Node(node) => flatten(node, out),
+            Element::Token(token) => out.push((token, split && matches!(token.kind, TkLbrace | TkRbrace))),
+        }
+    }
+}
+
+/// Lays out the tokens and their trivia in one list, turning `;` separators into newlines. A
+/// block that held separators also gets newlines after its `{` and before its `}`.
+fn words<'a>(tokens: &[(&'a CstToken, bool)]) -> Vec<(TKind, &'a str)> {
+    let mut out = Vec::new();
+    for (idx, &(token, split)) in tokens.iter().enumerate() {
+        out.extend(token.trivia.iter().map(|trivia| (trivia.kind, trivia.text.as_str())));
+        match token.kind {
+            TkSemi => {
+                if !tokens.get(idx + 1).is_some_and(|&(next, _)| ends_line(next)) {
+                    out.push((TkNLine, "\n"));
+                }
+            }
+            TkEof => {}
+            kind => {
+                if split && kind == TkRbrace {
+                    out.push((TkNLine, "\n"));
+                }
+                out.push((kind, token.text.as_str()));
+                if split && kind == TkLbrace {
+                    out.push((TkNLine, "\n"));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Whether only spaces come between the end of the line and `token`, or `token` is a closing
+/// brace or the end of the input with nothing before it.
+fn ends_line(token: &CstToken) -> bool {
+    match token.trivia.iter().find(|trivia| trivia.kind != TkSpace) {
+        Some(trivia) => trivia.kind == TkNLine,
+        None => matches!(token.kind, TkRbrace | TkEof),
+    }
+}
+
+/// An open parenthesis or brace, with the indentation of its closing line and of the lines
+/// inside it, and the line it was opened on.
+struct Group {
+    paren: bool,
+    outer: usize,
+    inner: usize,
+    row: usize,
+}
+
+fn print(tokens: &[(TKind, &str)]) -> String {
+    let mut out = String::new();
+    let mut groups: Vec<Group> = Vec::new();
+    let mut blank = false;
+    let mut continues = false;
+    for (row, line) in tokens.split(|(kind, _)| *kind == TkNLine).enumerate() {
+        let words = line.iter().filter(|(kind, _)| *kind != TkSpace).collect::<Vec<_>>();
+        let Some(&&(first, _)) = words.first() else {
+            blank = true;
+            continue;
+        };
+        let closes = matches!(first, TkRparen | TkRbrace);
+        let mut level = match groups.last() {
+            Some(group) if closes => group.outer,
+            Some(group) => group.inner,
+            None => 0,
+        };
+        // Lines in parentheses are already indented as continuations.
+        if continues && !closes && !groups.last().is_some_and(|group| group.paren) {
+            level += 1;
+        }
+        // Blank lines are kept between lines, but not at the start or end of a block.
+        if blank && !out.is_empty() && !out.ends_with("{\n") && !out.ends_with("(\n") && !closes {
+            out.push('\n');
+        }
+        blank = false;
+
+        for _ in 0..level {
+            out.push_str(INDENT);
+        }
+        let mut prev: Option<TKind> = None;
+        let mut prefix = false;
+        let mut comment = false;
+        for (idx, &(kind, text)) in line.iter().enumerate() {
+            if kind == TkSpace {
+                continue;
+            }
+            let is_comment = kind == TkComment || kind == TkDoc;
+            if is_comment && (prev.is_some() || comment) {
+                // Keep the spacing before a trailing comment, so aligned comments stay so.
+                match line[idx - 1] {
+                    (TkSpace, space) => out.push_str(space),
+                    _ => out.push(' '),
+                }
+            } else if !is_comment && (comment || prev.is_some_and(|prev| spaced(prev, kind, prefix))) {
+                out.push(' ');
+            }
+            comment = is_comment;
+            out.push_str(text);
+            match kind {
+                TkLparen | TkLbrace => {
+                    // A group opened in a block that was opened on the same line is indented
+                    // within that block, as a block body in a match arm is within the match.
+                    let outer = match groups.last() {
+                        Some(group) if group.row == row && !group.paren => group.inner,
+                        _ => level,
+                    };
+                    groups.push(Group { paren: kind == TkLparen, outer, inner: outer + 1, row });
+                }
+                TkRparen | TkRbrace => {
+                    groups.pop();
+                }
+                _ => {}
+            }
+            if !is_comment {
+                prefix = matches!(kind, TkMinus | TkTilde) && !prev.is_some_and(ends_operand);
+                prev = Some(kind);
+            }
+        }
+        out.push('\n');
+        continues = prev.is_some_and(continues_line);
+    }
+    out
+}
+
+/// Whether a line ending in `kind` continues the expression on the next line.
+fn continues_line(kind: TKind) -> bool {
+    matches!(
+        kind,
+        TkPlus | TkMinus | TkStar | TkSlash | TkPercent | TkCaret | TkAmp | TkBar | TkPipe | TkTilde | TkLtLt
+            | TkGtGt | TkGtGtGt | TkLt | TkGt | TkLtEq | TkGtEq | TkEqEq | TkNotEq | TkEq | TkPlusEq | TkMinusEq
+            | TkStarEq | TkSlashEq | TkPercentEq | TkCaretEq | TkArrow | TkThinArrow | TkQuestionQuestion
+    )
+}
+
+/// Whether a token can end an operand, which decides if a `-` or `~` after it is binary.
+fn ends_operand(kind: TKind) -> bool {
+    matches!(
+        kind,
+        TkIdent | TkInt | TkBin | TkOct | TkHex | TkReal | TkStr | TkTrue | TkFalse | TkRparen | TkRbrace
+            | TkQuestion
+    )
+}
+
+/// Whether to put a space between two tokens on a line. `prefix` is whether `prev` is a unary
+/// operator.
+fn spaced(prev: TKind, next: TKind, prefix: bool) -> bool {
+    if matches!(next, TkRparen | TkComma | TkColon | TkDot | TkQuestionDot | TkQuestion | TkDotDot | TkDotDotEq) {
+        return false;
+    }
+    if matches!(prev, TkLparen | TkDot | TkQuestionDot | TkEllipsis | TkDotDot | TkDotDotEq) {
+        return false;
+    }
+    if prev == TkLbrace && next == TkRbrace {
+        return false;
+    }
+    if next == TkLparen && matches!(prev, TkIdent | TkRparen) {
+        return false;
+    }
+    // Prefix operators stick to their operand.
+    !prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::format;
+    use crate::cst::tests::suite_files;
+
+    #[test]
+    fn spacing() {
+        let src = "x=1+2*-3\ny = f( a,b )?.c\nz = t.0..t.1\nfn g(y:int , ...xs)->int{xs}\n";
+        let out = "x = 1 + 2 * -3\ny = f(a, b)?.c\nz = t.0..t.1\nfn g(y: int, ...xs) -> int { xs }\n";
+        assert_eq!(format(src).unwrap(), out);
+    }
+
+    #[test]
+    fn indentation() {
+        let src = "fn f(x) {\nmatch x {\n1 =>\n\"one\",\n_ => g(\nx,\n2),\n}\n}\n";
+        let out = "fn f(x) {\n    match x {\n        1 =>\n            \"one\",\n        _ => g(\n            x,\n            2),\n    }\n}\n";
+        assert_eq!(format(src).unwrap(), out);
+    }
+
+    #[test]
+    fn semicolons() {
+        let src = "a = 1; b = 2;\nfn f() { a; b }\n";
+        let out = "a = 1\nb = 2\nfn f() {\n    a\n    b\n}\n";
+        assert_eq!(format(src).unwrap(), out);
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let src = "\n\n# head\nx = 1    #=> aligned\n\n\n\nfn f() {\n\n    ## doc\n  #[ block ]# y\n\n}\n";
+        let out = "# head\nx = 1    #=> aligned\n\nfn f() {\n    ## doc\n    #[ block ]# y\n}\n";
+        assert_eq!(format(src).unwrap(), out);
+    }
+
+    #[test]
+    fn block_in_match_arm() {
+        let src = "x = match 1 { 1 => {\n    2\n    3\n}, _ => 4 }\n";
+        let out = "x = match 1 { 1 => {\n        2\n        3\n    }, _ => 4 }\n";
+        assert_eq!(format(src).unwrap(), out);
+        assert_eq!(format(out).unwrap(), out);
+    }
+
+    #[test]
+    fn suite_idempotent() {
+        for path in suite_files() {
+            let src = fs::read_to_string(&path).unwrap();
+            let once = format(&src).unwrap();
+            let twice = format(&once).unwrap();
+            assert_eq!(once, twice, "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use crate::ast::AliasDef;
+use crate::ast::Arg;
+use crate::ast::AssignOp;
+use crate::ast::ConstDef;
+use crate::ast::EnumDef;
+use crate::ast::Expr;
+use crate::ast::ExprKind;
+use crate::ast::FnDef;
+use crate::ast::FnParam;
+use crate::ast::ImplDef;
+use crate::ast::Item;
+use crate::ast::ItemKind;
+use crate::ast::LetBind;
+use crate::ast::MatchArm;
+use crate::ast::ModDef;
+use crate::ast::Pattern;
+use crate::ast::RelOp;
+use crate::ast::TraitDef;
+use crate::ast::TryCatch;
+use crate::ast::TyDef;
+use crate::ast::UseDef;
+
+/// A pass that takes a syntax tree apart and builds a new one from it. It has the same methods
+/// as `Visitor`, each of which by default folds the nodes below it and rebuilds the node from
+/// them. The methods for each kind of expression may return another kind, so a fold can replace
+/// `1 + 2` with `3`, and the node keeps its id and span.
+pub trait Fold {
+    fn fold_items(&mut self, items: Vec<Item>) -> Result<Vec<Item>, String> {
+        items.into_iter().map(|item| self.fold_item(item)).collect()
+    }
+
+    fn fold_item(&mut self, item: Item) -> Result<Item, String> {
+        walk_item(self, item)
+    }
+
+    fn fold_mod(&mut self, mut def: ModDef) -> Result<ModDef, String> {
+        def.body.nodes = self.fold_items(def.body.nodes)?;
+        Ok(def)
+    }
+
+    fn fold_use(&mut self, def: UseDef) -> Result<UseDef, String> {
+        Ok(def)
+    }
+
+    fn fold_def(&mut self, def: TyDef) -> Result<TyDef, String> {
+        Ok(def)
+    }
+
+    fn fold_enum(&mut self, def: EnumDef) -> Result<EnumDef, String> {
+        Ok(def)
+    }
+
+    fn fold_impl(&mut self, mut def: ImplDef) -> Result<ImplDef, String> {
+        def.methods = def.methods.into_iter().map(|method| self.fold_fn(method)).collect::<Result<_, _>>()?;
+        Ok(def)
+    }
+
+    fn fold_alias(&mut self, def: AliasDef) -> Result<AliasDef, String> {
+        Ok(def)
+    }
+
+    fn fold_trait(&mut self, mut def: TraitDef) -> Result<TraitDef, String> {
+        def.methods = def.methods.into_iter().map(|method| self.fold_fn(method)).collect::<Result<_, _>>()?;
+        Ok(def)
+    }
+
+    fn fold_fn(&mut self, def: FnDef) -> Result<FnDef, String> {
+        walk_fn(self, def)
+    }
+
+    fn fold_const(&mut self, mut def: ConstDef) -> Result<ConstDef, String> {
+        def.init = self.fold_expr(def.init)?;
+        Ok(def)
+    }
+
+    fn fold_let(&mut self, mut bind: LetBind) -> Result<LetBind, String> {
+        bind.pat = self.fold_pattern(bind.pat)?;
+        bind.init = self.fold_expr(bind.init)?;
+        Ok(bind)
+    }
+
+    fn fold_pattern(&mut self, pat: Pattern) -> Result<Pattern, String> {
+        walk_pattern(self, pat)
+    }
+
+    fn fold_arg(&mut self, arg: Arg) -> Result<Arg, String> {
+        match arg {
+            Arg::Pos(value) => Ok(Arg::Pos(self.fold_expr(value)?)),
+            Arg::Named(name, value) => Ok(Arg::Named(name, self.fold_expr(value)?)),
+            Arg::Spread(value) => Ok(Arg::Spread(self.fold_expr(value)?)),
+        }
+    }
+
+    fn fold_arm(&mut self, arm: MatchArm) -> Result<MatchArm, String> {
+        let pat = self.fold_pattern(arm.pat)?;
+        let guard = arm.guard.map(|guard| self.fold_expr(guard)).transpose()?;
+        let body = self.fold_expr(arm.body)?;
+        Ok(MatchArm { pat, guard, body })
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Result<Expr, String> {
+        walk_expr(self, expr)
+    }
+
+    fn fold_int(&mut self, lit: i32) -> Result<ExprKind, String> {
+        Ok(ExprKind::Int(lit))
+    }
+
+    fn fold_real(&mut self, lit: f64) -> Result<ExprKind, String> {
+        Ok(ExprKind::Real(lit))
+    }
+
+    fn fold_bool(&mut self, lit: bool) -> Result<ExprKind, String> {
+        Ok(ExprKind::Bool(lit))
+    }
+
+    fn fold_str(&mut self, lit: String) -> Result<ExprKind, String> {
+        Ok(ExprKind::Str(lit))
+    }
+
+    fn fold_ident(&mut self, name: String) -> Result<ExprKind, String> {
+        Ok(ExprKind::Ident(name))
+    }
+
+    fn fold_tuple(&mut self, elems: Vec<Expr>) -> Result<ExprKind, String> {
+        let elems = elems.into_iter().map(|elem| self.fold_expr(elem)).collect::<Result<_, _>>()?;
+        Ok(ExprKind::Tuple(elems))
+    }
+
+    fn fold_block(&mut self, items: Vec<Item>) -> Result<ExprKind, String> {
+        Ok(ExprKind::Block(self.fold_items(items)?))
+    }
+
+    fn fold_field(&mut self, object: Expr, name: String) -> Result<ExprKind, String> {
+        Ok(ExprKind::Field(Box::new(self.fold_expr(object)?), name))
+    }
+
+    fn fold_index(&mut self, tuple: Expr, idx: usize) -> Result<ExprKind, String> {
+        Ok(ExprKind::Index(Box::new(self.fold_expr(tuple)?), idx))
+    }
+
+    fn fold_safe_field(&mut self, object: Expr, name: String) -> Result<ExprKind, String> {
+        Ok(ExprKind::SafeField(Box::new(self.fold_expr(object)?), name))
+    }
+
+    fn fold_safe_index(&mut self, tuple: Expr, idx: usize) -> Result<ExprKind, String> {
+        Ok(ExprKind::SafeIndex(Box::new(self.fold_expr(tuple)?), idx))
+    }
+
+    fn fold_call(&mut self, callee: Expr, args: Vec<Arg>) -> Result<ExprKind, String> {
+        let callee = self.fold_expr(callee)?;
+        let args = args.into_iter().map(|arg| self.fold_arg(arg)).collect::<Result<_, _>>()?;
+        Ok(ExprKind::Call(Box::new(callee), args))
+    }
+
+    fn fold_propagate(&mut self, inner: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Propagate(Box::new(self.fold_expr(inner)?)))
+    }
+
+    fn fold_power(&mut self, base: Expr, exp: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Power(Box::new(self.fold_expr(base)?), Box::new(self.fold_expr(exp)?)))
+    }
+
+    fn fold_negate(&mut self, inner: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Negate(Box::new(self.fold_expr(inner)?)))
+    }
+
+    fn fold_bit_not(&mut self, inner: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::BitNot(Box::new(self.fold_expr(inner)?)))
+    }
+
+    fn fold_mul(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Mul(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_div(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Div(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_rem(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Rem(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_add(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Add(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_sub(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Sub(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_shl(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Shl(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_shr(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Shr(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_ushr(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::UShr(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_bit_and(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::BitAnd(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_bit_xor(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::BitXor(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_bit_or(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::BitOr(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_coalesce(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Coalesce(Box::new(self.fold_expr(lhs)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_relation(&mut self, lhs: Expr, ops: Vec<(RelOp, Expr)>) -> Result<ExprKind, String> {
+        let lhs = self.fold_expr(lhs)?;
+        let ops = ops.into_iter().map(|(op, rhs)| Ok((op, self.fold_expr(rhs)?))).collect::<Result<_, String>>()?;
+        Ok(ExprKind::Relation(Box::new(lhs), ops))
+    }
+
+    fn fold_range(&mut self, start: Expr, end: Expr, inclusive: bool) -> Result<ExprKind, String> {
+        Ok(ExprKind::Range(Box::new(self.fold_expr(start)?), Box::new(self.fold_expr(end)?), inclusive))
+    }
+
+    fn fold_assign(&mut self, pat: Pattern, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Assign(Box::new(self.fold_pattern(pat)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_set_field(&mut self, object: Expr, name: String, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::SetField(Box::new(self.fold_expr(object)?), name, Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_set_index(&mut self, object: Expr, idx: usize, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::SetIndex(Box::new(self.fold_expr(object)?), idx, Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_op_assign(&mut self, op: AssignOp, target: Expr, rhs: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::OpAssign(op, Box::new(self.fold_expr(target)?), Box::new(self.fold_expr(rhs)?)))
+    }
+
+    fn fold_match(&mut self, subject: Expr, arms: Vec<MatchArm>) -> Result<ExprKind, String> {
+        let subject = self.fold_expr(subject)?;
+        let arms = arms.into_iter().map(|arm| self.fold_arm(arm)).collect::<Result<_, _>>()?;
+        Ok(ExprKind::Match(Box::new(subject), arms))
+    }
+
+    fn fold_yield(&mut self, value: Expr) -> Result<ExprKind, String> {
+        Ok(ExprKind::Yield(Box::new(self.fold_expr(value)?)))
+    }
+
+    fn fold_try(&mut self, def: TryCatch) -> Result<ExprKind, String> {
+        Ok(ExprKind::Try(Box::new(walk_try(self, def)?)))
+    }
+
+    fn fold_for(&mut self, pat: Pattern, iterable: Expr, body: Vec<Item>) -> Result<ExprKind, String> {
+        let pat = self.fold_pattern(pat)?;
+        let iterable = self.fold_expr(iterable)?;
+        let body = self.fold_items(body)?;
+        Ok(ExprKind::For(Box::new(pat), Box::new(iterable), body))
+    }
+}
+
+/// Hands an item to the fold method for its kind.
+pub fn walk_item<F: Fold + ?Sized>(folder: &mut F, item: Item) -> Result<Item, String> {
+    let kind = match item.kind {
+        ItemKind::Mod(def) => ItemKind::Mod(folder.fold_mod(def)?),
+        ItemKind::Use(def) => ItemKind::Use(folder.fold_use(def)?),
+        ItemKind::Def(def) => ItemKind::Def(folder.fold_def(def)?),
+        ItemKind::Enum(def) => ItemKind::Enum(folder.fold_enum(def)?),
+        ItemKind::Impl(def) => ItemKind::Impl(folder.fold_impl(def)?),
+        ItemKind::Alias(def) => ItemKind::Alias(folder.fold_alias(def)?),
+        ItemKind::Trait(def) => ItemKind::Trait(folder.fold_trait(def)?),
+        ItemKind::Fn(def) => ItemKind::Fn(folder.fold_fn(def)?),
+        ItemKind::Const(def) => ItemKind::Const(folder.fold_const(def)?),
+        ItemKind::Let(bind) => ItemKind::Let(folder.fold_let(bind)?),
+        ItemKind::Expr(expr) => ItemKind::Expr(folder.fold_expr(expr)?),
+    };
+    Ok(Item { kind, ..item })
+}
+
+/// Folds the parameter defaults and the body of a function.
+pub fn walk_fn<F: Fold + ?Sized>(folder: &mut F, mut def: FnDef) -> Result<FnDef, String> {
+    let mut params = Vec::new();
+    for param in def.params {
+        let default = param.default.map(|default| folder.fold_expr(default)).transpose()?;
+        params.push(FnParam { default, ..param });
+    }
+    def.params = params;
+    def.body = def.body.map(|body| folder.fold_items(body)).transpose()?;
+    Ok(def)
+}
+
+/// Folds the literals in a pattern.
+pub fn walk_pattern<F: Fold + ?Sized>(folder: &mut F, pat: Pattern) -> Result<Pattern, String> {
+    match pat {
+        Pattern::Wildcard | Pattern::Ident(_) => Ok(pat),
+        Pattern::Lit(lit) => Ok(Pattern::Lit(folder.fold_expr(lit)?)),
+        Pattern::Variant(name, pats) => {
+            let pats = pats.into_iter().map(|pat| folder.fold_pattern(pat)).collect::<Result<_, _>>()?;
+            Ok(Pattern::Variant(name, pats))
+        }
+        Pattern::Tuple(pats) => {
+            let pats = pats.into_iter().map(|pat| folder.fold_pattern(pat)).collect::<Result<_, _>>()?;
+            Ok(Pattern::Tuple(pats))
+        }
+    }
+}
+
+/// Folds the body of a `try` expression, then its catch and finally blocks.
+pub fn walk_try<F: Fold + ?Sized>(folder: &mut F, def: TryCatch) -> Result<TryCatch, String> {
+    let body = folder.fold_items(def.body)?;
+    let catch = match def.catch {
+        Some((pat, body)) => Some((folder.fold_pattern(pat)?, folder.fold_items(body)?)),
+        None => None,
+    };
+    let finally = def.finally.map(|body| folder.fold_items(body)).transpose()?;
+    Ok(TryCatch { body, catch, finally })
+}
+
+/// Hands an expression to the fold method for its kind.
+pub fn walk_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expr) -> Result<Expr, String> {
+    let kind = match expr.kind {
+        ExprKind::Int(lit) => folder.fold_int(lit),
+        ExprKind::Real(lit) => folder.fold_real(lit),
+        ExprKind::Bool(lit) => folder.fold_bool(lit),
+        ExprKind::Str(lit) => folder.fold_str(lit),
+        ExprKind::Ident(name) => folder.fold_ident(name),
+        ExprKind::Tuple(elems) => folder.fold_tuple(elems),
+        ExprKind::Block(items) => folder.fold_block(items),
+        ExprKind::Field(object, name) => folder.fold_field(*object, name),
+        ExprKind::Index(tuple, idx) => folder.fold_index(*tuple, idx),
+        ExprKind::SafeField(object, name) => folder.fold_safe_field(*object, name),
+        ExprKind::SafeIndex(tuple, idx) => folder.fold_safe_index(*tuple, idx),
+        ExprKind::Call(callee, args) => folder.fold_call(*callee, args),
+        ExprKind::Propagate(inner) => folder.fold_propagate(*inner),
+        ExprKind::Power(base, exp) => folder.fold_power(*base, *exp),
+        ExprKind::Negate(inner) => folder.fold_negate(*inner),
+        ExprKind::BitNot(inner) => folder.fold_bit_not(*inner),
+        ExprKind::Mul(lhs, rhs) => folder.fold_mul(*lhs, *rhs),
+        ExprKind::Div(lhs, rhs) => folder.fold_div(*lhs, *rhs),
+        ExprKind::Rem(lhs, rhs) => folder.fold_rem(*lhs, *rhs),
+        ExprKind::Add(lhs, rhs) => folder.fold_add(*lhs, *rhs),
+        ExprKind::Sub(lhs, rhs) => folder.fold_sub(*lhs, *rhs),
+        ExprKind::Shl(lhs, rhs) => folder.fold_shl(*lhs, *rhs),
+        ExprKind::Shr(lhs, rhs) => folder.fold_shr(*lhs, *rhs),
+        ExprKind::UShr(lhs, rhs) => folder.fold_ushr(*lhs, *rhs),
+        ExprKind::BitAnd(lhs, rhs) => folder.fold_bit_and(*lhs, *rhs),
+        ExprKind::BitXor(lhs, rhs) => folder.fold_bit_xor(*lhs, *rhs),
+        ExprKind::BitOr(lhs, rhs) => folder.fold_bit_or(*lhs, *rhs),
+        ExprKind::Coalesce(lhs, rhs) => folder.fold_coalesce(*lhs, *rhs),
+        ExprKind::Relation(lhs, ops) => folder.fold_relation(*lhs, ops),
+        ExprKind::Range(start, end, inclusive) => folder.fold_range(*start, *end, inclusive),
+        ExprKind::Assign(pat, rhs) => folder.fold_assign(*pat, *rhs),
+        ExprKind::SetField(object, name, rhs) => folder.fold_set_field(*object, name, *rhs),
+        ExprKind::SetIndex(object, idx, rhs) => folder.fold_set_index(*object, idx, *rhs),
+        ExprKind::OpAssign(op, target, rhs) => folder.fold_op_assign(op, *target, *rhs),
+        ExprKind::Match(subject, arms) => folder.fold_match(*subject, arms),
+        ExprKind::Yield(value) => folder.fold_yield(*value),
+        ExprKind::Try(def) => folder.fold_try(*def),
+        ExprKind::For(pat, iterable, body) => folder.fold_for(*pat, *iterable, body),
+    }?;
+    Ok(Expr { kind, ..expr })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fold;
+    use crate::ast::Expr;
+    use crate::ast::ExprKind;
+    use crate::ast::Item;
+    use crate::ast::ItemKind;
+    use crate::parse::Parser;
+
+    /// Replaces sums of integer literals with their result.
+    struct AddInts;
+
+    impl Fold for AddInts {
+        fn fold_add(&mut self, lhs: Expr, rhs: Expr) -> Result<ExprKind, String> {
+            let (lhs, rhs) = (self.fold_expr(lhs)?, self.fold_expr(rhs)?);
+            match (&lhs.kind, &rhs.kind) {
+                (ExprKind::Int(lhs), ExprKind::Int(rhs)) => Ok(ExprKind::Int(lhs + rhs)),
+                _ => Ok(ExprKind::Add(Box::new(lhs), Box::new(rhs))),
+            }
+        }
+    }
+
+    #[test]
+    fn replaces_nodes() {
+        let ast = Parser::new("x = (1 + 2 + 3, y + 1)").ast().unwrap();
+        let ExprKind::Assign(_, rhs) = &expr(&ast.nodes[0]).kind else { panic!() };
+        let ExprKind::Tuple(elems) = &rhs.kind else { panic!() };
+        let sum = (elems[0].id, elems[0].span);
+        let items = AddInts.fold_items(ast.nodes).unwrap();
+        let ExprKind::Assign(_, rhs) = &expr(&items[0]).kind else { panic!() };
+        let ExprKind::Tuple(elems) = &rhs.kind else { panic!() };
+        assert!(matches!(elems[0].kind, ExprKind::Int(6)));
+        assert_eq!((elems[0].id, elems[0].span), sum);
+        let ExprKind::Add(lhs, rhs) = &elems[1].kind else { panic!() };
+        assert!(matches!(lhs.kind, ExprKind::Ident(_)) && matches!(rhs.kind, ExprKind::Int(1)));
+    }
+
+    fn expr(item: &Item) -> &Expr {
+        match &item.kind {
+            ItemKind::Expr(expr) => expr,
+            _ => panic!(),
+        }
+    }
+}
@@ -3,29 +3,43 @@ use crate::token::TKind::*;
 use crate::token::Token;
 
 pub struct Lexer<'a> {
-    src: &'a [u8],
+    src: &'a str,
     head: usize,
     curr: usize,
     line: usize,
     col: usize,
+    error: Option<String>,
+    trivia: bool,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(src: &'a str) -> Self {
         Self {
-            src: src.as_bytes(),
+            src,
             head: 0,
             curr: 0,
             line: 1,
             col: 1,
+            error: None,
+            trivia: false,
         }
     }
 
+    /// Creates a lexer that also returns whitespace and comments as `TkSpace` and `TkComment`
+    /// tokens, so that the tokens cover the source without gaps.
+    pub fn with_trivia(src: &'a str) -> Self {
+        let mut lexer = Self::new(src);
+        lexer.trivia = true;
+        lexer
+    }
+
     pub fn scan(&mut self) -> Token<'a> {
         let kind = self.next_token_kind();
-        let mut token = Token::new(kind, self.bytes());
+        let mut token = Token::new(kind, self.text());
         token.line = self.line;
         token.col = self.col;
+        token.offset = self.head;
+        token.error = self.error.take();
         if kind == TkNLine {
             self.shift_line();
         } else {
@@ -35,164 +49,326 @@ impl<'a> Lexer<'a> {
     }
 
     fn next_token_kind(&mut self) -> TKind {
+        if self.trivia && is_blank(self.peek(0)) {
+            self.scan_blanks();
+            return TkSpace;
+        }
         self.skip_blanks();
         if self.is_eof() {
             return TkEof;
         }
-        return match self.advance() {
-            b'#' => {
-                self.discard_comment();
-                self.next_token_kind()
+        match self.advance() {
+            '#' if self.matches('[') => match self.scan_block_comment() {
+                true => self.comment(),
+                false => self.malformed("unterminated block comment".to_owned()),
+            },
+            '#' if self.matches('#') => {
+                self.scan_line();
+                TkDoc
+            }
+            '#' => {
+                self.scan_line();
+                self.comment()
+            }
+            '\n' => TkNLine,
+            ';' => TkSemi,
+            '(' => TkLparen,
+            ')' => TkRparen,
+            '{' => TkLbrace,
+            '}' => TkRbrace,
+            ',' => TkComma,
+            '.' if self.peek(0) == '.' && self.peek(1) == '.' => {
+                self.advance();
+                self.advance();
+                TkEllipsis
+            }
+            '.' if self.matches('.') => {
+                if self.matches('=') {
+                    TkDotDotEq
+                } else {
+                    TkDotDot
+                }
+            }
+            '.' => TkDot,
+            ':' => TkColon,
+            '?' => {
+                if self.matches('.') {
+                    TkQuestionDot
+                } else if self.matches('?') {
+                    TkQuestionQuestion
+                } else {
+                    TkQuestion
+                }
             }
-            b'\n' => TkNLine,
-            b';' => TkSemi,
-            b'(' => TkLparen,
-            b')' => TkRparen,
-            b'^' => TkCaret,
-            b'+' => TkPlus,
-            b'-' => TkMinus,
-            b'*' => TkStar,
-            b'/' => TkSlash,
-            b'%' => TkPercent,
-            b'<' => {
-                if self.matches(b'=') {
+            '^' if self.matches('=') => TkCaretEq,
+            '^' => TkCaret,
+            '+' if self.matches('=') => TkPlusEq,
+            '+' => TkPlus,
+            '-' => {
+                if self.matches('>') {
+                    TkThinArrow
+                } else if self.matches('=') {
+                    TkMinusEq
+                } else {
+                    TkMinus
+                }
+            }
+            '*' if self.matches('=') => TkStarEq,
+            '*' => TkStar,
+            '/' if self.matches('=') => TkSlashEq,
+            '/' => TkSlash,
+            '%' if self.matches('=') => TkPercentEq,
+            '%' => TkPercent,
+            '&' => TkAmp,
+            '|' => {
+                if self.matches('>') {
+                    TkPipe
+                } else {
+                    TkBar
+                }
+            }
+            '~' => TkTilde,
+            '<' => {
+                if self.matches('=') {
                     TkLtEq
+                } else if self.matches('<') {
+                    TkLtLt
                 } else {
                     TkLt
                 }
             }
-            b'>' => {
-                if self.matches(b'=') {
+            '>' => {
+                if self.matches('=') {
                     TkGtEq
+                } else if self.matches('>') {
+                    if self.matches('>') {
+                        TkGtGtGt
+                    } else {
+                        TkGtGt
+                    }
                 } else {
                     TkGt
                 }
             }
-            b'=' => {
-                if self.matches(b'=') {
+            '=' => {
+                if self.matches('=') {
                     TkEqEq
+                } else if self.matches('>') {
+                    TkArrow
                 } else {
                     TkEq
                 }
             }
-            b'!' if self.matches(b'=') => TkNotEq,
-            b'0' if self.matches(b'b') => self.scan_bin(),
-            b'0' if self.matches(b'x') => self.scan_hex(),
+            '!' if self.matches('=') => TkNotEq,
+            '"' => self.scan_str(),
+            '0' if self.matches('b') => self.scan_radix(TkBin, is_bin_digit),
+            '0' if self.matches('o') => self.scan_radix(TkOct, is_oct_digit),
+            '0' if self.matches('x') => self.scan_radix(TkHex, is_hex_digit),
             c if is_digit(c) => self.scan_num(),
-            c if is_alscore(c) => self.scan_word(),
+            c if is_ident_start(c) => self.scan_word(),
             _ => TkErr,
-        };
+        }
     }
 
-    fn discard_comment(&mut self) {
-        while !self.is_eof() && self.curr() != b'\n' {
-            self.consume();
+    /// Returns the comment just scanned as a token when keeping trivia, and otherwise skips it.
+    fn comment(&mut self) -> TKind {
+        if self.trivia {
+            return TkComment;
         }
         self.shift();
+        self.next_token_kind()
     }
 
-    fn scan_bin(&mut self) -> TKind {
-        let mut valid = true;
-        let mut has_bin = false;
-        while !self.is_eof() {
-            let c = self.curr();
-            let is_sep = is_digit_sep(c);
-            if !is_sep && !is_digit(c) {
-                break;
-            }
-            let is_bin = is_bin_digit(c);
-            has_bin = has_bin || is_bin;
-            valid = valid && (is_bin || is_sep);
+    fn scan_line(&mut self) {
+        while !self.is_eof() && self.curr() != '\n' {
             self.consume();
         }
-        return if valid && has_bin { TkBin } else { TkErr };
     }
 
-    fn scan_hex(&mut self) -> TKind {
-        let mut valid = true;
-        let mut has_hex = false;
+    /// Scans a block comment `#[ ... ]#`, which may span lines and contain nested block comments.
+    /// An unterminated comment is left as the lexeme of an error token.
+    fn scan_block_comment(&mut self) -> bool {
+        let mut depth = 1;
         while !self.is_eof() {
-            let c = self.curr();
-            let is_sep = is_digit_sep(c);
-            if !is_sep && !is_alnum(c) {
-                break;
+            match self.advance() {
+                '#' if self.matches('[') => depth += 1,
+                ']' if self.matches('#') => depth -= 1,
+                '\n' => self.line += 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return true;
             }
-            let is_hex = is_hex_digit(c);
-            has_hex = has_hex || is_hex;
-            valid = valid && (is_hex || is_sep);
-            self.consume();
         }
-        return if valid && has_hex { TkHex } else { TkErr };
+        false
     }
 
-    fn scan_num(&mut self) -> TKind {
-        let mut valid = true;
+    /// Scans the digits of a binary, octal or hex literal after its prefix. Any alphanumerics
+    /// run into the literal, so that `0b12` is one malformed token.
+    fn scan_radix(&mut self, kind: TKind, is_radix_digit: fn(char) -> bool) -> TKind {
+        let base = match kind {
+            TkBin => "binary",
+            TkOct => "octal",
+            _ => "hex",
+        };
+        let mut invalid = None;
+        let mut has_digits = false;
         while !self.is_eof() {
             let c = self.curr();
-            let is_sep = is_digit_sep(c);
-            if !is_sep && !is_alnum(c) {
+            if !is_digit_sep(c) && !is_alnum(c) {
                 break;
             }
-            valid = valid && (is_digit(c) || is_sep);
+            if is_radix_digit(c) {
+                has_digits = true;
+            } else if !is_digit_sep(c) && invalid.is_none() {
+                invalid = Some(c);
+            }
             self.consume();
         }
-        if !valid {
-            return TkErr;
+        if let Some(c) = invalid {
+            return self.malformed(format!("invalid digit '{}' in {} literal '{}'", c, base, self.text()));
+        } else if !has_digits {
+            return self.malformed(format!("missing digits in {} literal '{}'", base, self.text()));
         }
+        kind
+    }
 
-        if is_digit(self.peek(1)) && self.curr() == b'.' {
+    /// Scans a decimal literal, which is a real if it has a fraction, an exponent or an `r`
+    /// suffix. A trailing `.` followed by a name is left alone for tuple indexes such as `t.0.x`,
+    /// unless the name is an exponent as in `1.e3`, and `1..2` is a range.
+    fn scan_num(&mut self) -> TKind {
+        self.scan_digits();
+        let mut kind = TkInt;
+        if self.curr() == '.' && is_digit(self.peek(1)) {
             self.consume();
+            self.scan_digits();
+            kind = TkReal;
+        } else if self.curr() == '.' && self.peek(1) != '.' && !is_ident_start(self.peek(1)) {
             self.consume();
-            while !self.is_eof() && is_digit(self.curr()) {
-                self.consume();
+            return self.malformed(format!("missing digits after the decimal point in '{}'", self.text()));
+        } else if self.curr() == '.' && self.is_exponent(1) {
+            self.consume();
+            self.consume();
+            if !self.matches('+') {
+                self.matches('-');
             }
-            return TkReal;
-        } else {
-            return TkInt;
+            self.skip_suffix();
+            return self.malformed(format!("missing digits after the decimal point in '{}'", self.text()));
         }
+        if self.curr() == 'e' || self.curr() == 'E' {
+            self.consume();
+            if !self.matches('+') {
+                self.matches('-');
+            }
+            if !is_digit(self.curr()) {
+                self.skip_suffix();
+                return self.malformed(format!("missing digits in the exponent of '{}'", self.text()));
+            }
+            self.scan_digits();
+            kind = TkReal;
+        }
+        let suffix_start = self.curr;
+        if self.matches('r') {
+            kind = TkReal;
+        } else if self.matches('i') && kind == TkReal {
+            return self.malformed(format!("real literal '{}' cannot have an integer suffix", self.text()));
+        }
+        if is_ident_continue(self.curr()) {
+            self.skip_suffix();
+            let suffix = &self.src[suffix_start..self.curr];
+            return self.malformed(format!("invalid suffix '{}' on number '{}'", suffix, self.text()));
+        }
+        kind
     }
 
-    fn scan_word(&mut self) -> TKind {
-        while !self.is_eof() && is_alnumscore(self.curr()) {
+    /// Whether an exponent such as `e3` or `E-3` starts `look_ahead` characters ahead.
+    fn is_exponent(&self, look_ahead: usize) -> bool {
+        let sign = matches!(self.peek(look_ahead + 1), '+' | '-') as usize;
+        matches!(self.peek(look_ahead), 'e' | 'E') && is_digit(self.peek(look_ahead + 1 + sign))
+    }
+
+    fn scan_digits(&mut self) {
+        while !self.is_eof() && (is_digit(self.curr()) || is_digit_sep(self.curr())) {
+            self.consume();
+        }
+    }
+
+    fn skip_suffix(&mut self) {
+        while !self.is_eof() && is_ident_continue(self.curr()) {
             self.consume();
         }
+    }
 
-        let t_str = "true".as_bytes();
-        if self.curr <= self.src.len() && self.bytes() == t_str {
-            return TkTrue;
+    /// Produces an error token, recording why the literal or comment it covers is malformed.
+    fn malformed(&mut self, reason: String) -> TKind {
+        self.error = Some(reason);
+        TkErr
+    }
+
+    fn scan_str(&mut self) -> TKind {
+        while !self.is_eof() && self.curr() != '"' {
+            if self.curr() == '\n' {
+                return self.malformed("unterminated string".to_owned());
+            }
+            self.consume();
+        }
+        if self.is_eof() {
+            return self.malformed("unterminated string".to_owned());
         }
+        self.consume();
+        TkStr
+    }
 
-        let f_str = "false".as_bytes();
-        if self.curr <= self.src.len() && self.bytes() == f_str {
-            return TkFalse;
+    fn scan_word(&mut self) -> TKind {
+        while !self.is_eof() && is_ident_continue(self.curr()) {
+            self.consume();
         }
 
-        TkIdent
+        match self.text() {
+            "true" => TkTrue,
+            "false" => TkFalse,
+            "fn" => TkFn,
+            "def" => TkDef,
+            "impl" => TkImpl,
+            "trait" => TkTrait,
+            "for" => TkFor,
+            "enum" => TkEnum,
+            "match" => TkMatch,
+            "if" => TkIf,
+            "alias" => TkAlias,
+            "const" => TkConst,
+            "mod" => TkMod,
+            "use" => TkUse,
+            "pub" => TkPub,
+            "let" => TkLet,
+            "in" => TkIn,
+            "yield" => TkYield,
+            "try" => TkTry,
+            "catch" => TkCatch,
+            "finally" => TkFinally,
+            _ => TkIdent,
+        }
     }
 
     fn is_eof(&self) -> bool {
         self.curr >= self.src.len()
     }
 
-    fn curr(&self) -> u8 {
-        self.src[self.curr]
+    fn curr(&self) -> char {
+        self.peek(0)
     }
 
-    fn bytes(&self) -> &'a [u8] {
+    fn text(&self) -> &'a str {
         &self.src[self.head..self.curr]
     }
 
-    fn peek(&self, look_ahead: usize) -> u8 {
-        if self.curr + look_ahead < self.src.len() {
-            self.src[self.curr + look_ahead]
-        } else {
-            b'\0'
-        }
+    fn peek(&self, look_ahead: usize) -> char {
+        self.src[self.curr..].chars().nth(look_ahead).unwrap_or('\0')
     }
 
-    fn advance(&mut self) -> u8 {
-        let ch = self.src[self.curr];
-        self.curr += 1;
+    fn advance(&mut self) -> char {
+        let ch = self.curr();
+        self.curr += ch.len_utf8();
         ch
     }
 
@@ -201,12 +377,16 @@ impl<'a> Lexer<'a> {
         self.col = 1;
     }
 
+    /// Moves the column past the text just scanned, which only spans lines for block comments.
     fn advance_column(&mut self) {
-        let num_chars = std::str::from_utf8(self.bytes()).unwrap().chars().count();
-        self.col += num_chars;
+        let text = self.text();
+        match text.rfind('\n') {
+            Some(idx) => self.col = 1 + text[idx + 1..].chars().count(),
+            None => self.col += text.chars().count(),
+        }
     }
 
-    fn matches(&mut self, c: u8) -> bool {
+    fn matches(&mut self, c: char) -> bool {
         if self.peek(0) == c {
             self.advance();
             true
@@ -216,7 +396,7 @@ impl<'a> Lexer<'a> {
     }
 
     fn consume(&mut self) {
-        self.curr += 1;
+        self.advance();
     }
 
     fn shift(&mut self) {
@@ -229,54 +409,61 @@ impl<'a> Lexer<'a> {
         self.head = self.curr;
     }
 
-    fn skip_blanks(&mut self) {
-        while !self.is_eof() && is_spacing(self.curr()) {
-            if self.curr() == b'\n' {
-                break;
-            } else {
-                self.consume();
-            }
+    fn scan_blanks(&mut self) {
+        while !self.is_eof() && is_blank(self.curr()) {
+            self.consume();
         }
+    }
+
+    fn skip_blanks(&mut self) {
+        self.scan_blanks();
         self.shift();
     }
 }
 
-fn is_spacing(c: u8) -> bool {
-    (c as char).is_whitespace()
+/// Whitespace other than newlines, which are tokens of their own.
+fn is_blank(c: char) -> bool {
+    c.is_whitespace() && c != '\n'
 }
 
-fn is_digit(c: u8) -> bool {
+fn is_digit(c: char) -> bool {
     c.is_ascii_digit()
 }
 
-fn is_digit_sep(c: u8) -> bool {
-    c == b'_'
+fn is_digit_sep(c: char) -> bool {
+    c == '_'
+}
+
+fn is_bin_digit(c: char) -> bool {
+    c == '0' || c == '1'
 }
 
-fn is_bin_digit(c: u8) -> bool {
-    c == b'0' || c == b'1'
+fn is_oct_digit(c: char) -> bool {
+    ('0'..='7').contains(&c)
 }
 
-fn is_hex_digit(c: u8) -> bool {
+fn is_hex_digit(c: char) -> bool {
     c.is_ascii_hexdigit()
 }
 
-fn is_alnum(c: u8) -> bool {
+fn is_alnum(c: char) -> bool {
     c.is_ascii_alphanumeric()
 }
 
-fn is_alscore(c: u8) -> bool {
-    c == b'_' || c.is_ascii_alphabetic()
+/// Identifiers follow Unicode's XID rules, with an underscore also allowed to start one.
+fn is_ident_start(c: char) -> bool {
+    c == '_' || unicode_ident::is_xid_start(c)
 }
 
-fn is_alnumscore(c: u8) -> bool {
-    c == b'_' || c.is_ascii_alphanumeric()
+fn is_ident_continue(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
 }
 
 #[cfg(test)]
 mod tests {
     use super::Lexer;
     use super::TKind::*;
+    use crate::parse::Parser;
 
     #[test]
     fn line_comment() {
@@ -310,6 +497,119 @@ mod tests {
         assert_eq!(tok.kind, TkEof);
     }
 
+    #[test]
+    fn block_comment() {
+        let src = "1 #[ a #[ nested ]# b ]# 2 #[\n\n]# 3";
+        let mut lexer = Lexer::new(src);
+        let cols = [(TkInt, 1, 1), (TkInt, 1, 26), (TkInt, 3, 4)];
+        for (kind, line, col) in cols {
+            let tok = lexer.scan();
+            assert_eq!((tok.kind, tok.line, tok.col), (kind, line, col));
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn doc_comment() {
+        let src = "## Adds one.\nfn";
+        let mut lexer = Lexer::new(src);
+        let tok = lexer.scan();
+        assert_eq!(tok.kind, TkDoc);
+        assert_eq!(tok.lexeme(), "## Adds one.");
+        assert_eq!(lexer.scan().kind, TkNLine);
+        assert_eq!(lexer.scan().kind, TkFn);
+    }
+
+    #[test]
+    fn trivia() {
+        let src = "a  # note\n\t#[ x\n]# b";
+        let mut lexer = Lexer::with_trivia(src);
+        let tokens = [(TkIdent, "a"), (TkSpace, "  "), (TkComment, "# note"), (TkNLine, "\n"), (TkSpace, "\t"), (TkComment, "#[ x\n]#"), (TkSpace, " "), (TkIdent, "b")];
+        for (kind, lexeme) in tokens {
+            let tok = lexer.scan();
+            assert_eq!((tok.kind, tok.lexeme()), (kind, lexeme));
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn compound_assign() {
+        let src = "+= -= *= /= %= ^= -> - =";
+        let mut lexer = Lexer::new(src);
+        let kinds = [TkPlusEq, TkMinusEq, TkStarEq, TkSlashEq, TkPercentEq, TkCaretEq, TkThinArrow, TkMinus, TkEq];
+        for kind in kinds {
+            assert_eq!(lexer.scan().kind, kind);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn bitwise() {
+        let src = "& | ~ << >> >>> < > <= >= |>";
+        let mut lexer = Lexer::new(src);
+        let kinds = [TkAmp, TkBar, TkTilde, TkLtLt, TkGtGt, TkGtGtGt, TkLt, TkGt, TkLtEq, TkGtEq, TkPipe];
+        for kind in kinds {
+            assert_eq!(lexer.scan().kind, kind);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn ellipsis() {
+        let src = "... .. . ..= 1..2";
+        let mut lexer = Lexer::new(src);
+        let kinds = [TkEllipsis, TkDotDot, TkDot, TkDotDotEq, TkInt, TkDotDot, TkInt];
+        for kind in kinds {
+            assert_eq!(lexer.scan().kind, kind);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn question() {
+        let src = "? ?. ?? a?.b";
+        let mut lexer = Lexer::new(src);
+        let kinds = [TkQuestion, TkQuestionDot, TkQuestionQuestion, TkIdent, TkQuestionDot, TkIdent];
+        for kind in kinds {
+            assert_eq!(lexer.scan().kind, kind);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn unicode_ident() {
+        let src = "größe 名前 _x1 ñ_2";
+        let mut lexer = Lexer::new(src);
+        for lexeme in ["größe", "名前", "_x1", "ñ_2"] {
+            let tok = lexer.scan();
+            assert_eq!(tok.kind, TkIdent);
+            assert_eq!(tok.lexeme(), lexeme);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn unrecognized_char() {
+        let src = "a € b";
+        let mut lexer = Lexer::new(src);
+        assert_eq!(lexer.scan().kind, TkIdent);
+        let tok = lexer.scan();
+        assert_eq!(tok.kind, TkErr);
+        assert_eq!(tok.lexeme(), "€");
+        assert_eq!(lexer.scan().kind, TkIdent);
+    }
+
+    #[test]
+    fn column() {
+        let src = "名前 = \"ü\" # kommentär\n  x";
+        let mut lexer = Lexer::new(src);
+        let cols = [(TkIdent, 1, 1), (TkEq, 1, 4), (TkStr, 1, 6), (TkNLine, 1, 21), (TkIdent, 2, 3)];
+        for (kind, line, col) in cols {
+            let tok = lexer.scan();
+            assert_eq!((tok.kind, tok.line, tok.col), (kind, line, col));
+        }
+    }
+
     #[test]
     fn literal_bin() {
         let src = "0b1101";
@@ -429,4 +729,136 @@ mod tests {
         let tok = lexer.scan();
         assert_eq!(tok.kind, TkEof);
     }
+
+    #[test]
+    fn literal_int_invalid_digits_reason() {
+        let src = "0b0123 0o758 0x_ 12ab";
+        let mut lexer = Lexer::new(src);
+        let reasons = [
+            "invalid digit '2' in binary literal '0b0123'",
+            "invalid digit '8' in octal literal '0o758'",
+            "missing digits in hex literal '0x_'",
+            "invalid suffix 'ab' on number '12ab'",
+        ];
+        for reason in reasons {
+            let tok = lexer.scan();
+            assert_eq!(tok.kind, TkErr);
+            assert_eq!(tok.error.as_deref(), Some(reason));
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn literal_oct() {
+        let src = "0o755 0o_17";
+        let mut lexer = Lexer::new(src);
+        assert_eq!(lexer.scan().kind, TkOct);
+        assert_eq!(lexer.scan().kind, TkOct);
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn literal_real() {
+        let src = "3.14 1_000.5";
+        let mut lexer = Lexer::new(src);
+        assert_eq!(lexer.scan().kind, TkReal);
+        assert_eq!(lexer.scan().kind, TkReal);
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn literal_real_exponent() {
+        let src = "1e9 2.5e-3 6E+2";
+        let mut lexer = Lexer::new(src);
+        for lexeme in ["1e9", "2.5e-3", "6E+2"] {
+            let tok = lexer.scan();
+            assert_eq!(tok.kind, TkReal);
+            assert_eq!(tok.lexeme(), lexeme);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn literal_real_missing_exponent() {
+        let src = "1e 2.5e-x";
+        let mut lexer = Lexer::new(src);
+        let reasons = ["missing digits in the exponent of '1e'", "missing digits in the exponent of '2.5e-x'"];
+        for reason in reasons {
+            let tok = lexer.scan();
+            assert_eq!(tok.kind, TkErr);
+            assert_eq!(tok.error.as_deref(), Some(reason));
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn literal_real_trailing_dot() {
+        let src = "1. + 2";
+        let mut lexer = Lexer::new(src);
+        let tok = lexer.scan();
+        assert_eq!(tok.kind, TkErr);
+        assert_eq!(tok.error.as_deref(), Some("missing digits after the decimal point in '1.'"));
+        assert_eq!(lexer.scan().kind, TkPlus);
+    }
+
+    #[test]
+    fn literal_real_dot_exponent() {
+        let src = "1.e3 2.E-4 + 1";
+        let mut lexer = Lexer::new(src);
+        let reasons = [
+            "missing digits after the decimal point in '1.e3'",
+            "missing digits after the decimal point in '2.E-4'",
+        ];
+        for reason in reasons {
+            let tok = lexer.scan();
+            assert_eq!(tok.kind, TkErr);
+            assert_eq!(tok.error.as_deref(), Some(reason));
+        }
+        assert_eq!(lexer.scan().kind, TkPlus);
+    }
+
+    #[test]
+    fn literal_int_out_of_range() {
+        let src = "3000000000";
+        let mut lexer = Lexer::new(src);
+        assert_eq!(lexer.scan().kind, TkInt);
+        assert_eq!(lexer.scan().kind, TkEof);
+        let err = Parser::new(src).ast().unwrap_err();
+        assert!(err.contains("integer literal out of range"), "{}", err);
+    }
+
+    #[test]
+    fn literal_int_before_dot() {
+        let src = "0.x 1..2";
+        let mut lexer = Lexer::new(src);
+        let kinds = [TkInt, TkDot, TkIdent, TkInt, TkDotDot, TkInt];
+        for kind in kinds {
+            assert_eq!(lexer.scan().kind, kind);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn literal_suffix() {
+        let src = "10i 10r 1.5r 1e3r";
+        let mut lexer = Lexer::new(src);
+        let kinds = [TkInt, TkReal, TkReal, TkReal];
+        for kind in kinds {
+            assert_eq!(lexer.scan().kind, kind);
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
+
+    #[test]
+    fn literal_suffix_invalid() {
+        let src = "2.5i 10u";
+        let mut lexer = Lexer::new(src);
+        let reasons = ["real literal '2.5i' cannot have an integer suffix", "invalid suffix 'u' on number '10u'"];
+        for reason in reasons {
+            let tok = lexer.scan();
+            assert_eq!(tok.kind, TkErr);
+            assert_eq!(tok.error.as_deref(), Some(reason));
+        }
+        assert_eq!(lexer.scan().kind, TkEof);
+    }
 }
@@ -1,4 +1,10 @@
 pub mod ast;
+pub mod cst;
+pub mod fmt;
+pub mod fold;
 pub mod lex;
+pub mod lower;
 pub mod parse;
 pub mod token;
+pub mod visit;
+pub mod visit_mut;
@@ -0,0 +1,436 @@
+use crate::ast::AliasDef;
+use crate::ast::Arg;
+use crate::ast::AssignOp;
+use crate::ast::Ast;
+use crate::ast::ConstDef;
+use crate::ast::EnumDef;
+use crate::ast::Expr;
+use crate::ast::ExprKind;
+use crate::ast::FnDef;
+use crate::ast::FnParam;
+use crate::ast::ImplDef;
+use crate::ast::Item;
+use crate::ast::ItemKind;
+use crate::ast::LetBind;
+use crate::ast::MatchArm;
+use crate::ast::ModDef;
+use crate::ast::NodeId;
+use crate::ast::Pattern;
+use crate::ast::RelOp;
+use crate::ast::Span;
+use crate::ast::TraitDef;
+use crate::ast::TryCatch;
+use crate::ast::TyDef;
+use crate::ast::TyHint;
+use crate::ast::UseDef;
+use crate::cst::CstToken;
+use crate::cst::Node;
+use crate::cst::NodeKind;
+use crate::token::TKind::*;
+use std::num::IntErrorKind;
+
+/// Lowers the items under the root of a `Cst` to an `Ast`, failing with the message of the
+/// first error node. Literals are only converted to values here, so a malformed number is
+/// reported while lowering rather than while parsing.
+pub fn lower(root: &Node) -> Result<Ast, String> {
+    Lowering { ids: 0 }.module(root)
+}
+
+struct Lowering {
+    ids: u32,
+}
+
+impl Lowering {
+    fn node_id(&mut self) -> NodeId {
+        self.ids += 1;
+        NodeId(self.ids - 1)
+    }
+
+    /// Lowers the items of a source file or `mod` block, exporting the names of `pub` items.
+    fn module(&mut self, node: &Node) -> Result<Ast, String> {
+        let mut ast = Ast::new();
+        for child in node.nodes() {
+            let item = self.item(child)?;
+            if child.child_tokens().next().is_some_and(|token| token.kind == TkPub) {
+                ast.exports.extend(item.kind.name().map(|name| name.to_owned()));
+                // Variants are bound alongside their enum, so they are exported with it.
+                if let ItemKind::Enum(def) = &item.kind {
+                    ast.exports.extend(def.variants.iter().map(|variant| variant.name.to_owned()));
+                }
+            }
+            ast.nodes.push(item);
+        }
+        Ok(ast)
+    }
+
+    fn items(&mut self, node: &Node) -> Result<Vec<Item>, String> {
+        node.nodes().map(|child| self.item(child)).collect()
+    }
+
+    fn item(&mut self, node: &Node) -> Result<Item, String> {
+        if let Some(error) = &node.error {
+            return Err(error.clone());
+        }
+        let id = self.node_id();
+        let nodes = node.nodes().collect::<Vec<_>>();
+        let kind = match node.kind {
+            NodeKind::Mod => ItemKind::Mod(ModDef { name: name(node), body: self.module(node)? }),
+            NodeKind::Use => ItemKind::Use(UseDef { path: names(node) }),
+            NodeKind::Fn => ItemKind::Fn(self.fn_def(node)?),
+            NodeKind::Def => ItemKind::Def(TyDef { name: name(node), doc: doc(node), fields: fields(node) }),
+            NodeKind::Enum => {
+                let variants = node.nodes().map(|variant| {
+                    TyDef { name: name(variant), doc: None, fields: fields(variant) }
+                });
+                ItemKind::Enum(EnumDef { name: name(node), variants: variants.collect() })
+            }
+            NodeKind::Trait => {
+                let methods = node.nodes().map(|method| self.fn_def(method)).collect::<Result<_, _>>()?;
+                ItemKind::Trait(TraitDef { name: name(node), methods })
+            }
+            NodeKind::Impl => {
+                let mut names = names(node);
+                let ty_name = names.pop().unwrap();
+                let methods = node.nodes().map(|method| self.fn_def(method)).collect::<Result<_, _>>()?;
+                ItemKind::Impl(ImplDef { trait_name: names.pop(), ty_name, methods })
+            }
+            NodeKind::Alias => ItemKind::Alias(AliasDef { name: name(node), ty: hint(nodes[0]) }),
+            NodeKind::Const => {
+                let init = self.expr(nodes[nodes.len() - 1])?;
+                ItemKind::Const(ConstDef { name: name(node), doc: doc(node), ty: opt_hint(node), init })
+            }
+            NodeKind::Let => {
+                let pat = self.pattern(nodes[0])?;
+                let init = self.expr(nodes[nodes.len() - 1])?;
+                ItemKind::Let(LetBind { pat, init, ty: opt_hint(node) })
+            }
+            NodeKind::ExprItem => ItemKind::Expr(self.expr(nodes[0])?),
+            kind => unreachable!("{:?} is not an item", kind),
+        };
+        Ok(Item { id, span: span(node), kind })
+    }
+
+    fn fn_def(&mut self, node: &Node) -> Result<FnDef, String> {
+        let mut params = Vec::new();
+        for param in node.child(NodeKind::Params).unwrap().nodes() {
+            let default = match param.nodes().find(|node| node.kind != NodeKind::Hint) {
+                Some(default) => Some(self.expr(default)?),
+                None => None,
+            };
+            params.push(FnParam {
+                name: name(param),
+                ty: opt_hint(param),
+                default,
+                variadic: param.has_token(TkEllipsis),
+            });
+        }
+        let body = match node.child(NodeKind::Block) {
+            Some(block) => Some(self.items(block)?),
+            None => None,
+        };
+        Ok(FnDef { name: name(node), doc: doc(node), params, body, ret: opt_hint(node) })
+    }
+
+    fn exprs(&mut self, node: &Node) -> Result<Vec<Expr>, String> {
+        node.nodes().map(|child| self.expr(child)).collect()
+    }
+
+    fn expr(&mut self, node: &Node) -> Result<Expr, String> {
+        let nodes = node.nodes().collect::<Vec<_>>();
+        let operator = node.child_tokens().next().map(|token| token.kind);
+        let kind = match node.kind {
+            // The parentheses only group, so the expression keeps its own span.
+            NodeKind::Paren => return self.expr(nodes[0]),
+            NodeKind::Field => return self.field(node),
+            NodeKind::Literal => literal(node.first_token().unwrap())?,
+            NodeKind::Name => ExprKind::Ident(node.text()),
+            NodeKind::Tuple => ExprKind::Tuple(self.exprs(node)?),
+            NodeKind::Block => ExprKind::Block(self.items(node)?),
+            NodeKind::Unary => {
+                let expr = Box::new(self.expr(nodes[0])?);
+                match operator.unwrap() {
+                    TkMinus => ExprKind::Negate(expr),
+                    TkTilde => ExprKind::BitNot(expr),
+                    _ => unreachable!(),
+                }
+            }
+            NodeKind::Binary => {
+                let lhs = Box::new(self.expr(nodes[0])?);
+                let rhs = Box::new(self.expr(nodes[1])?);
+                match operator.unwrap() {
+                    TkCaret => ExprKind::Power(lhs, rhs),
+                    TkPlus => ExprKind::Add(lhs, rhs),
+                    TkMinus => ExprKind::Sub(lhs, rhs),
+                    TkStar => ExprKind::Mul(lhs, rhs),
+                    TkSlash => ExprKind::Div(lhs, rhs),
+                    TkPercent => ExprKind::Rem(lhs, rhs),
+                    TkLtLt => ExprKind::Shl(lhs, rhs),
+                    TkGtGt => ExprKind::Shr(lhs, rhs),
+                    TkGtGtGt => ExprKind::UShr(lhs, rhs),
+                    TkAmp => ExprKind::BitAnd(lhs, rhs),
+                    TkTilde => ExprKind::BitXor(lhs, rhs),
+                    TkBar => ExprKind::BitOr(lhs, rhs),
+                    TkQuestionQuestion => ExprKind::Coalesce(lhs, rhs),
+                    _ => unreachable!(),
+                }
+            }
+            NodeKind::Range => {
+                let start = Box::new(self.expr(nodes[0])?);
+                let end = Box::new(self.expr(nodes[1])?);
+                ExprKind::Range(start, end, operator == Some(TkDotDotEq))
+            }
+            NodeKind::Pipe => {
+                let lhs = self.expr(nodes[0])?;
+                let rhs = self.expr(nodes[1])?;
+                match rhs.kind {
+                    ExprKind::Call(callee, mut args) => {
+                        args.insert(0, Arg::Pos(lhs));
+                        ExprKind::Call(callee, args)
+                    }
+                    _ => ExprKind::Call(Box::new(rhs), vec![Arg::Pos(lhs)]),
+                }
+            }
+            NodeKind::Relation => {
+                let init = Box::new(self.expr(nodes[0])?);
+                let mut relations = Vec::new();
+                for (token, node) in node.child_tokens().zip(&nodes[1..]) {
+                    relations.push((RelOp::from(token.kind), self.expr(node)?));
+                }
+                ExprKind::Relation(init, relations)
+            }
+            NodeKind::Assign => self.assign(node)?,
+            NodeKind::Call => {
+                let callee = Box::new(self.expr(nodes[0])?);
+                let mut args = Vec::new();
+                for arg in nodes[1].nodes() {
+                    let value = self.expr(arg.nodes().next().unwrap())?;
+                    args.push(if arg.has_token(TkEllipsis) {
+                        Arg::Spread(value)
+                    } else if arg.has_token(TkColon) {
+                        Arg::Named(name(arg), value)
+                    } else {
+                        Arg::Pos(value)
+                    });
+                }
+                ExprKind::Call(callee, args)
+            }
+            NodeKind::Propagate => ExprKind::Propagate(Box::new(self.expr(nodes[0])?)),
+            NodeKind::Match => {
+                let subject = Box::new(self.expr(nodes[0])?);
+                let mut arms = Vec::new();
+                for arm in &nodes[1..] {
+                    let parts = arm.nodes().collect::<Vec<_>>();
+                    let pat = self.pattern(parts[0])?;
+                    let guard = match arm.has_token(TkIf) {
+                        true => Some(self.expr(parts[1])?),
+                        false => None,
+                    };
+                    let body = self.expr(parts[parts.len() - 1])?;
+                    arms.push(MatchArm { pat, guard, body });
+                }
+                ExprKind::Match(subject, arms)
+            }
+            NodeKind::For => {
+                let pat = Box::new(self.pattern(nodes[0])?);
+                let iterable = Box::new(self.expr(nodes[1])?);
+                ExprKind::For(pat, iterable, self.items(nodes[2])?)
+            }
+            NodeKind::Yield => ExprKind::Yield(Box::new(self.expr(nodes[0])?)),
+            NodeKind::Try => {
+                let body = self.items(nodes[0])?;
+                let catch = match node.child(NodeKind::Catch) {
+                    Some(catch) => {
+                        let parts = catch.nodes().collect::<Vec<_>>();
+                        Some((self.pattern(parts[0])?, self.items(parts[1])?))
+                    }
+                    None => None,
+                };
+                let finally = match node.child(NodeKind::Finally) {
+                    Some(finally) => Some(self.items(finally.child(NodeKind::Block).unwrap())?),
+                    None => None,
+                };
+                ExprKind::Try(Box::new(TryCatch { body, catch, finally }))
+            }
+            kind => unreachable!("{:?} is not an expression", kind),
+        };
+        Ok(Expr { id: self.node_id(), span: span(node), kind })
+    }
+
+    /// Lowers an assignment. A compound assignment takes a name, field or index, and a plain one
+    /// a field, index, name or tuple of names to destructure into.
+    fn assign(&mut self, node: &Node) -> Result<ExprKind, String> {
+        let nodes = node.nodes().collect::<Vec<_>>();
+        let operator = node.child_tokens().next().unwrap().kind;
+        let target = nodes[0];
+        if operator != TkEq {
+            let target = self.expr(target)?;
+            if !matches!(target.kind, ExprKind::Ident(_) | ExprKind::Field(..) | ExprKind::Index(..)) {
+                return Err("invalid assignment target".to_owned());
+            }
+            let rhs = self.expr(nodes[1])?;
+            return Ok(ExprKind::OpAssign(AssignOp::from(operator), Box::new(target), Box::new(rhs)));
+        }
+        match target.kind {
+            NodeKind::Name => {
+                let rhs = self.expr(nodes[1])?;
+                Ok(ExprKind::Assign(Box::new(Pattern::Ident(target.text())), Box::new(rhs)))
+            }
+            NodeKind::Tuple if target.nodes().next().is_some() => {
+                let pat = assign_target(target)?;
+                let rhs = self.expr(nodes[1])?;
+                Ok(ExprKind::Assign(Box::new(pat), Box::new(rhs)))
+            }
+            _ => {
+                let target = self.expr(target)?;
+                let rhs = Box::new(self.expr(nodes[1])?);
+                match target.kind {
+                    ExprKind::Field(object, name) => Ok(ExprKind::SetField(object, name, rhs)),
+                    ExprKind::Index(object, idx) => Ok(ExprKind::SetIndex(object, idx, rhs)),
+                    _ => Err("invalid assignment target".to_owned()),
+                }
+            }
+        }
+    }
+
+    /// Lowers a field access or tuple index. Chained indexes like `t.0.1` are scanned as a real
+    /// number and split back into two indexes.
+    fn field(&mut self, node: &Node) -> Result<Expr, String> {
+        let object = self.expr(node.nodes().next().unwrap())?;
+        let mut tokens = node.child_tokens();
+        let safe = tokens.next().unwrap().kind == TkQuestionDot;
+        let field = tokens.next().unwrap();
+        if field.kind == TkIdent {
+            let kind = match safe {
+                true => ExprKind::SafeField(Box::new(object), field.text.clone()),
+                false => ExprKind::Field(Box::new(object), field.text.clone()),
+            };
+            return Ok(Expr { id: self.node_id(), span: span(node), kind });
+        }
+        let span = object.span.to(token_span(field));
+        let mut expr = object;
+        for (i, index) in field.text.split('.').enumerate() {
+            let index = match index.parse::<usize>() {
+                Ok(index) => index,
+                Err(_) => return Err(format!("invalid tuple index '{}'", field.text)),
+            };
+            let kind = match safe && i == 0 {
+                true => ExprKind::SafeIndex(Box::new(expr), index),
+                false => ExprKind::Index(Box::new(expr), index),
+            };
+            expr = Expr { id: self.node_id(), span, kind };
+        }
+        Ok(expr)
+    }
+
+    fn pattern(&mut self, node: &Node) -> Result<Pattern, String> {
+        match node.kind {
+            NodeKind::IdentPat if node.text() == "_" => Ok(Pattern::Wildcard),
+            NodeKind::IdentPat => Ok(Pattern::Ident(node.text())),
+            NodeKind::ParenPat => self.pattern(node.nodes().next().unwrap()),
+            NodeKind::TuplePat => Ok(Pattern::Tuple(self.patterns(node)?)),
+            NodeKind::VariantPat => Ok(Pattern::Variant(name(node), self.patterns(node)?)),
+            NodeKind::LitPat => {
+                let mut kind = literal(node.last_token().unwrap())?;
+                if node.has_token(TkMinus) {
+                    kind = match kind {
+                        ExprKind::Int(int) => ExprKind::Int(-int),
+                        ExprKind::Real(num) => ExprKind::Real(-num),
+                        _ => unreachable!(),
+                    };
+                }
+                Ok(Pattern::Lit(Expr { id: self.node_id(), span: span(node), kind }))
+            }
+            kind => unreachable!("{:?} is not a pattern", kind),
+        }
+    }
+
+    fn patterns(&mut self, node: &Node) -> Result<Vec<Pattern>, String> {
+        node.nodes().map(|child| self.pattern(child)).collect()
+    }
+}
+
+/// Converts an element of a tuple on the left of `=` into the pattern it assigns to.
+fn assign_target(node: &Node) -> Result<Pattern, String> {
+    match node.kind {
+        NodeKind::Name if node.text() == "_" => Ok(Pattern::Wildcard),
+        NodeKind::Name => Ok(Pattern::Ident(node.text())),
+        NodeKind::Paren => assign_target(node.nodes().next().unwrap()),
+        NodeKind::Tuple => Ok(Pattern::Tuple(node.nodes().map(assign_target).collect::<Result<_, _>>()?)),
+        _ => Err("invalid assignment target".to_owned()),
+    }
+}
+
+fn literal(token: &CstToken) -> Result<ExprKind, String> {
+    let lexeme = token.text.as_str();
+    let radix = match token.kind {
+        TkTrue => return Ok(ExprKind::Bool(true)),
+        TkFalse => return Ok(ExprKind::Bool(false)),
+        TkStr => return Ok(ExprKind::Str(lexeme[1..lexeme.len() - 1].to_owned())),
+        TkReal => {
+            return match lexeme.trim_end_matches('r').replace('_', "").parse::<f64>() {
+                Err(_) => Err("invalid real format".to_owned()),
+                Ok(num) => Ok(ExprKind::Real(num)),
+            };
+        }
+        TkInt => 10,
+        TkBin => 2,
+        TkOct => 8,
+        TkHex => 16,
+        _ => unreachable!(),
+    };
+    let digits = match token.kind {
+        TkInt => lexeme.trim_end_matches('i'),
+        _ => &lexeme[2..],
+    };
+    match i32::from_str_radix(&digits.replace('_', ""), radix) {
+        Err(err) if matches!(err.kind(), IntErrorKind::PosOverflow | IntErrorKind::NegOverflow) => {
+            Err("integer literal out of range".to_owned())
+        }
+        Err(_) => Err("invalid integer format".to_owned()),
+        Ok(int) => Ok(ExprKind::Int(int)),
+    }
+}
+
+/// The text of the `##` comments directly above an item, one line per comment.
+fn doc(node: &Node) -> Option<String> {
+    let lines = node.first_token()?.trivia.iter().filter(|trivia| trivia.kind == TkDoc).map(|trivia| {
+        let text = trivia.text.trim_start_matches('#');
+        text.strip_prefix(' ').unwrap_or(text).trim_end()
+    });
+    let lines = lines.collect::<Vec<_>>();
+    match lines.is_empty() {
+        true => None,
+        false => Some(lines.join("\n")),
+    }
+}
+
+/// The names directly under a node, such as the path of a `use`.
+fn names(node: &Node) -> Vec<String> {
+    node.child_tokens().filter(|token| token.kind == TkIdent).map(|token| token.text.clone()).collect()
+}
+
+/// The name an item or parameter declares, which is its first name.
+fn name(node: &Node) -> String {
+    names(node).swap_remove(0)
+}
+
+fn fields(node: &Node) -> Vec<String> {
+    node.child(NodeKind::Names).map(names).unwrap_or_default()
+}
+
+fn hint(node: &Node) -> TyHint {
+    TyHint { name: name(node) }
+}
+
+fn opt_hint(node: &Node) -> Option<TyHint> {
+    node.child(NodeKind::Hint).map(hint)
+}
+
+fn span(node: &Node) -> Span {
+    let first = node.first_token().unwrap();
+    token_span(first).to(token_span(node.last_token().unwrap()))
+}
+
+fn token_span(token: &CstToken) -> Span {
+    Span { start: token.offset, end: token.offset + token.text.len(), line: token.line, col: token.col }
+}
@@ -1,78 +1,132 @@
 use crate::ast::Ast;
-use crate::ast::Expr;
-use crate::ast::Item;
-use crate::ast::RelOp;
+use crate::cst::Builder;
+use crate::cst::Cst;
+use crate::cst::CstToken;
+use crate::cst::NodeKind;
+use crate::cst::Trivia;
 use crate::lex::Lexer;
 use crate::token::TKind;
 use crate::token::TKind::*;
 use crate::token::Token;
+use std::mem;
 
+/// Parses source into a `Cst`. Each token is added to the tree once it becomes the current
+/// token, and the grammar methods wrap the tokens into nodes as they finish parsing them.
 pub struct Parser<'a> {
-    stack: Vec<Expr>,
     lexer: Lexer<'a>,
+    builder: Builder,
     curr: Token<'a>,
     next: Token<'a>,
+    next_trivia: Vec<Trivia>,
+    /// Trivia that goes before the next token added to the tree, which includes the newlines
+    /// that separate items.
+    pending: Vec<Trivia>,
+    delims: Vec<TKind>,
+    last: TKind,
+    /// How many parentheses and braces are open after the current token.
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(src: &'a str) -> Self {
         Self {
-            stack: Vec::new(),
-            lexer: Lexer::new(src),
+            lexer: Lexer::with_trivia(src),
+            builder: Builder::new(),
             curr: Token::eof(),
             next: Token::eof(),
+            next_trivia: Vec::new(),
+            pending: Vec::new(),
+            delims: Vec::new(),
+            last: TkNLine,
+            depth: 0,
         }
     }
 
-    pub fn ast(mut self) -> Result<Ast, String> {
-        self.curr = self.next.clone();
-        self.next = self.lexer.scan();
-        if self.next.kind == TkEof {
-            return Ok(Ast::new());
-        }
+    /// Parses the source and lowers it, failing with the first error in the source.
+    pub fn ast(self) -> Result<Ast, String> {
+        self.cst().ast()
+    }
 
-        self.advance()?;
-        self.expression()?;
-        while self.next.kind != TkEof {
-            if self.next.kind == TkErr {
-                return Err(format!("unrecognized character '{}'", self.next.lexeme()));
-            }
-            let mut has_sep = false;
-            while self.next.kind == TkNLine || self.next.kind == TkSemi {
-                self.curr = self.next.clone();
-                self.next = self.lexer.scan();
-                has_sep = true;
-            }
-            if !has_sep {
-                return Err("expected ';' or newline after expression".to_owned());
-            }
-            if self.next.kind != TkEof {
-                self.advance()?;
-                self.expression()?;
+    /// Parses the source into a lossless syntax tree, ending with the end-of-file token that
+    /// holds the trivia at the end of the source.
+    pub fn cst(mut self) -> Cst {
+        self.shift();
+        // Only nested item lists pass errors on, those at the top level are always recovered.
+        let _ = self.parse_items(TkEof, true);
+        let mut trivia = mem::take(&mut self.pending);
+        trivia.append(&mut self.next_trivia);
+        self.builder.token(CstToken::new(&self.next, trivia));
+        self.builder.finish()
+    }
+
+    /// Reads the next token from the lexer, along with the trivia before it. Whitespace and
+    /// comments are always trivia.
+    ///
+    /// Newlines are trivia inside parentheses and after a binary operator or a comma, so that
+    /// long expressions can span lines. Anywhere else, including directly inside braces, they
+    /// separate items and are returned.
+    fn scan(&mut self) -> (Token<'a>, Vec<Trivia>) {
+        let mut trivia = Vec::new();
+        loop {
+            let token = self.lexer.scan();
+            match token.kind {
+                TkSpace | TkComment | TkDoc => {}
+                TkNLine if self.delims.last() == Some(&TkLparen) || continues_line(self.last) => {}
+                TkNLine => return (token, trivia),
+                kind => {
+                    match kind {
+                        TkLparen | TkLbrace => self.delims.push(kind),
+                        TkRparen | TkRbrace => {
+                            self.delims.pop();
+                        }
+                        _ => {}
+                    }
+                    self.last = kind;
+                    return (token, trivia);
+                }
             }
+            trivia.push(Trivia::new(&token));
         }
+    }
 
-        let mut root = Ast::new();
-        for expr in self.stack {
-            root.nodes.push(Item::Expr(expr));
+    /// Moves on to the next token and adds it to the tree. Newlines that separate items are not
+    /// added as tokens, but kept as trivia of the token after them.
+    fn shift(&mut self) {
+        let (next, trivia) = self.scan();
+        self.curr = mem::replace(&mut self.next, next);
+        let mut trivia = mem::replace(&mut self.next_trivia, trivia);
+        self.pending.append(&mut trivia);
+        match self.curr.kind {
+            TkNLine => self.pending.push(Trivia::new(&self.curr)),
+            TkEof => {}
+            kind => {
+                match kind {
+                    TkLparen | TkLbrace => self.depth += 1,
+                    TkRparen | TkRbrace => self.depth = self.depth.saturating_sub(1),
+                    _ => {}
+                }
+                let trivia = mem::take(&mut self.pending);
+                self.builder.token(CstToken::new(&self.curr, trivia));
+            }
         }
+    }
 
-        Ok(root)
+    /// A checkpoint before the current token, for a node that starts with it.
+    fn mark(&self) -> usize {
+        self.builder.checkpoint() - 1
     }
 
     fn advance(&mut self) -> Result<(), String> {
-        self.curr = self.next.clone();
-        self.next = self.lexer.scan();
+        self.shift();
         match self.curr.kind {
-            TkEof => Err(format!("reached end-of-file")),
-            TkErr => Err(format!("unrecognized character '{}'", self.curr.lexeme())),
+            TkEof => Err("reached end-of-file".to_owned()),
+            TkErr => Err(lex_error(&self.curr)),
             TkNLine => self.advance(),
             _ => Ok(()),
         }
     }
 
     fn match_next(&mut self, tkind: TKind) -> Result<bool, String> {
-        // TODO: need to support newlines somehow
         if self.next.kind == tkind {
             self.advance()?;
             Ok(true)
@@ -82,223 +136,718 @@ impl<'a> Parser<'a> {
     }
 
     fn consume_next(&mut self, tkind: TKind, message: &str) -> Result<(), String> {
-        if self.next.kind == TkNLine {
-            return self.consume_next(tkind, message);
-        } else if self.next.kind == tkind {
+        if self.next.kind == tkind {
             return self.advance();
         }
         Err(message.to_owned())
     }
 
+    fn consume_ident(&mut self, message: &str) -> Result<(), String> {
+        self.consume_next(TkIdent, message)
+    }
+
+    fn skip_separators(&mut self) -> bool {
+        let mut has_sep = false;
+        while self.next.kind == TkNLine || self.next.kind == TkSemi {
+            self.shift();
+            has_sep = true;
+        }
+        has_sep
+    }
+
+    fn skip_list_separators(&mut self) -> bool {
+        let mut has_sep = false;
+        while self.next.kind == TkNLine || self.next.kind == TkComma {
+            self.shift();
+            has_sep = true;
+        }
+        has_sep
+    }
+
+    /// Parses items up to `end`. Module-level item lists allow `pub`, `use` and `mod` items.
+    ///
+    /// An item that fails to parse is skipped up to the separator or closing brace after it, and
+    /// becomes an error node. A nested item list passes an error on instead if the item ran
+    /// past its closing brace or into the end of the input, since the enclosing item is broken
+    /// as well then. The error passed on is the first one in the list.
+    fn parse_items(&mut self, end: TKind, module: bool) -> Result<(), String> {
+        let mut first = None;
+        self.skip_separators();
+        while self.next.kind != end {
+            let start = self.builder.checkpoint();
+            let depth = self.depth;
+            if let Err(msg) = self.parse_item(end, module) {
+                self.skip_item(depth);
+                if end != TkEof && (self.depth < depth || self.next.kind == TkEof) {
+                    return Err(first.unwrap_or(msg));
+                }
+                first.get_or_insert_with(|| msg.clone());
+                self.builder.wrap_error(start, msg);
+                self.skip_separators();
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_item(&mut self, end: TKind, module: bool) -> Result<(), String> {
+        let start = self.builder.checkpoint();
+        self.advance()?;
+        let kind = match module {
+            true => self.module_item()?,
+            false => self.item()?,
+        };
+        self.builder.wrap(start, kind);
+        if self.next.kind == TkErr {
+            return Err(lex_error(&self.next));
+        }
+        let has_sep = self.skip_separators();
+        if !has_sep && self.next.kind != end {
+            return Err("expected ';' or newline after expression".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Skips tokens up to the next separator or closing delimiter outside of the parentheses
+    /// and braces opened since `depth`.
+    fn skip_item(&mut self, depth: usize) {
+        loop {
+            match self.next.kind {
+                TkEof => break,
+                TkNLine | TkSemi | TkRparen | TkRbrace if self.depth <= depth => break,
+                _ => self.shift(),
+            }
+        }
+    }
+
+    fn parse_block(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        self.parse_items(TkRbrace, false)?;
+        self.consume_next(TkRbrace, "expected '}' after block")?;
+        self.builder.wrap(start, NodeKind::Block);
+        Ok(())
+    }
+
+    fn parse_names(&mut self, message: &str) -> Result<(), String> {
+        let start = self.mark();
+        if !self.match_next(TkRparen)? {
+            loop {
+                self.consume_ident(message)?;
+                if !self.match_next(TkComma)? {
+                    break;
+                }
+            }
+            self.consume_next(TkRparen, "expected ')' after names")?;
+        }
+        self.builder.wrap(start, NodeKind::Names);
+        Ok(())
+    }
+
+    fn parse_hint(&mut self) -> Result<(), String> {
+        let start = self.builder.checkpoint();
+        self.consume_ident("expected type name")?;
+        self.builder.wrap(start, NodeKind::Hint);
+        Ok(())
+    }
+
+    fn parse_opt_hint(&mut self, prefix: TKind) -> Result<(), String> {
+        let start = self.builder.checkpoint();
+        if self.match_next(prefix)? {
+            self.consume_ident("expected type name")?;
+            self.builder.wrap(start, NodeKind::Hint);
+        }
+        Ok(())
+    }
+
+    fn parse_params(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        if !self.match_next(TkRparen)? {
+            loop {
+                let param = self.builder.checkpoint();
+                let variadic = self.match_next(TkEllipsis)?;
+                self.consume_ident("expected parameter name")?;
+                let name = self.curr.lexeme();
+                self.parse_opt_hint(TkColon)?;
+                if !variadic && self.match_next(TkEq)? {
+                    self.advance()?;
+                    self.expression()?;
+                }
+                if variadic && self.next.kind != TkRparen {
+                    return Err(format!("variadic parameter '{}' must be the last parameter", name));
+                }
+                self.builder.wrap(param, NodeKind::Param);
+                if !self.match_next(TkComma)? {
+                    break;
+                }
+            }
+            self.consume_next(TkRparen, "expected ')' after parameters")?;
+        }
+        self.builder.wrap(start, NodeKind::Params);
+        Ok(())
+    }
+
+    /// Parses a function after its `fn`, returning whether it has a body.
+    fn parse_fn(&mut self) -> Result<bool, String> {
+        self.consume_ident("expected function name")?;
+        self.consume_next(TkLparen, "expected '(' after function name")?;
+        self.parse_params()?;
+        self.parse_opt_hint(TkThinArrow)?;
+        if !self.match_next(TkLbrace)? {
+            return Ok(false);
+        }
+        self.parse_block()?;
+        Ok(true)
+    }
+
+    /// Parses the methods of a trait or impl, returning whether they all have a body.
+    fn parse_methods(&mut self) -> Result<bool, String> {
+        let mut bodies = true;
+        self.skip_separators();
+        while self.next.kind != TkRbrace {
+            let start = self.builder.checkpoint();
+            self.consume_next(TkFn, "expected method definition")?;
+            bodies &= self.parse_fn()?;
+            self.builder.wrap(start, NodeKind::Fn);
+            let has_sep = self.skip_separators();
+            if !has_sep && self.next.kind != TkRbrace {
+                return Err("expected ';' or newline after method".to_owned());
+            }
+        }
+        self.consume_next(TkRbrace, "expected '}' after methods")?;
+        Ok(bodies)
+    }
+
+    fn module_item(&mut self) -> Result<NodeKind, String> {
+        let is_pub = self.curr.kind == TkPub;
+        if is_pub {
+            self.advance()?;
+        }
+        let kind = match self.curr.kind {
+            TkMod => self.item_mod()?,
+            TkUse => self.item_use()?,
+            _ => self.item()?,
+        };
+        if is_pub && matches!(kind, NodeKind::Impl | NodeKind::Let | NodeKind::ExprItem) {
+            return Err("expected a named item after 'pub'".to_owned());
+        }
+        Ok(kind)
+    }
+
+    fn item(&mut self) -> Result<NodeKind, String> {
+        match self.curr.kind {
+            TkMod | TkUse | TkPub => {
+                Err(format!("'{}' is only allowed at the top level of a module", self.curr.lexeme()))
+            }
+            TkFn => self.item_fn(),
+            TkDef => self.item_def(),
+            TkEnum => self.item_enum(),
+            TkTrait => self.item_trait(),
+            TkImpl => self.item_impl(),
+            TkAlias => self.item_alias(),
+            TkConst => self.item_const(),
+            TkLet => self.item_let(),
+            _ => {
+                self.expression()?;
+                Ok(NodeKind::ExprItem)
+            }
+        }
+    }
+
+    fn item_mod(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected module name")?;
+        self.consume_next(TkLbrace, "expected '{' after module name")?;
+        self.parse_items(TkRbrace, true)?;
+        self.consume_next(TkRbrace, "expected '}' after module")?;
+        Ok(NodeKind::Mod)
+    }
+
+    fn item_use(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected module name after 'use'")?;
+        while self.match_next(TkDot)? {
+            self.consume_ident("expected name after '.'")?;
+        }
+        Ok(NodeKind::Use)
+    }
+
+    fn item_fn(&mut self) -> Result<NodeKind, String> {
+        if !self.parse_fn()? {
+            return Err("expected '{' before function body".to_owned());
+        }
+        Ok(NodeKind::Fn)
+    }
+
+    fn item_def(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected type name")?;
+        if self.match_next(TkLparen)? {
+            self.parse_names("expected field name")?;
+        }
+        Ok(NodeKind::Def)
+    }
+
+    fn item_enum(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected enum name")?;
+        self.consume_next(TkLbrace, "expected '{' after enum name")?;
+        self.skip_list_separators();
+        while self.next.kind != TkRbrace {
+            let start = self.builder.checkpoint();
+            self.consume_ident("expected variant name")?;
+            if self.match_next(TkLparen)? {
+                self.parse_names("expected field name")?;
+            }
+            self.builder.wrap(start, NodeKind::Variant);
+            let has_sep = self.skip_list_separators();
+            if !has_sep && self.next.kind != TkRbrace {
+                return Err("expected ',' or newline after variant".to_owned());
+            }
+        }
+        self.consume_next(TkRbrace, "expected '}' after variants")?;
+        Ok(NodeKind::Enum)
+    }
+
+    fn item_alias(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected alias name")?;
+        self.consume_next(TkEq, "expected '=' after alias name")?;
+        self.parse_hint()?;
+        Ok(NodeKind::Alias)
+    }
+
+    fn item_const(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected const name")?;
+        self.parse_opt_hint(TkColon)?;
+        self.consume_next(TkEq, "expected '=' after const name")?;
+        self.advance()?;
+        self.expression()?;
+        Ok(NodeKind::Const)
+    }
+
+    fn item_let(&mut self) -> Result<NodeKind, String> {
+        self.advance()?;
+        self.parse_pattern()?;
+        self.parse_opt_hint(TkColon)?;
+        self.consume_next(TkEq, "expected '=' after let pattern")?;
+        self.advance()?;
+        self.expression()?;
+        Ok(NodeKind::Let)
+    }
+
+    fn item_trait(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected trait name")?;
+        self.consume_next(TkLbrace, "expected '{' after trait name")?;
+        self.parse_methods()?;
+        Ok(NodeKind::Trait)
+    }
+
+    fn item_impl(&mut self) -> Result<NodeKind, String> {
+        self.consume_ident("expected type name after 'impl'")?;
+        if self.match_next(TkFor)? {
+            self.consume_ident("expected type name after 'for'")?;
+        }
+        self.consume_next(TkLbrace, "expected '{' after impl header")?;
+        if !self.parse_methods()? {
+            return Err("expected '{' before method body".to_owned());
+        }
+        Ok(NodeKind::Impl)
+    }
+
     fn expression(&mut self) -> Result<(), String> {
         self.expr_precedence(Prec::Assign)
     }
 
+    /// Parses an expression of at least the given precedence. Every expression built along the
+    /// way starts at the first token of the operand, so infix operators wrap the nodes from there.
     fn expr_precedence(&mut self, prec: Prec) -> Result<(), String> {
+        let start = self.mark();
         self.dispatch_prefix_op()?;
         while prec <= Prec::of(&self.next.kind) {
             self.advance()?;
-            self.dispatch_infix_op()?;
+            self.dispatch_infix_op(start)?;
         }
         Ok(())
     }
 
-    fn expr_relation(&mut self) -> Result<(), String> {
-        let init = self.stack.pop().unwrap();
-
-        let mut relations = Vec::new();
-        let mut operator = self.curr.kind;
-        let mut prec = Prec::of(&operator);
-
+    fn expr_relation(&mut self, start: usize) -> Result<(), String> {
+        let mut prec = Prec::of(&self.curr.kind);
         loop {
             self.advance()?;
             self.expr_precedence(prec.higher())?;
 
-            let rel_op = RelOp::from(operator);
-            let rel_expr = self.stack.pop().unwrap();
-            relations.push((rel_op, rel_expr));
-
-            operator = self.next.kind;
-            prec = Prec::of(&operator);
+            prec = Prec::of(&self.next.kind);
             if prec == Prec::Relation {
                 self.advance()?;
             } else {
                 break;
             }
         }
-
-        let expr = Expr::Relation(Box::new(init), relations);
-        self.stack.push(expr);
+        self.builder.wrap(start, NodeKind::Relation);
         Ok(())
     }
 
-    fn expr_binary(&mut self) -> Result<(), String> {
-        let operator = self.curr.kind;
-        let prec = Prec::of(&operator);
-
+    fn expr_binary(&mut self, start: usize) -> Result<(), String> {
+        let prec = Prec::of(&self.curr.kind);
         self.advance()?;
         self.expr_precedence(prec.higher())?;
+        self.builder.wrap(start, NodeKind::Binary);
+        Ok(())
+    }
 
-        let rhs = self.stack.pop().unwrap();
-        let lhs = self.stack.pop().unwrap();
-
-        let expr = match operator {
-            TkPlus => Expr::Add(Box::new(lhs), Box::new(rhs)),
-            TkMinus => Expr::Sub(Box::new(lhs), Box::new(rhs)),
-            TkStar => Expr::Mul(Box::new(lhs), Box::new(rhs)),
-            TkSlash => Expr::Div(Box::new(lhs), Box::new(rhs)),
-            TkPercent => Expr::Rem(Box::new(lhs), Box::new(rhs)),
-            _ => unreachable!(),
-        };
+    fn expr_range(&mut self, start: usize) -> Result<(), String> {
+        self.advance()?;
+        self.expr_precedence(Prec::Range.higher())?;
+        self.builder.wrap(start, NodeKind::Range);
+        Ok(())
+    }
 
-        self.stack.push(expr);
+    fn expr_pipe(&mut self, start: usize) -> Result<(), String> {
+        self.advance()?;
+        self.expr_precedence(Prec::Pipe.higher())?;
+        self.builder.wrap(start, NodeKind::Pipe);
         Ok(())
     }
 
     fn expr_unary(&mut self) -> Result<(), String> {
-        let operator = self.curr.kind;
-
+        let start = self.mark();
         self.advance()?;
         self.expr_precedence(Prec::Unary)?;
+        self.builder.wrap(start, NodeKind::Unary);
+        Ok(())
+    }
 
-        let expr = self.stack.pop().unwrap();
-        let expr = match operator {
-            TkMinus => Expr::Negate(Box::new(expr)),
-            _ => unreachable!(),
-        };
-
-        self.stack.push(expr);
+    fn expr_power(&mut self, start: usize) -> Result<(), String> {
+        self.advance()?;
+        self.expr_precedence(Prec::Power)?;
+        self.builder.wrap(start, NodeKind::Binary);
         Ok(())
     }
 
-    fn expr_power(&mut self) -> Result<(), String> {
-        let operator = self.curr.kind;
+    /// Parses a parenthesized expression, which is a tuple if it is empty or contains a comma.
+    /// A tuple followed by `=` is the target of a destructuring assignment.
+    fn expr_group(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        if self.match_next(TkRparen)? {
+            self.builder.wrap(start, NodeKind::Tuple);
+            return Ok(());
+        }
+        self.advance()?;
+        self.expression()?;
+        if !self.match_next(TkComma)? {
+            self.consume_next(TkRparen, "expected ')' after expression")?;
+            self.builder.wrap(start, NodeKind::Paren);
+            return Ok(());
+        }
+
+        while !self.match_next(TkRparen)? {
+            self.advance()?;
+            self.expression()?;
+            if !self.match_next(TkComma)? {
+                self.consume_next(TkRparen, "expected ')' after tuple elements")?;
+                break;
+            }
+        }
+        self.builder.wrap(start, NodeKind::Tuple);
+
+        if self.match_next(TkEq)? {
+            self.advance()?;
+            self.expr_precedence(Prec::Assign.higher())?;
+            self.builder.wrap(start, NodeKind::Assign);
+        }
+        Ok(())
+    }
 
+    fn expr_match(&mut self) -> Result<(), String> {
+        let start = self.mark();
         self.advance()?;
-        self.expr_precedence(Prec::Power)?;
+        self.expression()?;
+        self.consume_next(TkLbrace, "expected '{' after match subject")?;
 
-        let rhs = self.stack.pop().unwrap();
-        let lhs = self.stack.pop().unwrap();
+        self.skip_list_separators();
+        while self.next.kind != TkRbrace {
+            let arm = self.builder.checkpoint();
+            self.advance()?;
+            self.parse_pattern()?;
+            if self.match_next(TkIf)? {
+                self.advance()?;
+                self.expression()?;
+            }
+            self.consume_next(TkArrow, "expected '=>' after pattern")?;
+            self.advance()?;
+            self.expression()?;
+            self.builder.wrap(arm, NodeKind::Arm);
 
-        let expr = match operator {
-            TkCaret => Expr::Power(Box::new(lhs), Box::new(rhs)),
-            _ => unreachable!(),
-        };
+            let has_sep = self.skip_list_separators();
+            if !has_sep && self.next.kind != TkRbrace {
+                return Err("expected ',' or newline after match arm".to_owned());
+            }
+        }
+        self.consume_next(TkRbrace, "expected '}' after match arms")?;
+        self.builder.wrap(start, NodeKind::Match);
+        Ok(())
+    }
 
-        self.stack.push(expr);
+    fn expr_for(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        self.advance()?;
+        self.parse_pattern()?;
+        self.consume_next(TkIn, "expected 'in' after pattern")?;
+        self.advance()?;
+        self.expression()?;
+        self.consume_next(TkLbrace, "expected '{' after for iterable")?;
+        self.parse_block()?;
+        self.builder.wrap(start, NodeKind::For);
         Ok(())
     }
 
-    fn expr_group(&mut self) -> Result<(), String> {
+    fn expr_yield(&mut self) -> Result<(), String> {
+        let start = self.mark();
         self.advance()?;
         self.expression()?;
-        self.consume_next(TkRparen, "expected ')' after expression")?;
+        self.builder.wrap(start, NodeKind::Yield);
         Ok(())
     }
 
-    fn expr_literal(&mut self) -> Result<(), String> {
-        let expr = match self.curr.kind {
-            TkTrue => Expr::Bool(true),
-            TkFalse => Expr::Bool(false),
-            _ => unreachable!(),
-        };
-        self.stack.push(expr);
+    fn expr_try(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        self.consume_next(TkLbrace, "expected '{' after 'try'")?;
+        self.parse_block()?;
+        let catch = self.builder.checkpoint();
+        let has_catch = self.match_next(TkCatch)?;
+        if has_catch {
+            self.advance()?;
+            self.parse_pattern()?;
+            self.consume_next(TkLbrace, "expected '{' after catch pattern")?;
+            self.parse_block()?;
+            self.builder.wrap(catch, NodeKind::Catch);
+        }
+        let finally = self.builder.checkpoint();
+        let has_finally = self.match_next(TkFinally)?;
+        if has_finally {
+            self.consume_next(TkLbrace, "expected '{' after 'finally'")?;
+            self.parse_block()?;
+            self.builder.wrap(finally, NodeKind::Finally);
+        }
+        if !has_catch && !has_finally {
+            return Err("expected 'catch' or 'finally' after try block".to_owned());
+        }
+        self.builder.wrap(start, NodeKind::Try);
         Ok(())
     }
 
-    fn expr_int(&mut self) -> Result<(), String> {
-        let lexeme = self.curr.lexeme()
-            .trim_start_matches("0b")
-            .trim_start_matches("0x")
-            .replace('_', "");
-        let radix = match self.curr.kind {
-            TkInt => 10,
-            TkBin => 2,
-            TkHex => 16,
-            _ => unreachable!(),
-        };
-        let expr = match i32::from_str_radix(&lexeme, radix) {
-            Err(_) => return Err(format!("invalid integer format")),
-            Ok(int) => Expr::Int(int),
+    fn parse_pattern(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        let kind = match self.curr.kind {
+            TkIdent if self.curr.lexeme() == "_" => NodeKind::IdentPat,
+            TkLparen => {
+                let mut count = 0;
+                let mut is_tuple = true;
+                while !self.match_next(TkRparen)? {
+                    self.advance()?;
+                    self.parse_pattern()?;
+                    count += 1;
+                    if !self.match_next(TkComma)? {
+                        self.consume_next(TkRparen, "expected ')' after patterns")?;
+                        is_tuple = count != 1;
+                        break;
+                    }
+                }
+                match is_tuple {
+                    true => NodeKind::TuplePat,
+                    false => NodeKind::ParenPat,
+                }
+            }
+            TkIdent => {
+                if !self.match_next(TkLparen)? {
+                    NodeKind::IdentPat
+                } else {
+                    if !self.match_next(TkRparen)? {
+                        loop {
+                            self.advance()?;
+                            self.parse_pattern()?;
+                            if !self.match_next(TkComma)? {
+                                break;
+                            }
+                        }
+                        self.consume_next(TkRparen, "expected ')' after patterns")?;
+                    }
+                    NodeKind::VariantPat
+                }
+            }
+            TkMinus => {
+                self.advance()?;
+                match self.curr.kind {
+                    TkInt | TkBin | TkOct | TkHex | TkReal => NodeKind::LitPat,
+                    TkTrue | TkFalse | TkStr => return Err("expected a number after '-' in pattern".to_owned()),
+                    _ => return Err("expected a pattern".to_owned()),
+                }
+            }
+            TkInt | TkBin | TkOct | TkHex | TkReal | TkTrue | TkFalse | TkStr => NodeKind::LitPat,
+            _ => return Err("expected a pattern".to_owned()),
         };
-        self.stack.push(expr);
+        self.builder.wrap(start, kind);
         Ok(())
     }
 
-    fn expr_real(&mut self) -> Result<(), String> {
-        let lexeme = self.curr.lexeme()
-            .replace('_', "");
-        let expr = match lexeme.parse::<f64>() {
-            Err(_) => return Err(format!("invalid real format")),
-            Ok(num) => Expr::Real(num),
-        };
-        self.stack.push(expr);
+    fn expr_literal(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        self.builder.wrap(start, NodeKind::Literal);
         Ok(())
     }
 
     fn expr_ident(&mut self) -> Result<(), String> {
-        let name = self.curr.lexeme().to_owned();
-        let expr = if self.match_next(TkEq)? {
+        let start = self.mark();
+        self.builder.wrap(start, NodeKind::Name);
+        if self.match_next(TkEq)? {
             self.advance()?;
             self.expr_precedence(Prec::Assign.higher())?;
-            let rhs = self.stack.pop().unwrap();
-            Expr::Assign(name, Box::new(rhs))
-        } else {
-            Expr::Ident(name)
-        };
-        self.stack.push(expr);
+            self.builder.wrap(start, NodeKind::Assign);
+        }
         Ok(())
     }
 
-    fn expr_call(&mut self) -> Result<(), String> {
+    /// Parses an assignment or compound assignment after its target. Assignments to names are
+    /// handled along with the name in `expr_ident`.
+    fn expr_assign(&mut self, start: usize) -> Result<(), String> {
         self.advance()?;
-        let args = self.parse_arguments()?;
-        let callee = self.stack.pop().unwrap();
-        let expr = Expr::Call(Box::new(callee), args);
-        self.stack.push(expr);
+        self.expr_precedence(Prec::Assign.higher())?;
+        self.builder.wrap(start, NodeKind::Assign);
         Ok(())
     }
 
-    fn parse_arguments(&mut self) -> Result<Vec<Expr>, String> {
-        let mut args = Vec::new();
-        self.expression()?;
-        args.push(self.stack.pop().unwrap());
-        self.consume_next(TkRparen, "expected ')' after arguments")?;
-        Ok(args)
+    fn expr_call(&mut self, start: usize) -> Result<(), String> {
+        self.parse_arguments()?;
+        self.builder.wrap(start, NodeKind::Call);
+        Ok(())
+    }
+
+    fn expr_propagate(&mut self, start: usize) -> Result<(), String> {
+        self.builder.wrap(start, NodeKind::Propagate);
+        Ok(())
+    }
+
+    /// Parses a field access or a tuple index such as `t.0`, or their nil-safe forms after
+    /// `?.`. Chained indexes like `t.0.1` are scanned as a real number.
+    fn expr_field(&mut self, start: usize) -> Result<(), String> {
+        match self.next.kind {
+            TkInt | TkReal => self.advance()?,
+            _ => self.consume_ident("expected field name after '.'")?,
+        }
+        self.builder.wrap(start, NodeKind::Field);
+        Ok(())
+    }
+
+    fn parse_arguments(&mut self) -> Result<(), String> {
+        let start = self.mark();
+        if !self.match_next(TkRparen)? {
+            let mut named = false;
+            loop {
+                let arg = self.builder.checkpoint();
+                self.advance()?;
+                if self.curr.kind == TkEllipsis {
+                    self.advance()?;
+                    self.expression()?;
+                } else if self.curr.kind == TkIdent && self.next.kind == TkColon {
+                    self.advance()?;
+                    self.advance()?;
+                    self.expression()?;
+                    named = true;
+                } else {
+                    self.expression()?;
+                    if named {
+                        return Err("positional argument after named argument".to_owned());
+                    }
+                }
+                self.builder.wrap(arg, NodeKind::Arg);
+                if !self.match_next(TkComma)? {
+                    break;
+                }
+            }
+            if self.next.kind == TkErr {
+                return Err(lex_error(&self.next));
+            }
+            self.consume_next(TkRparen, "expected ')' after arguments")?;
+        }
+        self.builder.wrap(start, NodeKind::Args);
+        Ok(())
     }
 
     fn dispatch_prefix_op(&mut self) -> Result<(), String> {
-        return match self.curr.kind {
-            TkInt => self.expr_int(),
-            TkBin => self.expr_int(),
-            TkHex => self.expr_int(),
-            TkReal => self.expr_real(),
+        match self.curr.kind {
+            TkInt => self.expr_literal(),
+            TkBin => self.expr_literal(),
+            TkOct => self.expr_literal(),
+            TkHex => self.expr_literal(),
+            TkReal => self.expr_literal(),
             TkTrue => self.expr_literal(),
             TkFalse => self.expr_literal(),
+            TkStr => self.expr_literal(),
             TkIdent => self.expr_ident(),
             TkLparen => self.expr_group(),
+            TkLbrace => self.parse_block(),
+            TkMatch => self.expr_match(),
+            TkFor => self.expr_for(),
+            TkYield => self.expr_yield(),
+            TkTry => self.expr_try(),
             TkMinus => self.expr_unary(),
-            _ => Err(format!("expected an expression")),
-        };
+            TkTilde => self.expr_unary(),
+            _ => Err("expected an expression".to_owned()),
+        }
     }
 
-    fn dispatch_infix_op(&mut self) -> Result<(), String> {
-        return match self.curr.kind {
-            TkLparen => self.expr_call(),
-            TkCaret => self.expr_power(),
-            TkPlus => self.expr_binary(),
-            TkMinus => self.expr_binary(),
-            TkStar => self.expr_binary(),
-            TkSlash => self.expr_binary(),
-            TkPercent => self.expr_binary(),
-            TkLt => self.expr_relation(),
-            TkGt => self.expr_relation(),
-            TkLtEq => self.expr_relation(),
-            TkGtEq => self.expr_relation(),
-            TkEqEq => self.expr_relation(),
-            TkNotEq => self.expr_relation(),
-            _ => panic!(),
-        };
+    fn dispatch_infix_op(&mut self, start: usize) -> Result<(), String> {
+        match self.curr.kind {
+            TkLparen => self.expr_call(start),
+            TkDot => self.expr_field(start),
+            TkQuestion => self.expr_propagate(start),
+            TkQuestionDot => self.expr_field(start),
+            TkQuestionQuestion => self.expr_binary(start),
+            TkCaret => self.expr_power(start),
+            TkPlus => self.expr_binary(start),
+            TkMinus => self.expr_binary(start),
+            TkStar => self.expr_binary(start),
+            TkSlash => self.expr_binary(start),
+            TkPercent => self.expr_binary(start),
+            TkLtLt => self.expr_binary(start),
+            TkGtGt => self.expr_binary(start),
+            TkGtGtGt => self.expr_binary(start),
+            TkAmp => self.expr_binary(start),
+            TkTilde => self.expr_binary(start),
+            TkBar => self.expr_binary(start),
+            TkPipe => self.expr_pipe(start),
+            TkDotDot => self.expr_range(start),
+            TkDotDotEq => self.expr_range(start),
+            TkLt => self.expr_relation(start),
+            TkGt => self.expr_relation(start),
+            TkLtEq => self.expr_relation(start),
+            TkGtEq => self.expr_relation(start),
+            TkEqEq => self.expr_relation(start),
+            TkNotEq => self.expr_relation(start),
+            TkEq => self.expr_assign(start),
+            TkPlusEq => self.expr_assign(start),
+            TkMinusEq => self.expr_assign(start),
+            TkStarEq => self.expr_assign(start),
+            TkSlashEq => self.expr_assign(start),
+            TkPercentEq => self.expr_assign(start),
+            TkCaretEq => self.expr_assign(start),
+            kind => unreachable!("{:?} is not an infix operator", kind),
+        }
+    }
+}
+
+/// Whether a line ending in `kind` continues onto the next line, which is the case when it ends
+/// in a binary operator or a comma.
+fn continues_line(kind: TKind) -> bool {
+    matches!(
+        kind,
+        TkComma | TkPlus | TkMinus | TkStar | TkSlash | TkPercent | TkCaret | TkAmp | TkBar | TkPipe | TkTilde
+            | TkLtLt | TkGtGt | TkGtGtGt | TkLt | TkGt | TkLtEq | TkGtEq | TkEqEq | TkNotEq | TkEq | TkPlusEq
+            | TkMinusEq | TkStarEq | TkSlashEq | TkPercentEq | TkCaretEq | TkArrow | TkThinArrow | TkDotDot
+            | TkDotDotEq | TkQuestionQuestion
+    )
+}
+
+/// Describes an error token, which the lexer produces for malformed literals and comments as
+/// well as for characters it does not recognize.
+fn lex_error(token: &Token) -> String {
+    match &token.error {
+        Some(reason) => reason.to_owned(),
+        None => format!("unrecognized character '{}'", token.lexeme()),
     }
 }
 
@@ -306,13 +855,20 @@ impl<'a> Parser<'a> {
 #[derive(PartialEq, PartialOrd)]
 enum Prec {
     None = 0,
-    Assign,   // =
+    Assign,   // = += -= *= /= %= ^=
+    Pipe,     // |>
+    Coalesce, // ??
+    Range,    // .. ..=
     Relation, // < > <= >= == !=
+    BitOr,    // |
+    BitXor,   // ~
+    BitAnd,   // &
+    Shift,    // << >> >>>
     Term,     // + -
     Factor,   // * / %
-    Unary,    // -
+    Unary,    // - ~
     Power,    // ^
-    Call,     // ()
+    Call,     // () . ? ?.
     Primary,
 }
 
@@ -320,12 +876,25 @@ impl Prec {
     fn of(tkind: &TKind) -> Self {
         match tkind {
             TkLparen => Self::Call,
+            TkDot => Self::Call,
+            TkQuestion => Self::Call,
+            TkQuestionDot => Self::Call,
+            TkQuestionQuestion => Self::Coalesce,
             TkCaret => Self::Power,
             TkPlus => Self::Term,
             TkMinus => Self::Term,
             TkStar => Self::Factor,
             TkSlash => Self::Factor,
             TkPercent => Self::Factor,
+            TkLtLt => Self::Shift,
+            TkGtGt => Self::Shift,
+            TkGtGtGt => Self::Shift,
+            TkAmp => Self::BitAnd,
+            TkTilde => Self::BitXor,
+            TkBar => Self::BitOr,
+            TkPipe => Self::Pipe,
+            TkDotDot => Self::Range,
+            TkDotDotEq => Self::Range,
             TkLt => Self::Relation,
             TkGt => Self::Relation,
             TkLtEq => Self::Relation,
@@ -333,6 +902,12 @@ impl Prec {
             TkEqEq => Self::Relation,
             TkNotEq => Self::Relation,
             TkEq => Self::Assign,
+            TkPlusEq => Self::Assign,
+            TkMinusEq => Self::Assign,
+            TkStarEq => Self::Assign,
+            TkSlashEq => Self::Assign,
+            TkPercentEq => Self::Assign,
+            TkCaretEq => Self::Assign,
             _ => Self::None,
         }
     }
@@ -340,8 +915,15 @@ impl Prec {
     fn higher(&self) -> Self {
         match self {
             Self::None => Self::Assign,
-            Self::Assign => Self::Relation,
-            Self::Relation => Self::Term,
+            Self::Assign => Self::Pipe,
+            Self::Pipe => Self::Coalesce,
+            Self::Coalesce => Self::Range,
+            Self::Range => Self::Relation,
+            Self::Relation => Self::BitOr,
+            Self::BitOr => Self::BitXor,
+            Self::BitXor => Self::BitAnd,
+            Self::BitAnd => Self::Shift,
+            Self::Shift => Self::Term,
             Self::Term => Self::Factor,
             Self::Factor => Self::Unary,
             Self::Unary => Self::Power,
@@ -351,3 +933,88 @@ impl Prec {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::ast::Expr;
+    use crate::ast::ExprKind;
+    use crate::ast::ItemKind;
+    use crate::ast::NodeMap;
+    use crate::ast::Pattern;
+    use crate::ast::Span;
+    use crate::visit::walk_expr;
+    use crate::visit::Visitor;
+
+    #[test]
+    fn doc_comments() {
+        let src = "## Adds one.\n##\n## Works on ints.\nfn inc(x) { x + 1 }\n\n## The limit.\npub const MAX = 10\n## A point.\ndef Point(x, y)\n# plain\nfn bare() {}\n## dangling\nx = 1\nfn after() {}";
+        let ast = Parser::new(src).ast().unwrap();
+        let docs = ast.nodes.iter().map(|item| match &item.kind {
+            ItemKind::Fn(def) => def.doc.as_deref(),
+            ItemKind::Const(def) => def.doc.as_deref(),
+            ItemKind::Def(def) => def.doc.as_deref(),
+            _ => None,
+        });
+        let expected = [Some("Adds one.\n\nWorks on ints."), Some("The limit."), Some("A point."), None, None, None];
+        assert!(docs.eq(expected));
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let err = Parser::new("x = 1 #[ outer #[ inner ]# \n").ast().err().unwrap();
+        assert_eq!(err, "unterminated block comment");
+    }
+
+    #[test]
+    fn newline_ends_item() {
+        let err = Parser::new("enum E\n{ A }").ast().err().unwrap();
+        assert_eq!(err, "expected '{' after enum name");
+        assert!(Parser::new("enum E {\n    A,\n    B\n}").ast().is_ok());
+    }
+
+    #[test]
+    fn lex_error_in_arguments() {
+        let err = Parser::new("f(1 @)").ast().err().unwrap();
+        assert_eq!(err, "unrecognized character '@'");
+        let err = Parser::new("f(1, 2 $ 3)").ast().err().unwrap();
+        assert_eq!(err, "unrecognized character '$'");
+    }
+
+    /// Records the source text of every expression by its id.
+    struct Sources<'a>(&'a str, NodeMap<&'a str>);
+
+    impl Visitor for Sources<'_> {
+        fn visit_expr(&mut self, expr: &Expr) -> Result<(), String> {
+            assert!(self.1.insert(expr.id, &self.0[expr.span.start..expr.span.end]).is_none());
+            walk_expr(self, expr)
+        }
+    }
+
+    #[test]
+    fn node_spans() {
+        let src = "x = (1 + 22) * f(y.z)\nfn g() {\n    -a\n}";
+        let ast = Parser::new(src).ast().unwrap();
+        assert_eq!(ast.nodes[0].span, Span { start: 0, end: 21, line: 1, col: 1 });
+        assert_eq!(ast.nodes[1].span, Span { start: 22, end: 39, line: 2, col: 1 });
+        let mut sources = Sources(src, NodeMap::new());
+        sources.visit_items(&ast.nodes).unwrap();
+        let mut texts = sources.1.into_values().collect::<Vec<_>>();
+        texts.sort_unstable();
+        let expected = ["(1 + 22) * f(y.z)", "-a", "1", "1 + 22", "22", "a", "f", "f(y.z)", "x = (1 + 22) * f(y.z)", "y", "y.z"];
+        assert_eq!(texts, expected);
+    }
+
+    #[test]
+    fn negative_pattern_span() {
+        let src = "match x { - 5 => 1, -2.5 => 2 }";
+        let ast = Parser::new(src).ast().unwrap();
+        let ItemKind::Expr(expr) = &ast.nodes[0].kind else { panic!() };
+        let ExprKind::Match(_, arms) = &expr.kind else { panic!() };
+        let spans = arms.iter().map(|arm| match &arm.pat {
+            Pattern::Lit(lit) => &src[lit.span.start..lit.span.end],
+            _ => panic!(),
+        });
+        assert!(spans.eq(["- 5", "-2.5"]));
+    }
+}
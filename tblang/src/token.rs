@@ -1,17 +1,47 @@
+use std::fmt;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TKind {
     TkErr,
     TkEof,
     TkSemi,
     TkNLine,
+    TkDoc,
+    TkSpace,
+    TkComment,
     TkLparen,
     TkRparen,
+    TkLbrace,
+    TkRbrace,
+    TkComma,
+    TkDot,
+    TkEllipsis,
+    TkDotDot,
+    TkDotDotEq,
+    TkColon,
+    TkQuestion,
+    TkQuestionDot,
+    TkQuestionQuestion,
+    TkThinArrow,
     TkPlus,
     TkMinus,
     TkStar,
     TkSlash,
     TkPercent,
     TkCaret,
+    TkAmp,
+    TkBar,
+    TkPipe,
+    TkTilde,
+    TkLtLt,
+    TkGtGt,
+    TkGtGtGt,
+    TkPlusEq,
+    TkMinusEq,
+    TkStarEq,
+    TkSlashEq,
+    TkPercentEq,
+    TkCaretEq,
     TkLt,
     TkGt,
     TkLtEq,
@@ -19,13 +49,35 @@ pub enum TKind {
     TkEqEq,
     TkNotEq,
     TkEq,
+    TkArrow,
     TkTrue,
     TkFalse,
     TkInt,
     TkBin,
+    TkOct,
     TkHex,
     TkReal,
+    TkStr,
     TkIdent,
+    TkFn,
+    TkDef,
+    TkImpl,
+    TkTrait,
+    TkFor,
+    TkEnum,
+    TkMatch,
+    TkIf,
+    TkAlias,
+    TkConst,
+    TkMod,
+    TkUse,
+    TkPub,
+    TkLet,
+    TkIn,
+    TkYield,
+    TkTry,
+    TkCatch,
+    TkFinally,
 }
 
 #[derive(Clone)]
@@ -33,19 +85,35 @@ pub struct Token<'a> {
     pub kind: TKind,
     pub line: usize,
     pub col: usize,
-    span: &'a [u8],
+    /// The byte offset of the token in the source.
+    pub offset: usize,
+    /// Why the lexer rejected an error token, unless it is just an unrecognized character.
+    pub error: Option<String>,
+    span: &'a str,
 }
 
 impl<'a> Token<'a> {
     pub fn eof() -> Self {
-        Self::new(TKind::TkEof, &[])
+        Self::new(TKind::TkEof, "")
     }
 
-    pub fn new(kind: TKind, span: &'a [u8]) -> Self {
-        Self { kind, span, line: 1, col: 1 }
+    pub fn new(kind: TKind, span: &'a str) -> Self {
+        Self { kind, span, line: 1, col: 1, offset: 0, error: None }
     }
 
     pub fn lexeme(&self) -> &'a str {
-        std::str::from_utf8(self.span).unwrap()
+        self.span
+    }
+}
+
+/// Prints the position, kind and lexeme of a token, e.g. `3:5 TkIdent "x"`, followed by the
+/// reason for an error token.
+impl fmt::Debug for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{} {:?} {:?}", self.line, self.col, self.kind, self.span)?;
+        if let Some(error) = &self.error {
+            write!(f, " ({})", error)?;
+        }
+        Ok(())
     }
 }
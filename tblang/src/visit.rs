@@ -0,0 +1,438 @@
+use crate::ast::AliasDef;
+use crate::ast::Arg;
+use crate::ast::AssignOp;
+use crate::ast::ConstDef;
+use crate::ast::EnumDef;
+use crate::ast::Expr;
+use crate::ast::ExprKind;
+use crate::ast::FnDef;
+use crate::ast::ImplDef;
+use crate::ast::Item;
+use crate::ast::ItemKind;
+use crate::ast::LetBind;
+use crate::ast::MatchArm;
+use crate::ast::ModDef;
+use crate::ast::Pattern;
+use crate::ast::RelOp;
+use crate::ast::TraitDef;
+use crate::ast::TryCatch;
+use crate::ast::TyDef;
+use crate::ast::UseDef;
+
+/// A pass over a syntax tree that reads it. There is a method for every kind of item and
+/// expression, and by default each one visits the nodes below it, so a pass only overrides the
+/// nodes it cares about. An override that still wants to reach the nodes below calls the
+/// matching `walk_` function or visits them itself. The first error stops the pass.
+pub trait Visitor {
+    fn visit_items(&mut self, items: &[Item]) -> Result<(), String> {
+        for item in items {
+            self.visit_item(item)?;
+        }
+        Ok(())
+    }
+
+    fn visit_item(&mut self, item: &Item) -> Result<(), String> {
+        walk_item(self, item)
+    }
+
+    fn visit_mod(&mut self, def: &ModDef) -> Result<(), String> {
+        self.visit_items(&def.body.nodes)
+    }
+
+    fn visit_use(&mut self, _def: &UseDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_def(&mut self, _def: &TyDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_enum(&mut self, _def: &EnumDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_impl(&mut self, def: &ImplDef) -> Result<(), String> {
+        for method in &def.methods {
+            self.visit_fn(method)?;
+        }
+        Ok(())
+    }
+
+    fn visit_alias(&mut self, _def: &AliasDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_trait(&mut self, def: &TraitDef) -> Result<(), String> {
+        for method in &def.methods {
+            self.visit_fn(method)?;
+        }
+        Ok(())
+    }
+
+    fn visit_fn(&mut self, def: &FnDef) -> Result<(), String> {
+        walk_fn(self, def)
+    }
+
+    fn visit_const(&mut self, def: &ConstDef) -> Result<(), String> {
+        self.visit_expr(&def.init)
+    }
+
+    fn visit_let(&mut self, bind: &LetBind) -> Result<(), String> {
+        self.visit_pattern(&bind.pat)?;
+        self.visit_expr(&bind.init)
+    }
+
+    fn visit_pattern(&mut self, pat: &Pattern) -> Result<(), String> {
+        walk_pattern(self, pat)
+    }
+
+    fn visit_arg(&mut self, arg: &Arg) -> Result<(), String> {
+        self.visit_expr(arg.value())
+    }
+
+    fn visit_arm(&mut self, arm: &MatchArm) -> Result<(), String> {
+        self.visit_pattern(&arm.pat)?;
+        if let Some(guard) = &arm.guard {
+            self.visit_expr(guard)?;
+        }
+        self.visit_expr(&arm.body)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), String> {
+        walk_expr(self, expr)
+    }
+
+    fn visit_int(&mut self, _lit: i32) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_real(&mut self, _lit: f64) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, _lit: bool) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_str(&mut self, _lit: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_ident(&mut self, _name: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_tuple(&mut self, elems: &[Expr]) -> Result<(), String> {
+        for elem in elems {
+            self.visit_expr(elem)?;
+        }
+        Ok(())
+    }
+
+    fn visit_block(&mut self, items: &[Item]) -> Result<(), String> {
+        self.visit_items(items)
+    }
+
+    fn visit_field(&mut self, object: &Expr, _name: &str) -> Result<(), String> {
+        self.visit_expr(object)
+    }
+
+    fn visit_index(&mut self, tuple: &Expr, _idx: usize) -> Result<(), String> {
+        self.visit_expr(tuple)
+    }
+
+    fn visit_safe_field(&mut self, object: &Expr, _name: &str) -> Result<(), String> {
+        self.visit_expr(object)
+    }
+
+    fn visit_safe_index(&mut self, tuple: &Expr, _idx: usize) -> Result<(), String> {
+        self.visit_expr(tuple)
+    }
+
+    fn visit_call(&mut self, callee: &Expr, args: &[Arg]) -> Result<(), String> {
+        self.visit_expr(callee)?;
+        for arg in args {
+            self.visit_arg(arg)?;
+        }
+        Ok(())
+    }
+
+    fn visit_propagate(&mut self, inner: &Expr) -> Result<(), String> {
+        self.visit_expr(inner)
+    }
+
+    fn visit_power(&mut self, base: &Expr, exp: &Expr) -> Result<(), String> {
+        self.visit_expr(base)?;
+        self.visit_expr(exp)
+    }
+
+    fn visit_negate(&mut self, inner: &Expr) -> Result<(), String> {
+        self.visit_expr(inner)
+    }
+
+    fn visit_bit_not(&mut self, inner: &Expr) -> Result<(), String> {
+        self.visit_expr(inner)
+    }
+
+    fn visit_mul(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_div(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_rem(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_add(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_sub(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_shl(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_shr(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_ushr(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_bit_and(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_bit_xor(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_bit_or(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_coalesce(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_relation(&mut self, lhs: &Expr, ops: &[(RelOp, Expr)]) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        for (_, rhs) in ops {
+            self.visit_expr(rhs)?;
+        }
+        Ok(())
+    }
+
+    fn visit_range(&mut self, start: &Expr, end: &Expr, _inclusive: bool) -> Result<(), String> {
+        self.visit_expr(start)?;
+        self.visit_expr(end)
+    }
+
+    fn visit_assign(&mut self, pat: &Pattern, rhs: &Expr) -> Result<(), String> {
+        self.visit_pattern(pat)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_set_field(&mut self, object: &Expr, _name: &str, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(object)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_set_index(&mut self, object: &Expr, _idx: usize, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(object)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_op_assign(&mut self, _op: &AssignOp, target: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(target)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_match(&mut self, subject: &Expr, arms: &[MatchArm]) -> Result<(), String> {
+        self.visit_expr(subject)?;
+        for arm in arms {
+            self.visit_arm(arm)?;
+        }
+        Ok(())
+    }
+
+    fn visit_yield(&mut self, value: &Expr) -> Result<(), String> {
+        self.visit_expr(value)
+    }
+
+    fn visit_try(&mut self, def: &TryCatch) -> Result<(), String> {
+        walk_try(self, def)
+    }
+
+    fn visit_for(&mut self, pat: &Pattern, iterable: &Expr, body: &[Item]) -> Result<(), String> {
+        self.visit_pattern(pat)?;
+        self.visit_expr(iterable)?;
+        self.visit_items(body)
+    }
+}
+
+/// Hands an item to the visitor method for its kind.
+pub fn walk_item<V: Visitor + ?Sized>(visitor: &mut V, item: &Item) -> Result<(), String> {
+    match &item.kind {
+        ItemKind::Mod(def) => visitor.visit_mod(def),
+        ItemKind::Use(def) => visitor.visit_use(def),
+        ItemKind::Def(def) => visitor.visit_def(def),
+        ItemKind::Enum(def) => visitor.visit_enum(def),
+        ItemKind::Impl(def) => visitor.visit_impl(def),
+        ItemKind::Alias(def) => visitor.visit_alias(def),
+        ItemKind::Trait(def) => visitor.visit_trait(def),
+        ItemKind::Fn(def) => visitor.visit_fn(def),
+        ItemKind::Const(def) => visitor.visit_const(def),
+        ItemKind::Let(bind) => visitor.visit_let(bind),
+        ItemKind::Expr(expr) => visitor.visit_expr(expr),
+    }
+}
+
+/// Visits the parameter defaults and the body of a function.
+pub fn walk_fn<V: Visitor + ?Sized>(visitor: &mut V, def: &FnDef) -> Result<(), String> {
+    for param in &def.params {
+        if let Some(default) = &param.default {
+            visitor.visit_expr(default)?;
+        }
+    }
+    match &def.body {
+        Some(body) => visitor.visit_items(body),
+        None => Ok(()),
+    }
+}
+
+/// Visits the literals in a pattern.
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pat: &Pattern) -> Result<(), String> {
+    match pat {
+        Pattern::Wildcard | Pattern::Ident(_) => Ok(()),
+        Pattern::Lit(lit) => visitor.visit_expr(lit),
+        Pattern::Variant(_, pats) | Pattern::Tuple(pats) => {
+            for pat in pats {
+                visitor.visit_pattern(pat)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Visits the body of a `try` expression, then its catch and finally blocks.
+pub fn walk_try<V: Visitor + ?Sized>(visitor: &mut V, def: &TryCatch) -> Result<(), String> {
+    visitor.visit_items(&def.body)?;
+    if let Some((pat, body)) = &def.catch {
+        visitor.visit_pattern(pat)?;
+        visitor.visit_items(body)?;
+    }
+    if let Some(body) = &def.finally {
+        visitor.visit_items(body)?;
+    }
+    Ok(())
+}
+
+/// Hands an expression to the visitor method for its kind.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) -> Result<(), String> {
+    match &expr.kind {
+        ExprKind::Int(lit) => visitor.visit_int(*lit),
+        ExprKind::Real(lit) => visitor.visit_real(*lit),
+        ExprKind::Bool(lit) => visitor.visit_bool(*lit),
+        ExprKind::Str(lit) => visitor.visit_str(lit),
+        ExprKind::Ident(name) => visitor.visit_ident(name),
+        ExprKind::Tuple(elems) => visitor.visit_tuple(elems),
+        ExprKind::Block(items) => visitor.visit_block(items),
+        ExprKind::Field(object, name) => visitor.visit_field(object, name),
+        ExprKind::Index(tuple, idx) => visitor.visit_index(tuple, *idx),
+        ExprKind::SafeField(object, name) => visitor.visit_safe_field(object, name),
+        ExprKind::SafeIndex(tuple, idx) => visitor.visit_safe_index(tuple, *idx),
+        ExprKind::Call(callee, args) => visitor.visit_call(callee, args),
+        ExprKind::Propagate(inner) => visitor.visit_propagate(inner),
+        ExprKind::Power(base, exp) => visitor.visit_power(base, exp),
+        ExprKind::Negate(inner) => visitor.visit_negate(inner),
+        ExprKind::BitNot(inner) => visitor.visit_bit_not(inner),
+        ExprKind::Mul(lhs, rhs) => visitor.visit_mul(lhs, rhs),
+        ExprKind::Div(lhs, rhs) => visitor.visit_div(lhs, rhs),
+        ExprKind::Rem(lhs, rhs) => visitor.visit_rem(lhs, rhs),
+        ExprKind::Add(lhs, rhs) => visitor.visit_add(lhs, rhs),
+        ExprKind::Sub(lhs, rhs) => visitor.visit_sub(lhs, rhs),
+        ExprKind::Shl(lhs, rhs) => visitor.visit_shl(lhs, rhs),
+        ExprKind::Shr(lhs, rhs) => visitor.visit_shr(lhs, rhs),
+        ExprKind::UShr(lhs, rhs) => visitor.visit_ushr(lhs, rhs),
+        ExprKind::BitAnd(lhs, rhs) => visitor.visit_bit_and(lhs, rhs),
+        ExprKind::BitXor(lhs, rhs) => visitor.visit_bit_xor(lhs, rhs),
+        ExprKind::BitOr(lhs, rhs) => visitor.visit_bit_or(lhs, rhs),
+        ExprKind::Coalesce(lhs, rhs) => visitor.visit_coalesce(lhs, rhs),
+        ExprKind::Relation(lhs, ops) => visitor.visit_relation(lhs, ops),
+        ExprKind::Range(start, end, inclusive) => visitor.visit_range(start, end, *inclusive),
+        ExprKind::Assign(pat, rhs) => visitor.visit_assign(pat, rhs),
+        ExprKind::SetField(object, name, rhs) => visitor.visit_set_field(object, name, rhs),
+        ExprKind::SetIndex(object, idx, rhs) => visitor.visit_set_index(object, *idx, rhs),
+        ExprKind::OpAssign(op, target, rhs) => visitor.visit_op_assign(op, target, rhs),
+        ExprKind::Match(subject, arms) => visitor.visit_match(subject, arms),
+        ExprKind::Yield(value) => visitor.visit_yield(value),
+        ExprKind::Try(def) => visitor.visit_try(def),
+        ExprKind::For(pat, iterable, body) => visitor.visit_for(pat, iterable, body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Visitor;
+    use crate::ast::Expr;
+    use crate::ast::ExprKind;
+    use crate::ast::FnDef;
+    use crate::ast::NodeId;
+    use crate::ast::Span;
+    use crate::parse::Parser;
+
+    /// Collects the names used in expressions, but not those in nested functions.
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_ident(&mut self, name: &str) -> Result<(), String> {
+            self.0.push(name.to_owned());
+            Ok(())
+        }
+
+        fn visit_fn(&mut self, _def: &FnDef) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn walks_into_nested_nodes() {
+        let ast = Parser::new("a = b + c.d\nmatch e { 1 => f(g: h), _ => { i } }\nfn j() { k }").ast().unwrap();
+        let mut names = Names(Vec::new());
+        names.visit_items(&ast.nodes).unwrap();
+        assert_eq!(names.0, ["b", "c", "e", "f", "h", "i"]);
+    }
+
+    #[test]
+    fn stops_at_first_error() {
+        struct NoInts;
+        impl Visitor for NoInts {
+            fn visit_int(&mut self, lit: i32) -> Result<(), String> {
+                Err(format!("found {}", lit))
+            }
+        }
+        let ast = Parser::new("x = (1, 2)").ast().unwrap();
+        assert_eq!(NoInts.visit_items(&ast.nodes), Err("found 1".to_owned()));
+        assert!(NoInts.visit_expr(&Expr { id: NodeId(0), span: Span::default(), kind: ExprKind::Real(1.0) }).is_ok());
+    }
+}
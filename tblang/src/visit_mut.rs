@@ -0,0 +1,427 @@
+use crate::ast::AliasDef;
+use crate::ast::Arg;
+use crate::ast::AssignOp;
+use crate::ast::ConstDef;
+use crate::ast::EnumDef;
+use crate::ast::Expr;
+use crate::ast::ExprKind;
+use crate::ast::FnDef;
+use crate::ast::ImplDef;
+use crate::ast::Item;
+use crate::ast::ItemKind;
+use crate::ast::LetBind;
+use crate::ast::MatchArm;
+use crate::ast::ModDef;
+use crate::ast::Pattern;
+use crate::ast::RelOp;
+use crate::ast::TraitDef;
+use crate::ast::TryCatch;
+use crate::ast::TyDef;
+use crate::ast::UseDef;
+
+/// A pass over a syntax tree that changes it in place. It has the same methods as `Visitor`,
+/// each of which by default visits the nodes below it.
+pub trait VisitorMut {
+    fn visit_items(&mut self, items: &mut Vec<Item>) -> Result<(), String> {
+        for item in items {
+            self.visit_item(item)?;
+        }
+        Ok(())
+    }
+
+    fn visit_item(&mut self, item: &mut Item) -> Result<(), String> {
+        walk_item(self, item)
+    }
+
+    fn visit_mod(&mut self, def: &mut ModDef) -> Result<(), String> {
+        self.visit_items(&mut def.body.nodes)
+    }
+
+    fn visit_use(&mut self, _def: &mut UseDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_def(&mut self, _def: &mut TyDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_enum(&mut self, _def: &mut EnumDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_impl(&mut self, def: &mut ImplDef) -> Result<(), String> {
+        for method in &mut def.methods {
+            self.visit_fn(method)?;
+        }
+        Ok(())
+    }
+
+    fn visit_alias(&mut self, _def: &mut AliasDef) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_trait(&mut self, def: &mut TraitDef) -> Result<(), String> {
+        for method in &mut def.methods {
+            self.visit_fn(method)?;
+        }
+        Ok(())
+    }
+
+    fn visit_fn(&mut self, def: &mut FnDef) -> Result<(), String> {
+        walk_fn(self, def)
+    }
+
+    fn visit_const(&mut self, def: &mut ConstDef) -> Result<(), String> {
+        self.visit_expr(&mut def.init)
+    }
+
+    fn visit_let(&mut self, bind: &mut LetBind) -> Result<(), String> {
+        self.visit_pattern(&mut bind.pat)?;
+        self.visit_expr(&mut bind.init)
+    }
+
+    fn visit_pattern(&mut self, pat: &mut Pattern) -> Result<(), String> {
+        walk_pattern(self, pat)
+    }
+
+    fn visit_arg(&mut self, arg: &mut Arg) -> Result<(), String> {
+        match arg {
+            Arg::Pos(value) | Arg::Named(_, value) | Arg::Spread(value) => self.visit_expr(value),
+        }
+    }
+
+    fn visit_arm(&mut self, arm: &mut MatchArm) -> Result<(), String> {
+        self.visit_pattern(&mut arm.pat)?;
+        if let Some(guard) = &mut arm.guard {
+            self.visit_expr(guard)?;
+        }
+        self.visit_expr(&mut arm.body)
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) -> Result<(), String> {
+        walk_expr(self, expr)
+    }
+
+    fn visit_int(&mut self, _lit: &mut i32) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_real(&mut self, _lit: &mut f64) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, _lit: &mut bool) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_str(&mut self, _lit: &mut String) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_ident(&mut self, _name: &mut String) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn visit_tuple(&mut self, elems: &mut Vec<Expr>) -> Result<(), String> {
+        for elem in elems {
+            self.visit_expr(elem)?;
+        }
+        Ok(())
+    }
+
+    fn visit_block(&mut self, items: &mut Vec<Item>) -> Result<(), String> {
+        self.visit_items(items)
+    }
+
+    fn visit_field(&mut self, object: &mut Expr, _name: &mut String) -> Result<(), String> {
+        self.visit_expr(object)
+    }
+
+    fn visit_index(&mut self, tuple: &mut Expr, _idx: &mut usize) -> Result<(), String> {
+        self.visit_expr(tuple)
+    }
+
+    fn visit_safe_field(&mut self, object: &mut Expr, _name: &mut String) -> Result<(), String> {
+        self.visit_expr(object)
+    }
+
+    fn visit_safe_index(&mut self, tuple: &mut Expr, _idx: &mut usize) -> Result<(), String> {
+        self.visit_expr(tuple)
+    }
+
+    fn visit_call(&mut self, callee: &mut Expr, args: &mut Vec<Arg>) -> Result<(), String> {
+        self.visit_expr(callee)?;
+        for arg in args {
+            self.visit_arg(arg)?;
+        }
+        Ok(())
+    }
+
+    fn visit_propagate(&mut self, inner: &mut Expr) -> Result<(), String> {
+        self.visit_expr(inner)
+    }
+
+    fn visit_power(&mut self, base: &mut Expr, exp: &mut Expr) -> Result<(), String> {
+        self.visit_expr(base)?;
+        self.visit_expr(exp)
+    }
+
+    fn visit_negate(&mut self, inner: &mut Expr) -> Result<(), String> {
+        self.visit_expr(inner)
+    }
+
+    fn visit_bit_not(&mut self, inner: &mut Expr) -> Result<(), String> {
+        self.visit_expr(inner)
+    }
+
+    fn visit_mul(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_div(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_rem(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_add(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_sub(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_shl(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_shr(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_ushr(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_bit_and(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_bit_xor(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_bit_or(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_coalesce(&mut self, lhs: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_relation(&mut self, lhs: &mut Expr, ops: &mut Vec<(RelOp, Expr)>) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        for (_, rhs) in ops {
+            self.visit_expr(rhs)?;
+        }
+        Ok(())
+    }
+
+    fn visit_range(&mut self, start: &mut Expr, end: &mut Expr, _inclusive: &mut bool) -> Result<(), String> {
+        self.visit_expr(start)?;
+        self.visit_expr(end)
+    }
+
+    fn visit_assign(&mut self, pat: &mut Pattern, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_pattern(pat)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_set_field(&mut self, object: &mut Expr, _name: &mut String, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(object)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_set_index(&mut self, object: &mut Expr, _idx: &mut usize, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(object)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_op_assign(&mut self, _op: &mut AssignOp, target: &mut Expr, rhs: &mut Expr) -> Result<(), String> {
+        self.visit_expr(target)?;
+        self.visit_expr(rhs)
+    }
+
+    fn visit_match(&mut self, subject: &mut Expr, arms: &mut Vec<MatchArm>) -> Result<(), String> {
+        self.visit_expr(subject)?;
+        for arm in arms {
+            self.visit_arm(arm)?;
+        }
+        Ok(())
+    }
+
+    fn visit_yield(&mut self, value: &mut Expr) -> Result<(), String> {
+        self.visit_expr(value)
+    }
+
+    fn visit_try(&mut self, def: &mut TryCatch) -> Result<(), String> {
+        walk_try(self, def)
+    }
+
+    fn visit_for(&mut self, pat: &mut Pattern, iterable: &mut Expr, body: &mut Vec<Item>) -> Result<(), String> {
+        self.visit_pattern(pat)?;
+        self.visit_expr(iterable)?;
+        self.visit_items(body)
+    }
+}
+
+/// Hands an item to the visitor method for its kind.
+pub fn walk_item<V: VisitorMut + ?Sized>(visitor: &mut V, item: &mut Item) -> Result<(), String> {
+    match &mut item.kind {
+        ItemKind::Mod(def) => visitor.visit_mod(def),
+        ItemKind::Use(def) => visitor.visit_use(def),
+        ItemKind::Def(def) => visitor.visit_def(def),
+        ItemKind::Enum(def) => visitor.visit_enum(def),
+        ItemKind::Impl(def) => visitor.visit_impl(def),
+        ItemKind::Alias(def) => visitor.visit_alias(def),
+        ItemKind::Trait(def) => visitor.visit_trait(def),
+        ItemKind::Fn(def) => visitor.visit_fn(def),
+        ItemKind::Const(def) => visitor.visit_const(def),
+        ItemKind::Let(bind) => visitor.visit_let(bind),
+        ItemKind::Expr(expr) => visitor.visit_expr(expr),
+    }
+}
+
+/// Visits the parameter defaults and the body of a function.
+pub fn walk_fn<V: VisitorMut + ?Sized>(visitor: &mut V, def: &mut FnDef) -> Result<(), String> {
+    for param in &mut def.params {
+        if let Some(default) = &mut param.default {
+            visitor.visit_expr(default)?;
+        }
+    }
+    match &mut def.body {
+        Some(body) => visitor.visit_items(body),
+        None => Ok(()),
+    }
+}
+
+/// Visits the literals in a pattern.
+pub fn walk_pattern<V: VisitorMut + ?Sized>(visitor: &mut V, pat: &mut Pattern) -> Result<(), String> {
+    match pat {
+        Pattern::Wildcard | Pattern::Ident(_) => Ok(()),
+        Pattern::Lit(lit) => visitor.visit_expr(lit),
+        Pattern::Variant(_, pats) | Pattern::Tuple(pats) => {
+            for pat in pats {
+                visitor.visit_pattern(pat)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Visits the body of a `try` expression, then its catch and finally blocks.
+pub fn walk_try<V: VisitorMut + ?Sized>(visitor: &mut V, def: &mut TryCatch) -> Result<(), String> {
+    visitor.visit_items(&mut def.body)?;
+    if let Some((pat, body)) = &mut def.catch {
+        visitor.visit_pattern(pat)?;
+        visitor.visit_items(body)?;
+    }
+    if let Some(body) = &mut def.finally {
+        visitor.visit_items(body)?;
+    }
+    Ok(())
+}
+
+/// Hands an expression to the visitor method for its kind.
+pub fn walk_expr<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) -> Result<(), String> {
+    match &mut expr.kind {
+        ExprKind::Int(lit) => visitor.visit_int(lit),
+        ExprKind::Real(lit) => visitor.visit_real(lit),
+        ExprKind::Bool(lit) => visitor.visit_bool(lit),
+        ExprKind::Str(lit) => visitor.visit_str(lit),
+        ExprKind::Ident(name) => visitor.visit_ident(name),
+        ExprKind::Tuple(elems) => visitor.visit_tuple(elems),
+        ExprKind::Block(items) => visitor.visit_block(items),
+        ExprKind::Field(object, name) => visitor.visit_field(object, name),
+        ExprKind::Index(tuple, idx) => visitor.visit_index(tuple, idx),
+        ExprKind::SafeField(object, name) => visitor.visit_safe_field(object, name),
+        ExprKind::SafeIndex(tuple, idx) => visitor.visit_safe_index(tuple, idx),
+        ExprKind::Call(callee, args) => visitor.visit_call(callee, args),
+        ExprKind::Propagate(inner) => visitor.visit_propagate(inner),
+        ExprKind::Power(base, exp) => visitor.visit_power(base, exp),
+        ExprKind::Negate(inner) => visitor.visit_negate(inner),
+        ExprKind::BitNot(inner) => visitor.visit_bit_not(inner),
+        ExprKind::Mul(lhs, rhs) => visitor.visit_mul(lhs, rhs),
+        ExprKind::Div(lhs, rhs) => visitor.visit_div(lhs, rhs),
+        ExprKind::Rem(lhs, rhs) => visitor.visit_rem(lhs, rhs),
+        ExprKind::Add(lhs, rhs) => visitor.visit_add(lhs, rhs),
+        ExprKind::Sub(lhs, rhs) => visitor.visit_sub(lhs, rhs),
+        ExprKind::Shl(lhs, rhs) => visitor.visit_shl(lhs, rhs),
+        ExprKind::Shr(lhs, rhs) => visitor.visit_shr(lhs, rhs),
+        ExprKind::UShr(lhs, rhs) => visitor.visit_ushr(lhs, rhs),
+        ExprKind::BitAnd(lhs, rhs) => visitor.visit_bit_and(lhs, rhs),
+        ExprKind::BitXor(lhs, rhs) => visitor.visit_bit_xor(lhs, rhs),
+        ExprKind::BitOr(lhs, rhs) => visitor.visit_bit_or(lhs, rhs),
+        ExprKind::Coalesce(lhs, rhs) => visitor.visit_coalesce(lhs, rhs),
+        ExprKind::Relation(lhs, ops) => visitor.visit_relation(lhs, ops),
+        ExprKind::Range(start, end, inclusive) => visitor.visit_range(start, end, inclusive),
+        ExprKind::Assign(pat, rhs) => visitor.visit_assign(pat, rhs),
+        ExprKind::SetField(object, name, rhs) => visitor.visit_set_field(object, name, rhs),
+        ExprKind::SetIndex(object, idx, rhs) => visitor.visit_set_index(object, idx, rhs),
+        ExprKind::OpAssign(op, target, rhs) => visitor.visit_op_assign(op, target, rhs),
+        ExprKind::Match(subject, arms) => visitor.visit_match(subject, arms),
+        ExprKind::Yield(value) => visitor.visit_yield(value),
+        ExprKind::Try(def) => visitor.visit_try(def),
+        ExprKind::For(pat, iterable, body) => visitor.visit_for(pat, iterable, body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VisitorMut;
+    use crate::ast::ExprKind;
+    use crate::ast::ItemKind;
+    use crate::parse::Parser;
+
+    /// Renames every use of a name, including those in nested blocks and patterns.
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_ident(&mut self, name: &mut String) -> Result<(), String> {
+            if name == "x" {
+                *name = "y".to_owned();
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn changes_nested_nodes() {
+        let mut ast = Parser::new("fn f() { match x { 1 => { x + 1 }, _ => x?.z } }").ast().unwrap();
+        Rename.visit_items(&mut ast.nodes).unwrap();
+        let ItemKind::Fn(def) = &ast.nodes[0].kind else { panic!() };
+        let Some([item]) = def.body.as_deref() else { panic!() };
+        let ItemKind::Expr(expr) = &item.kind else { panic!() };
+        let ExprKind::Match(subject, arms) = &expr.kind else { panic!() };
+        assert!(matches!(&subject.kind, ExprKind::Ident(name) if name == "y"));
+        assert!(matches!(&arms[1].body.kind, ExprKind::SafeField(object, _) if matches!(&object.kind, ExprKind::Ident(name) if name == "y")));
+        let ExprKind::Block(items) = &arms[0].body.kind else { panic!() };
+        let ItemKind::Expr(expr) = &items[0].kind else { panic!() };
+        assert!(matches!(&expr.kind, ExprKind::Add(lhs, _) if matches!(&lhs.kind, ExprKind::Ident(name) if name == "y")));
+    }
+}
@@ -1,9 +1,12 @@
+use std::fmt::Write;
 use std::mem;
+use std::rc::Rc;
 
+use crate::value::Function;
 use crate::value::Value;
 
 #[repr(u8)]
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum OpCode {
     OpNop,
     OpUnit,
@@ -17,6 +20,13 @@ pub enum OpCode {
     OpRem,
     OpPow,
     OpNeg,
+    OpBitAnd,
+    OpBitOr,
+    OpBitXor,
+    OpBitNot,
+    OpShl,
+    OpShr,
+    OpUShr,
     OpLt,
     OpGt,
     OpLtEq,
@@ -26,9 +36,38 @@ pub enum OpCode {
     OpLoop,
     OpJump,
     OpBranch,
+    OpJumpIfUnit,
+    OpJumpNotUnit,
     OpGet,
     OpSet,
+    OpGetLocal,
+    OpSetLocal,
+    OpField,
+    OpSetField,
     OpCall,
+    OpCallNamed,
+    OpCallSpread,
+    OpInvoke,
+    OpInvokeNamed,
+    OpInvokeSpread,
+    OpRet,
+    OpMethod,
+    OpImpl,
+    OpElem,
+    OpSetElem,
+    OpIsVariant,
+    OpTuple,
+    OpUnpack,
+    OpIsTuple,
+    OpRange,
+    OpIter,
+    OpNext,
+    OpYield,
+    OpTry,
+    OpEndTry,
+    OpRaise,
+    OpPropagate,
+    OpDup,
     OpPop,
 }
 
@@ -39,9 +78,66 @@ impl TryFrom<u8> for OpCode {
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         if value > OPCODE_MAX {
-            return Err(format!("byte value is outside opcode range"));
+            Err("byte value is outside opcode range".to_owned())
         } else {
-            return Ok(unsafe { mem::transmute(value) });
+            Ok(unsafe { mem::transmute::<u8, OpCode>(value) })
+        }
+    }
+}
+
+/// The kind of an operand that follows an opcode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operand {
+    /// A byte holding a count, a flag or a local slot.
+    Byte,
+    /// A byte holding an index into the values of the chunk.
+    Const,
+    /// Two bytes holding the distance to jump forward.
+    Jump,
+    /// Two bytes holding the distance to jump back.
+    Loop,
+}
+
+impl Operand {
+    pub fn size(self) -> usize {
+        match self {
+            Operand::Byte | Operand::Const => 1,
+            Operand::Jump | Operand::Loop => 2,
+        }
+    }
+}
+
+impl OpCode {
+    /// The operands that follow the opcode, in order.
+    pub fn operands(self) -> &'static [Operand] {
+        match self {
+            OpCode::OpConst
+            | OpCode::OpGet
+            | OpCode::OpSet
+            | OpCode::OpField
+            | OpCode::OpSetField
+            | OpCode::OpMethod
+            | OpCode::OpIsVariant => &[Operand::Const],
+            OpCode::OpGetLocal
+            | OpCode::OpSetLocal
+            | OpCode::OpCall
+            | OpCode::OpElem
+            | OpCode::OpSetElem
+            | OpCode::OpTuple
+            | OpCode::OpUnpack
+            | OpCode::OpIsTuple
+            | OpCode::OpRange => &[Operand::Byte],
+            OpCode::OpCallNamed | OpCode::OpCallSpread => &[Operand::Byte, Operand::Const],
+            OpCode::OpInvoke => &[Operand::Const, Operand::Byte],
+            OpCode::OpInvokeNamed | OpCode::OpInvokeSpread => &[Operand::Const, Operand::Byte, Operand::Const],
+            OpCode::OpJump
+            | OpCode::OpBranch
+            | OpCode::OpJumpIfUnit
+            | OpCode::OpJumpNotUnit
+            | OpCode::OpNext
+            | OpCode::OpTry => &[Operand::Jump],
+            OpCode::OpLoop => &[Operand::Loop],
+            _ => &[],
         }
     }
 }
@@ -50,6 +146,14 @@ impl TryFrom<u8> for OpCode {
 pub struct Chunk {
     code: Vec<u8>,
     vals: Vec<Value>,
+    /// The source line of the code from each offset on, in order of offset.
+    lines: Vec<(usize, usize)>,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chunk {
@@ -57,6 +161,7 @@ impl Chunk {
         Self {
             code: Vec::new(),
             vals: Vec::new(),
+            lines: Vec::new(),
         }
     }
 
@@ -64,6 +169,10 @@ impl Chunk {
         self.code.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
     pub fn code(&self, idx: usize) -> u8 {
         self.code[idx]
     }
@@ -80,6 +189,145 @@ impl Chunk {
         self.code.push(byte);
     }
 
+    /// Writes a one-byte operand, or fails when `value` does not fit in one. `what` names the
+    /// things the operand counts or indexes, for the error.
+    pub fn write_operand(&mut self, value: usize, what: &str) -> Result<(), String> {
+        match u8::try_from(value) {
+            Ok(byte) => {
+                self.write_byte(byte);
+                Ok(())
+            }
+            Err(_) => Err(format!("too many {}, the limit is 256", what)),
+        }
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.code.extend(value.to_be_bytes());
+    }
+
+    pub fn patch_u16(&mut self, idx: usize, value: u16) {
+        self.code[idx..idx + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Marks the code written from here on as coming from `line` of the source.
+    pub fn set_line(&mut self, line: usize) {
+        if self.lines.last().is_some_and(|&(_, last)| last == line) {
+            return;
+        }
+        if self.lines.last().is_some_and(|&(offset, _)| offset == self.code.len()) {
+            self.lines.pop();
+        }
+        self.lines.push((self.code.len(), line));
+    }
+
+    /// The source line of the code at `offset`, if it is known.
+    pub fn line(&self, offset: usize) -> Option<usize> {
+        let idx = self.lines.partition_point(|&(start, _)| start <= offset);
+        self.lines[..idx].last().map(|&(_, line)| line)
+    }
+
+    /// Lists the instructions of the chunk one per line, then those of the functions among its
+    /// values. Each instruction shows its offset, its source line or `|` when that is the same as
+    /// the instruction before, and its operands. Values are resolved in a comment after the
+    /// instruction and jumps show the offset they land on, e.g. `0004    2 OpGet 1  ; a` and
+    /// `0009    | OpJump 5 -> 0017`.
+    /// Lists the code of the chunk, followed by that of the functions among its constants. These
+    /// include the default methods of traits and the methods of types, as `fn Type.method`.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = String::new();
+        self.write_disassembly(&mut out, name, &mut Vec::new());
+        out
+    }
+
+    /// Writes the chunk and then the functions among its constants, skipping those in `seen`.
+    fn write_disassembly(&self, out: &mut String, name: &str, seen: &mut Vec<*const Function>) {
+        writeln!(out, "== {} ==", name).unwrap();
+        let mut ip = 0;
+        let mut last_line = None;
+        while ip < self.code.len() {
+            let line = self.line(ip);
+            let line = match line {
+                Some(_) if line == last_line => "|".to_owned(),
+                Some(line) => line.to_string(),
+                None => String::new(),
+            };
+            last_line = self.line(ip);
+            write!(out, "{:04} {:>4} ", ip, line).unwrap();
+            ip = self.write_instruction(out, ip);
+            out.push('\n');
+        }
+        let mut funcs = Vec::new();
+        for val in &self.vals {
+            match val {
+                Value::Func(func) => funcs.push((func.name.to_owned(), val.clone())),
+                Value::Trait(tr) => {
+                    let mut defaults = tr.defaults.iter().collect::<Vec<_>>();
+                    defaults.sort_by_key(|(name, _)| *name);
+                    funcs.extend(defaults.into_iter().map(|(name, method)| (format!("{}.{}", tr.name, name), method.clone())));
+                }
+                Value::Type(ty) => {
+                    funcs.extend(ty.methods().into_iter().map(|(name, method)| (format!("{}.{}", ty.name, name), method)));
+                }
+                _ => {}
+            }
+        }
+        for (name, func) in funcs {
+            let Value::Func(func) = func else { continue };
+            if seen.contains(&Rc::as_ptr(&func)) {
+                continue;
+            }
+            seen.push(Rc::as_ptr(&func));
+            out.push('\n');
+            func.chunk.write_disassembly(out, &format!("fn {}", name), seen);
+        }
+    }
+
+    /// Writes the instruction at `ip` and returns the offset of the next one.
+    fn write_instruction(&self, out: &mut String, ip: usize) -> usize {
+        let opcode = match OpCode::try_from(self.code[ip]) {
+            Ok(opcode) => opcode,
+            Err(_) => {
+                write!(out, "<byte {}>", self.code[ip]).unwrap();
+                return ip + 1;
+            }
+        };
+        let start = out.len();
+        write!(out, "{:?}", opcode).unwrap();
+        let next = ip + 1 + opcode.operands().iter().map(|operand| operand.size()).sum::<usize>();
+        if next > self.code.len() {
+            out.push_str(" <truncated>");
+            return self.code.len();
+        }
+        let mut notes = Vec::new();
+        let mut pos = ip + 1;
+        for &operand in opcode.operands() {
+            let value = match operand.size() {
+                1 => self.code[pos] as usize,
+                _ => u16::from_be_bytes([self.code[pos], self.code[pos + 1]]) as usize,
+            };
+            pos += operand.size();
+            write!(out, " {}", value).unwrap();
+            match operand {
+                Operand::Byte => {}
+                Operand::Const => match self.vals.get(value) {
+                    // Strings are quoted where they are values rather than names.
+                    Some(val) if opcode == OpCode::OpConst && val.is_str() => {
+                        notes.push(format!("{:?}", val.clone().as_str()))
+                    }
+                    Some(val) => notes.push(val.to_string()),
+                    None => notes.push("<missing value>".to_owned()),
+                },
+                Operand::Jump => write!(out, " -> {:04}", pos + value).unwrap(),
+                Operand::Loop => write!(out, " -> {:04}", pos.wrapping_sub(value)).unwrap(),
+            }
+        }
+        if !notes.is_empty() {
+            let width = out.len() - start;
+            write!(out, "{:pad$} ; {}", "", notes.join(", "), pad = 24usize.saturating_sub(width)).unwrap();
+        }
+        next
+    }
+
     pub fn add(&mut self, value: Value) -> usize {
         let idx = self.vals.len();
         if value.is_str() {
@@ -1,127 +1,1593 @@
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use tblang::ast::AliasDef;
+use tblang::ast::Arg;
+use tblang::ast::AssignOp;
 use tblang::ast::Ast;
+use tblang::ast::ConstDef;
+use tblang::ast::EnumDef;
 use tblang::ast::Expr;
+use tblang::ast::ExprKind;
+use tblang::ast::FnDef;
+use tblang::ast::ImplDef;
 use tblang::ast::Item;
+use tblang::ast::ItemKind;
+use tblang::ast::LetBind;
+use tblang::ast::MatchArm;
+use tblang::ast::Pattern;
 use tblang::ast::RelOp;
+use tblang::ast::TraitDef;
+use tblang::ast::TryCatch;
+use tblang::ast::TyDef;
+use tblang::ast::TyHint;
+use tblang::visit::walk_expr;
+use tblang::visit::Visitor;
 
 use crate::bytecode::Chunk;
+use crate::bytecode::OpCode;
 use crate::bytecode::OpCode::*;
+use crate::module::Module;
+use crate::value::binary_op;
+use crate::value::range_op;
+use crate::value::unary_op;
+use crate::value::Function;
+use crate::value::Object;
+use crate::value::Trait;
+use crate::value::TypeDef;
+use crate::value::Variant;
 use crate::value::Value;
 
-pub struct CodeGen {}
+const BUILTIN_TYPES: &[&str] = &["unit", "bool", "int", "real", "str", "range", "fn"];
+
+#[derive(Clone)]
+pub struct CodeGen {
+    scopes: Vec<Vec<String>>,
+    /// Locals of the top-level code. Assignments there bind globals, so these only hold the
+    /// names bound by match arms.
+    script_locals: Vec<String>,
+    traits: HashMap<String, Rc<Trait>>,
+    variants: HashMap<String, Rc<TypeDef>>,
+    types: HashMap<String, Rc<TypeDef>>,
+    aliases: HashMap<String, String>,
+    consts: HashMap<String, Value>,
+    fns: HashMap<String, Rc<Function>>,
+    module: Option<Rc<Module>>,
+    yields: bool,
+    warnings: Vec<String>,
+}
+
+impl Default for CodeGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl CodeGen {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            scopes: Vec::new(),
+            script_locals: Vec::new(),
+            traits: HashMap::new(),
+            variants: HashMap::new(),
+            types: HashMap::new(),
+            aliases: HashMap::new(),
+            consts: HashMap::new(),
+            fns: HashMap::new(),
+            module: None,
+            yields: false,
+            warnings: Vec::new(),
+        }
     }
 
-    pub fn compile(&self, ast: &Ast) -> Result<Chunk, String> {
-        let mut chunk = Chunk::new();
-        for item in &ast.nodes {
-            match item {
-                // Item::Fn(_) => unimplemented!(),
-                // Item::Let(_) => unimplemented!(),
-                Item::Expr(expr) => self.emit_expr(&mut chunk, &expr)?,
+    /// A code generator for an imported module, whose functions resolve globals in the module.
+    pub fn with_module(module: Rc<Module>) -> Self {
+        let mut codegen = Self::new();
+        codegen.module = Some(module);
+        codegen
+    }
+
+    /// Makes a trait or type defined outside of the compiled source, such as a built-in or one
+    /// from an earlier run, available for conformance checks and patterns.
+    pub fn declare(&mut self, value: &Value) {
+        match value {
+            Value::Trait(tr) => {
+                self.traits.insert(tr.name.to_owned(), tr.clone());
             }
+            Value::Type(ty) | Value::Ctor(ty, _) => self.declare_type(ty),
+            Value::Obj(obj) if obj.ty.is_enum && obj.variant().fields.is_empty() => self.declare_type(&obj.ty),
+            _ => {}
         }
+    }
+
+    fn declare_type(&mut self, ty: &Rc<TypeDef>) {
+        self.types.insert(ty.name.to_owned(), ty.clone());
+        for variant in &ty.variants {
+            self.variants.insert(variant.name.to_owned(), ty.clone());
+        }
+    }
+
+    pub fn const_value(&self, name: &str) -> Option<Value> {
+        self.consts.get(name).cloned()
+    }
+
+    /// Returns what a top-level item binds as far as it is known without running the code, for
+    /// consts, functions, traits, types and enum variants.
+    pub fn definition(&self, name: &str) -> Option<Value> {
+        if let Some(value) = self.consts.get(name) {
+            return Some(value.clone());
+        }
+        if let Some(func) = self.fns.get(name) {
+            return Some(Value::Func(func.clone()));
+        }
+        if let Some(tr) = self.traits.get(name) {
+            return Some(Value::Trait(tr.clone()));
+        }
+        if let Some(ty) = self.types.get(name) {
+            return Some(Value::Type(ty.clone()));
+        }
+        let ty = self.variants.get(name)?;
+        let tag = ty.variants.iter().position(|variant| variant.name == name)?;
+        if ty.variants[tag].fields.is_empty() {
+            Some(Value::Obj(Rc::new(Object::new(ty.clone(), tag, Vec::new()))))
+        } else {
+            Some(Value::Ctor(ty.clone(), tag))
+        }
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn compile(&mut self, ast: &Ast) -> Result<Chunk, String> {
+        self.warnings.clear();
+        self.script_locals.clear();
+        let mut chunk = Chunk::new();
+        self.emit_items(&mut chunk, &ast.nodes)?;
         Ok(chunk)
     }
 
-    fn emit_expr(&self, chunk: &mut Chunk, expr: &Expr) -> Result<(), String> {
-        match expr {
-            Expr::Int(lit) => {
-                self.emit_const(chunk, Value::Int(*lit));
+    fn emit_items(&mut self, chunk: &mut Chunk, items: &[Item]) -> Result<(), String> {
+        if items.is_empty() {
+            chunk.write(OpUnit);
+        }
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                chunk.write(OpPop);
             }
-            Expr::Real(lit) => {
-                self.emit_const(chunk, Value::Real(*lit));
+            chunk.set_line(item.span.line);
+            self.emit_item(chunk, item)?;
+        }
+        Ok(())
+    }
+
+    fn emit_item(&mut self, chunk: &mut Chunk, item: &Item) -> Result<(), String> {
+        match &item.kind {
+            // Modules are loaded and bound by the VM before the importing code is compiled.
+            ItemKind::Mod(_) | ItemKind::Use(_) => chunk.write(OpUnit),
+            ItemKind::Fn(def) => {
+                let func = Rc::new(self.compile_fn(def)?);
+                self.emit_const(chunk, Value::Func(func.clone()))?;
+                self.emit_set(chunk, &def.name)?;
+                if self.scopes.is_empty() {
+                    self.fns.insert(def.name.to_owned(), func);
+                }
+            }
+            ItemKind::Def(def) => self.emit_def(chunk, def)?,
+            ItemKind::Enum(def) => self.emit_enum(chunk, def)?,
+            ItemKind::Alias(def) => self.emit_alias(chunk, def)?,
+            ItemKind::Const(def) => self.emit_const_item(chunk, def)?,
+            ItemKind::Let(bind) => self.emit_let(chunk, bind)?,
+            ItemKind::Trait(def) => self.emit_trait(chunk, def)?,
+            ItemKind::Impl(def) => self.emit_impl(chunk, def)?,
+            ItemKind::Expr(expr) => self.emit_expr(chunk, expr)?,
+        }
+        Ok(())
+    }
+
+    fn compile_fn(&mut self, def: &FnDef) -> Result<Function, String> {
+        let body = match &def.body {
+            Some(body) => body,
+            None => return Err(format!("missing body for function '{}'", def.name)),
+        };
+        self.check_fn_hints(def)?;
+        let defaults = self.eval_defaults(def)?;
+
+        let params = def.params.iter().map(|param| param.name.to_owned()).collect::<Vec<_>>();
+        self.scopes.push(params.clone());
+        let outer_yields = mem::replace(&mut self.yields, false);
+        let mut chunk = Chunk::new();
+        let res = self.emit_items(&mut chunk, body);
+        let yields = mem::replace(&mut self.yields, outer_yields);
+        self.scopes.pop();
+        res?;
+
+        chunk.write(OpRet);
+        let mut func = Function::new(&def.name, params, chunk);
+        func.defaults = defaults;
+        func.variadic = def.params.last().is_some_and(|param| param.variadic);
+        func.generator = yields;
+        func.module = self.module.clone();
+        Ok(func)
+    }
+
+    fn eval_defaults(&self, def: &FnDef) -> Result<Vec<Value>, String> {
+        let mut defaults = Vec::new();
+        for param in &def.params {
+            match &param.default {
+                Some(expr) => match self.eval_const(expr) {
+                    Ok(value) => defaults.push(value),
+                    Err(msg) => return Err(format!("in default of parameter '{}': {}", param.name, msg)),
+                },
+                None if param.variadic => {}
+                None if !defaults.is_empty() => {
+                    return Err(format!("parameter '{}' without a default follows parameters with defaults", param.name));
+                }
+                None => {}
             }
-            Expr::Bool(lit) => {
-                chunk.write(if *lit { OpTrue } else { OpFalse });
+        }
+        Ok(defaults)
+    }
+
+    fn check_fn_hints(&self, def: &FnDef) -> Result<(), String> {
+        for param in &def.params {
+            if let Some(ty) = &param.ty {
+                self.resolve_hint(ty)?;
             }
-            Expr::Ident(lit) => {
-                let name = Value::Str(lit.to_owned());
-                let idx = chunk.add(name);
-                chunk.write(OpGet);
-                chunk.write_byte(idx as u8);
+        }
+        if let Some(ty) = &def.ret {
+            self.resolve_hint(ty)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a type hint through any aliases to the name of a known type.
+    fn resolve_hint(&self, hint: &TyHint) -> Result<String, String> {
+        if let Some(target) = self.aliases.get(&hint.name) {
+            return Ok(target.to_owned());
+        }
+        let name = hint.name.as_str();
+        if BUILTIN_TYPES.contains(&name) || self.types.contains_key(name) || self.traits.contains_key(name) {
+            Ok(hint.name.to_owned())
+        } else {
+            Err(format!("unknown type '{}'", hint.name))
+        }
+    }
+
+    fn emit_def(&mut self, chunk: &mut Chunk, def: &TyDef) -> Result<(), String> {
+        let ty = Rc::new(TypeDef::new(&def.name, def.fields.clone()));
+        self.types.insert(def.name.to_owned(), ty.clone());
+        self.variants.insert(def.name.to_owned(), ty.clone());
+        self.emit_const(chunk, Value::Type(ty))?;
+        self.emit_set(chunk, &def.name)
+    }
+
+    /// Binds the enum type along with each of its variants: fieldless variants are bound to their
+    /// single value and the rest to a constructor.
+    fn emit_enum(&mut self, chunk: &mut Chunk, def: &EnumDef) -> Result<(), String> {
+        let mut variants: Vec<Variant> = Vec::new();
+        for variant in &def.variants {
+            if variants.iter().any(|other| other.name == variant.name) {
+                return Err(format!("duplicate variant '{}' in enum '{}'", variant.name, def.name));
             }
-            Expr::Power(base, exp) => {
-                self.emit_expr(chunk, base)?;
-                self.emit_expr(chunk, exp)?;
-                chunk.write(OpPow);
+            variants.push(Variant::new(&variant.name, variant.fields.clone()));
+        }
+
+        let ty = Rc::new(TypeDef::with_variants(&def.name, variants, true));
+        self.types.insert(def.name.to_owned(), ty.clone());
+        self.emit_const(chunk, Value::Type(ty.clone()))?;
+        self.emit_set(chunk, &def.name)?;
+
+        for (tag, variant) in ty.variants.iter().enumerate() {
+            self.variants.insert(variant.name.to_owned(), ty.clone());
+            let value = if variant.fields.is_empty() {
+                Value::Obj(Rc::new(Object::new(ty.clone(), tag, Vec::new())))
+            } else {
+                Value::Ctor(ty.clone(), tag)
+            };
+            chunk.write(OpPop);
+            self.emit_const(chunk, value)?;
+            self.emit_set(chunk, &variant.name)?;
+        }
+        Ok(())
+    }
+
+    /// Aliases only exist at compile time, so they resolve to their target as they are defined.
+    fn emit_alias(&mut self, chunk: &mut Chunk, def: &AliasDef) -> Result<(), String> {
+        let name = def.name.as_str();
+        if BUILTIN_TYPES.contains(&name) || self.types.contains_key(name) || self.traits.contains_key(name) {
+            return Err(format!("type '{}' is already defined", name));
+        }
+        let target = self.resolve_hint(&def.ty)?;
+        self.aliases.insert(def.name.to_owned(), target);
+        chunk.write(OpUnit);
+        Ok(())
+    }
+
+    /// Consts are folded at compile time and inlined at every use, instead of being bound in the
+    /// environment like other items.
+    fn emit_const_item(&mut self, chunk: &mut Chunk, def: &ConstDef) -> Result<(), String> {
+        if let Some(ty) = &def.ty {
+            self.resolve_hint(ty)?;
+        }
+        let value = match self.eval_const(&def.init) {
+            Ok(value) => value,
+            Err(msg) => return Err(format!("in const '{}': {}", def.name, msg)),
+        };
+        self.consts.insert(def.name.to_owned(), value);
+        chunk.write(OpUnit);
+        Ok(())
+    }
+
+    fn emit_let(&mut self, chunk: &mut Chunk, bind: &LetBind) -> Result<(), String> {
+        if let Some(ty) = &bind.ty {
+            self.resolve_hint(ty)?;
+        }
+        self.emit_assign(chunk, &bind.pat, &bind.init, true)
+    }
+
+    fn eval_const(&self, expr: &Expr) -> Result<Value, String> {
+        let value = match &expr.kind {
+            ExprKind::Int(lit) => Value::Int(*lit),
+            ExprKind::Real(lit) => Value::Real(*lit),
+            ExprKind::Bool(lit) => Value::Bool(*lit),
+            ExprKind::Str(lit) => Value::Str(lit.to_owned()),
+            ExprKind::Ident(name) => match self.consts.get(name) {
+                Some(value) => value.clone(),
+                None => return Err(format!("cannot use runtime value '{}' in a constant", name)),
+            },
+            ExprKind::Block(items) if items.is_empty() => Value::Unit,
+            ExprKind::Tuple(elems) => {
+                let elems = elems.iter().map(|elem| self.eval_const(elem)).collect::<Result<_, _>>()?;
+                Value::Tuple(Rc::new(elems))
             }
-            Expr::Negate(inner) => {
-                self.emit_expr(chunk, inner)?;
-                chunk.write(OpNeg);
+            ExprKind::Index(tuple, idx) => match self.eval_const(tuple)? {
+                Value::Tuple(elems) if *idx < elems.len() => elems[*idx].clone(),
+                val => return Err(format!("cannot get element {} of {}", idx, val)),
+            },
+            ExprKind::Negate(inner) => unary_op(OpNeg, &self.eval_const(inner)?)?,
+            ExprKind::BitNot(inner) => unary_op(OpBitNot, &self.eval_const(inner)?)?,
+            ExprKind::Shl(lhs, rhs) => self.eval_binary(OpShl, lhs, rhs)?,
+            ExprKind::Shr(lhs, rhs) => self.eval_binary(OpShr, lhs, rhs)?,
+            ExprKind::UShr(lhs, rhs) => self.eval_binary(OpUShr, lhs, rhs)?,
+            ExprKind::BitAnd(lhs, rhs) => self.eval_binary(OpBitAnd, lhs, rhs)?,
+            ExprKind::BitXor(lhs, rhs) => self.eval_binary(OpBitXor, lhs, rhs)?,
+            ExprKind::BitOr(lhs, rhs) => self.eval_binary(OpBitOr, lhs, rhs)?,
+            ExprKind::Power(lhs, rhs) => self.eval_binary(OpPow, lhs, rhs)?,
+            ExprKind::Mul(lhs, rhs) => self.eval_binary(OpMul, lhs, rhs)?,
+            ExprKind::Div(lhs, rhs) => self.eval_binary(OpDiv, lhs, rhs)?,
+            ExprKind::Rem(lhs, rhs) => self.eval_binary(OpRem, lhs, rhs)?,
+            ExprKind::Add(lhs, rhs) => self.eval_binary(OpAdd, lhs, rhs)?,
+            ExprKind::Sub(lhs, rhs) => self.eval_binary(OpSub, lhs, rhs)?,
+            ExprKind::Relation(lhs, ops) => {
+                let mut lhs = self.eval_const(lhs)?;
+                for (op, rhs) in ops {
+                    let rhs = self.eval_const(rhs)?;
+                    if !binary_op(rel_opcode(op), &lhs, &rhs)?.as_bool() {
+                        return Ok(Value::Bool(false));
+                    }
+                    lhs = rhs;
+                }
+                Value::Bool(true)
             }
-            Expr::Add(lhs, rhs) => {
-                self.emit_expr(chunk, lhs)?;
-                self.emit_expr(chunk, rhs)?;
-                chunk.write(OpAdd);
+            ExprKind::Coalesce(lhs, rhs) => match self.eval_const(lhs)? {
+                Value::Unit => self.eval_const(rhs)?,
+                value => value,
+            },
+            ExprKind::Range(start, end, inclusive) => range_op(&self.eval_const(start)?, &self.eval_const(end)?, *inclusive)?,
+            _ => return Err("expected a constant expression".to_owned()),
+        };
+        Ok(value)
+    }
+
+    fn eval_binary(&self, opcode: OpCode, lhs: &Expr, rhs: &Expr) -> Result<Value, String> {
+        let lhs = self.eval_const(lhs)?;
+        let rhs = self.eval_const(rhs)?;
+        binary_op(opcode, &lhs, &rhs)
+    }
+
+    fn emit_trait(&mut self, chunk: &mut Chunk, def: &TraitDef) -> Result<(), String> {
+        let mut methods = Vec::new();
+        let mut defaults = HashMap::new();
+        for method in &def.methods {
+            if methods.contains(&method.name) {
+                return Err(format!("duplicate method '{}' in trait '{}'", method.name, def.name));
             }
-            Expr::Sub(lhs, rhs) => {
-                self.emit_expr(chunk, lhs)?;
-                self.emit_expr(chunk, rhs)?;
-                chunk.write(OpSub);
+            methods.push(method.name.to_owned());
+            if method.body.is_some() {
+                let func = self.compile_fn(method)?;
+                defaults.insert(method.name.to_owned(), Value::Func(Rc::new(func)));
+            } else {
+                self.check_fn_hints(method)?;
             }
-            Expr::Mul(lhs, rhs) => {
-                self.emit_expr(chunk, lhs)?;
-                self.emit_expr(chunk, rhs)?;
-                chunk.write(OpMul);
+        }
+
+        let tr = Rc::new(Trait::new(&def.name, methods, defaults));
+        self.traits.insert(def.name.to_owned(), tr.clone());
+        self.emit_const(chunk, Value::Trait(tr))?;
+        self.emit_set(chunk, &def.name)
+    }
+
+    fn emit_impl(&mut self, chunk: &mut Chunk, def: &ImplDef) -> Result<(), String> {
+        if let Some(trait_name) = &def.trait_name {
+            self.check_conformance(trait_name, def)?;
+        }
+
+        self.emit_get(chunk, &def.ty_name)?;
+        for method in &def.methods {
+            let func = self.compile_fn(method)?;
+            self.emit_const(chunk, Value::Func(Rc::new(func)))?;
+            let idx = chunk.add(Value::Str(method.name.to_owned()));
+            chunk.write(OpMethod);
+            chunk.write_operand(idx, "constants")?;
+        }
+        if let Some(trait_name) = &def.trait_name {
+            self.emit_get(chunk, trait_name)?;
+            chunk.write(OpImpl);
+        }
+        chunk.write(OpPop);
+        chunk.write(OpUnit);
+        Ok(())
+    }
+
+    fn check_conformance(&self, trait_name: &str, def: &ImplDef) -> Result<(), String> {
+        let tr = match self.traits.get(trait_name) {
+            Some(tr) => tr,
+            None => return Err(format!("unknown trait '{}'", trait_name)),
+        };
+
+        for method in &def.methods {
+            if !tr.has_method(&method.name) {
+                return Err(format!("method '{}' is not a member of trait '{}'", method.name, tr.name));
             }
-            Expr::Div(lhs, rhs) => {
-                self.emit_expr(chunk, lhs)?;
+        }
+
+        let missing = tr.methods.iter()
+            .filter(|name| !tr.defaults.contains_key(*name))
+            .filter(|name| !def.methods.iter().any(|method| &method.name == *name))
+            .map(|name| format!("'{}'", name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(format!(
+                "missing methods {} in impl of '{}' for '{}'",
+                missing.join(", "),
+                tr.name,
+                def.ty_name,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn emit_expr(&mut self, chunk: &mut Chunk, expr: &Expr) -> Result<(), String> {
+        ExprEmitter { codegen: self, chunk }.visit_expr(expr)
+    }
+
+    /// Lowers a for loop onto the iterator opcodes. The iterable and its cursor stay on the
+    /// stack while the loop runs, and the loop itself evaluates to unit.
+    fn emit_for(&mut self, chunk: &mut Chunk, pat: &Pattern, iterable: &Expr, body: &[Item]) -> Result<(), String> {
+        if !is_binding(pat) {
+            return Err("refutable pattern in for loop, use match instead".to_owned());
+        }
+        self.emit_expr(chunk, iterable)?;
+        chunk.write(OpIter);
+
+        let start = chunk.len();
+        let exit = self.emit_jump(chunk, OpNext);
+        self.emit_bind(chunk, pat, true)?;
+        self.emit_items(chunk, body)?;
+        chunk.write(OpPop);
+        self.emit_loop(chunk, start)?;
+        self.patch_jump(chunk, exit)?;
+
+        chunk.write(OpPop);
+        chunk.write(OpPop);
+        chunk.write(OpUnit);
+        Ok(())
+    }
+
+    /// Assigns the value of `rhs` to the names in an irrefutable pattern. With `declare` the
+    /// names are new locals, as for `let`.
+    fn emit_assign(&mut self, chunk: &mut Chunk, pat: &Pattern, rhs: &Expr, declare: bool) -> Result<(), String> {
+        check_destructure(pat, rhs)?;
+        self.emit_expr(chunk, rhs)?;
+        if let Pattern::Ident(name) = pat {
+            return self.emit_bind_name(chunk, name, declare);
+        }
+        self.emit_bind(chunk, pat, declare)?;
+        chunk.write(OpUnit);
+        Ok(())
+    }
+
+    /// Reads the target, applies the operator and writes the result back. For fields and indexes
+    /// the object is evaluated once and duplicated for the read.
+    fn emit_op_assign(&mut self, chunk: &mut Chunk, op: &AssignOp, target: &Expr, rhs: &Expr) -> Result<(), String> {
+        let opcode = match op {
+            AssignOp::Add => OpAdd,
+            AssignOp::Sub => OpSub,
+            AssignOp::Mul => OpMul,
+            AssignOp::Div => OpDiv,
+            AssignOp::Rem => OpRem,
+            AssignOp::Pow => OpPow,
+        };
+        match &target.kind {
+            ExprKind::Ident(name) => {
+                self.emit_get(chunk, name)?;
                 self.emit_expr(chunk, rhs)?;
-                chunk.write(OpDiv);
+                chunk.write(opcode);
+                self.emit_set(chunk, name)?;
             }
-            Expr::Rem(lhs, rhs) => {
-                self.emit_expr(chunk, lhs)?;
+            ExprKind::Field(object, name) => {
+                let idx = chunk.add(Value::Str(name.to_owned()));
+                self.emit_expr(chunk, object)?;
+                chunk.write(OpDup);
+                chunk.write(OpField);
+                chunk.write_operand(idx, "constants")?;
                 self.emit_expr(chunk, rhs)?;
-                chunk.write(OpRem);
+                chunk.write(opcode);
+                chunk.write(OpSetField);
+                chunk.write_operand(idx, "constants")?;
             }
-            Expr::Relation(lhs, ops) => {
-                // todo: handle chained comparisons
-                let (op, rhs) = &ops[0];
-                self.emit_expr(chunk, lhs)?;
+            ExprKind::Index(object, idx) => {
+                self.emit_expr(chunk, object)?;
+                chunk.write(OpDup);
+                chunk.write(OpElem);
+                chunk.write_operand(*idx, "tuple elements")?;
                 self.emit_expr(chunk, rhs)?;
-                let op = match op {
-                    RelOp::Lt => OpLt,
-                    RelOp::Gt => OpGt,
-                    RelOp::Le => OpLtEq,
-                    RelOp::Ge => OpGtEq,
-                    RelOp::Eq => OpEqual,
-                    RelOp::Ne => OpNotEq,
+                chunk.write(opcode);
+                chunk.write(OpSetElem);
+                chunk.write_operand(*idx, "tuple elements")?;
+            }
+            _ => return Err("invalid assignment target".to_owned()),
+        }
+        Ok(())
+    }
+
+    /// Binds the value on top of the stack to a pattern, consuming it.
+    fn emit_bind(&mut self, chunk: &mut Chunk, pat: &Pattern, declare: bool) -> Result<(), String> {
+        match pat {
+            Pattern::Wildcard => chunk.write(OpPop),
+            Pattern::Ident(name) => {
+                self.emit_bind_name(chunk, name, declare)?;
+                chunk.write(OpPop);
+            }
+            Pattern::Tuple(pats) => {
+                chunk.write(OpUnpack);
+                chunk.write_operand(pats.len(), "tuple elements")?;
+                for pat in pats {
+                    self.emit_bind(chunk, pat, declare)?;
+                }
+            }
+            Pattern::Lit(_) | Pattern::Variant(..) => {
+                return Err("refutable pattern in assignment, use match instead".to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_bind_name(&mut self, chunk: &mut Chunk, name: &str, declare: bool) -> Result<(), String> {
+        if declare {
+            if let Some(locals) = self.scopes.last_mut() {
+                locals.push(name.to_owned());
+            }
+        }
+        self.emit_set(chunk, name)
+    }
+
+    /// Lowers a match into a sequence of tests, one per arm, with the subject kept on the stack
+    /// until an arm is taken. If no arm matches the result is unit.
+    fn emit_match(&mut self, chunk: &mut Chunk, subject: &Expr, arms: &[MatchArm]) -> Result<(), String> {
+        self.check_exhaustive(arms);
+        self.emit_expr(chunk, subject)?;
+
+        let mut end_jumps = Vec::new();
+        for arm in arms {
+            let mut fails = Vec::new();
+            let start = self.locals_mut().len();
+            self.emit_pattern(chunk, &arm.pat, 0, &mut fails)?;
+            let end = self.locals_mut().len();
+            if let Some(guard) = &arm.guard {
+                self.emit_expr(chunk, guard)?;
+                fails.push((self.emit_jump(chunk, OpBranch), 0));
+            }
+            chunk.write(OpPop);
+            self.emit_expr(chunk, &arm.body)?;
+            self.hide_locals(start, end);
+            end_jumps.push(self.emit_jump(chunk, OpJump));
+            self.emit_fail_ladder(chunk, &fails)?;
+        }
+
+        chunk.write(OpPop);
+        chunk.write(OpUnit);
+        for pos in end_jumps {
+            self.patch_jump(chunk, pos)?;
+        }
+        Ok(())
+    }
+
+    /// Emits the test for a pattern against the value on top of the stack, leaving that value in
+    /// place. Failed tests jump away and are recorded along with how many extra values (the
+    /// fields being tested in nested patterns) are on the stack at that point.
+    fn emit_pattern(
+        &mut self,
+        chunk: &mut Chunk,
+        pat: &Pattern,
+        depth: usize,
+        fails: &mut Vec<(usize, usize)>,
+    ) -> Result<(), String> {
+        match pat {
+            Pattern::Wildcard => {}
+            Pattern::Ident(name) if self.variants.contains_key(name) => {
+                self.emit_variant_test(chunk, name, depth, fails)?;
+            }
+            Pattern::Ident(name) => {
+                let locals = self.locals_mut();
+                locals.push(name.to_owned());
+                let slot = locals.len() - 1;
+                chunk.write(OpDup);
+                chunk.write(OpSetLocal);
+                chunk.write_operand(slot, "local variables")?;
+                chunk.write(OpPop);
+            }
+            Pattern::Lit(lit) => {
+                chunk.write(OpDup);
+                self.emit_expr(chunk, lit)?;
+                chunk.write(OpEqual);
+                fails.push((self.emit_jump(chunk, OpBranch), depth));
+            }
+            Pattern::Variant(name, pats) => {
+                let ty = match self.variants.get(name) {
+                    Some(ty) => ty,
+                    None => return Err(format!("unknown variant '{}'", name)),
                 };
-                chunk.write(op);
+                let tag = ty.variant_tag(name).unwrap();
+                let count = ty.variants[tag].fields.len();
+                if count != pats.len() {
+                    return Err(format!("pattern '{}' expects {} fields but got {}", name, count, pats.len()));
+                }
+
+                self.emit_variant_test(chunk, name, depth, fails)?;
+                self.emit_elem_patterns(chunk, pats, depth, fails)?;
             }
-            Expr::Call(callee, args) => {
-                self.emit_call(chunk, callee, args)?;
+            Pattern::Tuple(pats) => {
+                chunk.write(OpDup);
+                chunk.write(OpIsTuple);
+                chunk.write_operand(pats.len(), "tuple elements")?;
+                fails.push((self.emit_jump(chunk, OpBranch), depth));
+                self.emit_elem_patterns(chunk, pats, depth, fails)?;
             }
-            Expr::Assign(name, rhs) => {
-                self.emit_expr(chunk, rhs)?;
-                let name = Value::Str(name.to_owned());
-                let idx = chunk.add(name);
-                chunk.write(OpSet);
-                chunk.write_byte(idx as u8);
+        }
+        Ok(())
+    }
+
+    fn emit_elem_patterns(
+        &mut self,
+        chunk: &mut Chunk,
+        pats: &[Pattern],
+        depth: usize,
+        fails: &mut Vec<(usize, usize)>,
+    ) -> Result<(), String> {
+        for (idx, pat) in pats.iter().enumerate() {
+            if matches!(pat, Pattern::Wildcard) {
+                continue;
             }
+            chunk.write(OpDup);
+            chunk.write(OpElem);
+            chunk.write_operand(idx, "tuple elements")?;
+            self.emit_pattern(chunk, pat, depth + 1, fails)?;
+            chunk.write(OpPop);
         }
         Ok(())
     }
 
-    fn emit_call(&self, chunk: &mut Chunk, callee: &Expr, args: &[Expr]) -> Result<(), String> {
-        match callee {
-            Expr::Ident(_) => self.emit_expr(chunk, callee)?,
-            _ => return Err(format!("can only call functions")),
+    fn emit_variant_test(&mut self, chunk: &mut Chunk, name: &str, depth: usize, fails: &mut Vec<(usize, usize)>) -> Result<(), String> {
+        let idx = chunk.add(Value::Str(name.to_owned()));
+        chunk.write(OpDup);
+        chunk.write(OpIsVariant);
+        chunk.write_operand(idx, "constants")?;
+        fails.push((self.emit_jump(chunk, OpBranch), depth));
+        Ok(())
+    }
+
+    /// Lands each failed test at a point that pops its extra values before moving on to the
+    /// next arm, with the deepest failures popping the most.
+    fn emit_fail_ladder(&self, chunk: &mut Chunk, fails: &[(usize, usize)]) -> Result<(), String> {
+        let max_depth = fails.iter().map(|(_, depth)| *depth).max().unwrap_or(0);
+        for level in (0..=max_depth).rev() {
+            for (pos, depth) in fails {
+                if *depth == level {
+                    self.patch_jump(chunk, *pos)?;
+                }
+            }
+            if level > 0 {
+                chunk.write(OpPop);
+            }
+        }
+        Ok(())
+    }
+
+    /// Warns when a match over an enum neither covers every variant nor has a catch-all arm.
+    fn check_exhaustive(&mut self, arms: &[MatchArm]) {
+        let mut ty: Option<Rc<TypeDef>> = None;
+        let mut covered = Vec::new();
+        for arm in arms {
+            let is_total = arm.guard.is_none();
+            let name = match &arm.pat {
+                Pattern::Wildcard if is_total => return,
+                Pattern::Ident(name) if !self.variants.contains_key(name) && is_total => return,
+                Pattern::Ident(name) => name,
+                Pattern::Variant(name, pats) if pats.iter().all(|pat| self.is_irrefutable(pat)) => name,
+                Pattern::Variant(name, _) => {
+                    ty = ty.or_else(|| self.variants.get(name).cloned());
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(variant_ty) = self.variants.get(name) {
+                ty = ty.or_else(|| Some(variant_ty.clone()));
+                if is_total {
+                    covered.push(name.to_owned());
+                }
+            }
+        }
+
+        let ty = match ty {
+            Some(ty) if ty.is_enum => ty,
+            _ => return,
+        };
+        let missing = ty.variants.iter()
+            .filter(|variant| !covered.contains(&variant.name))
+            .map(|variant| format!("'{}'", variant.name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.warnings.push(format!(
+                "non-exhaustive match on '{}', missing {}",
+                ty.name,
+                missing.join(", "),
+            ));
+        }
+    }
+
+    fn is_irrefutable(&self, pat: &Pattern) -> bool {
+        match pat {
+            Pattern::Wildcard => true,
+            Pattern::Ident(name) => !self.variants.contains_key(name),
+            Pattern::Lit(_) => false,
+            Pattern::Tuple(pats) => pats.iter().all(|pat| self.is_irrefutable(pat)),
+            Pattern::Variant(name, pats) => match self.variants.get(name) {
+                Some(ty) => ty.variants.len() == 1 && pats.iter().all(|pat| self.is_irrefutable(pat)),
+                None => false,
+            },
+        }
+    }
+
+    /// Emits a call with the arguments in source order. Named arguments are matched to
+    /// parameters by the VM, unless the callee is a function item known here, in which case the
+    /// names are checked now and the call becomes positional when the arguments are in order.
+    /// Spread arguments are expanded by the VM, so calls using them are never checked here.
+    fn emit_call(&mut self, chunk: &mut Chunk, callee: &Expr, args: &[Arg]) -> Result<(), String> {
+        let mut names: Vec<&str> = Vec::new();
+        for name in args.iter().filter_map(|arg| arg.name()) {
+            if names.contains(&name) {
+                return Err(format!("duplicate argument '{}'", name));
+            }
+            names.push(name);
+        }
+        let spreads = args.iter()
+            .enumerate()
+            .filter(|(_, arg)| matches!(arg, Arg::Spread(_)))
+            .map(|(pos, _)| Value::Int(pos as i32))
+            .collect::<Vec<_>>();
+        if !spreads.is_empty() && !names.is_empty() {
+            return Err("cannot combine spread and named arguments".to_owned());
+        }
+
+        let mut skip = None;
+        let func = match &callee.kind {
+            ExprKind::Field(object, _) => {
+                self.emit_expr(chunk, object)?;
+                None
+            }
+            ExprKind::SafeField(object, _) => {
+                self.emit_expr(chunk, object)?;
+                skip = Some(self.emit_jump(chunk, OpJumpIfUnit));
+                None
+            }
+            ExprKind::Ident(name) => {
+                self.emit_expr(chunk, callee)?;
+                match self.resolve_local(name) {
+                    Some(_) => None,
+                    None => self.fns.get(name).cloned(),
+                }
+            }
+            _ => return Err("can only call functions".to_owned()),
         };
         for arg in args {
-            self.emit_expr(chunk, arg)?;
+            self.emit_expr(chunk, arg.value())?;
+        }
+
+        let method = match &callee.kind {
+            ExprKind::Field(_, name) | ExprKind::SafeField(_, name) => Some(chunk.add(Value::Str(name.to_owned()))),
+            _ => None,
+        };
+        let mut count = args.len();
+        if !spreads.is_empty() {
+            let positions = chunk.add(Value::Tuple(Rc::new(spreads)));
+            if let Some(idx) = method {
+                chunk.write(OpInvokeSpread);
+                chunk.write_operand(idx, "constants")?;
+            } else {
+                chunk.write(OpCallSpread);
+            }
+            chunk.write_operand(count, "arguments")?;
+            chunk.write_operand(positions, "constants")?;
+            return self.patch_skip(chunk, skip);
+        }
+        if let Some(func) = func {
+            if let Some(defaults) = check_args(&func, args)? {
+                count += defaults.len();
+                for value in defaults {
+                    self.emit_const(chunk, value)?;
+                }
+                names.clear();
+            }
+        }
+        let names = if names.is_empty() {
+            None
+        } else {
+            let names = names.iter().map(|name| Value::Str(name.to_string())).collect();
+            Some(chunk.add(Value::Tuple(Rc::new(names))))
+        };
+
+        match (method, names) {
+            (Some(idx), None) => {
+                chunk.write(OpInvoke);
+                chunk.write_operand(idx, "constants")?;
+                chunk.write_operand(count, "arguments")?;
+            }
+            (Some(idx), Some(names)) => {
+                chunk.write(OpInvokeNamed);
+                chunk.write_operand(idx, "constants")?;
+                chunk.write_operand(count, "arguments")?;
+                chunk.write_operand(names, "named arguments")?;
+            }
+            (_, None) => {
+                chunk.write(OpCall);
+                chunk.write_operand(count, "arguments")?;
+            }
+            (_, Some(names)) => {
+                chunk.write(OpCallNamed);
+                chunk.write_operand(count, "arguments")?;
+                chunk.write_operand(names, "named arguments")?;
+            }
+        }
+        self.patch_skip(chunk, skip)
+    }
+
+    /// Lands the jump taken by a nil-safe access whose receiver is unit, if there is one.
+    fn patch_skip(&self, chunk: &mut Chunk, skip: Option<usize>) -> Result<(), String> {
+        match skip {
+            Some(pos) => self.patch_jump(chunk, pos),
+            None => Ok(()),
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        let locals = self.scopes.last().unwrap_or(&self.script_locals);
+        locals.iter().rposition(|local| local == name)
+    }
+
+    /// The locals of the function being compiled, or of the top-level code.
+    fn locals_mut(&mut self) -> &mut Vec<String> {
+        match self.scopes.last_mut() {
+            Some(locals) => locals,
+            None => &mut self.script_locals,
+        }
+    }
+
+    /// Declares a local that no name refers to, for values the generated code keeps around.
+    fn hidden_local(&mut self) -> usize {
+        let locals = self.locals_mut();
+        locals.push(String::new());
+        locals.len() - 1
+    }
+
+    /// Hides the names of the locals in `start..end` once their scope has ended. Their slots
+    /// stay taken, as locals declared later may come after them.
+    fn hide_locals(&mut self, start: usize, end: usize) {
+        for local in &mut self.locals_mut()[start..end] {
+            local.clear();
+        }
+    }
+
+    fn emit_get(&mut self, chunk: &mut Chunk, name: &str) -> Result<(), String> {
+        if let Some(slot) = self.resolve_local(name) {
+            chunk.write(OpGetLocal);
+            chunk.write_operand(slot, "local variables")?;
+        } else if let Some(value) = self.consts.get(name) {
+            self.emit_const(chunk, value.clone())?;
+        } else {
+            let idx = chunk.add(Value::Str(name.to_owned()));
+            chunk.write(OpGet);
+            chunk.write_operand(idx, "constants")?;
+        }
+        Ok(())
+    }
+
+    /// Assignments inside a function body bind locals, declaring the name on first use; at the
+    /// top level they bind globals in the environment, unless a match arm has bound the name.
+    fn emit_set(&mut self, chunk: &mut Chunk, name: &str) -> Result<(), String> {
+        if self.consts.contains_key(name) {
+            return Err(format!("cannot assign to const '{}'", name));
+        }
+        let slot = match (self.resolve_local(name), self.scopes.last_mut()) {
+            (Some(slot), _) => slot,
+            (None, Some(locals)) => {
+                locals.push(name.to_owned());
+                locals.len() - 1
+            }
+            (None, None) => {
+                self.fns.remove(name);
+                let idx = chunk.add(Value::Str(name.to_owned()));
+                chunk.write(OpSet);
+                chunk.write_operand(idx, "constants")?;
+                return Ok(());
+            }
+        };
+        chunk.write(OpSetLocal);
+        chunk.write_operand(slot, "local variables")?;
+        Ok(())
+    }
+
+    fn emit_jump(&self, chunk: &mut Chunk, opcode: OpCode) -> usize {
+        chunk.write(opcode);
+        let pos = chunk.len();
+        chunk.write_u16(u16::MAX);
+        pos
+    }
+
+    /// Lowers a try expression onto handlers installed by `OpTry`. On an error the VM unwinds
+    /// to the frame and stack height of the innermost handler and jumps to it with the error on
+    /// the stack. A finally block is emitted on both paths out, re-raising on the error path.
+    fn emit_try(&mut self, chunk: &mut Chunk, def: &TryCatch) -> Result<(), String> {
+        let finally = def.finally.as_ref().map(|finally| (finally, self.emit_jump(chunk, OpTry)));
+        match &def.catch {
+            Some((pat, handler)) => {
+                if !is_binding(pat) {
+                    return Err("refutable pattern in catch, use match instead".to_owned());
+                }
+                let catch = self.emit_jump(chunk, OpTry);
+                self.emit_items(chunk, &def.body)?;
+                chunk.write(OpEndTry);
+                let end = self.emit_jump(chunk, OpJump);
+                self.patch_jump(chunk, catch)?;
+                self.emit_bind(chunk, pat, true)?;
+                self.emit_items(chunk, handler)?;
+                self.patch_jump(chunk, end)?;
+            }
+            None => self.emit_items(chunk, &def.body)?,
+        }
+
+        if let Some((finally, handler)) = finally {
+            chunk.write(OpEndTry);
+            self.emit_items(chunk, finally)?;
+            chunk.write(OpPop);
+            let end = self.emit_jump(chunk, OpJump);
+            self.patch_jump(chunk, handler)?;
+            self.emit_items(chunk, finally)?;
+            chunk.write(OpPop);
+            chunk.write(OpRaise);
+            self.patch_jump(chunk, end)?;
         }
-        chunk.write(OpCall);
-        chunk.write_byte(args.len() as u8);
         Ok(())
     }
 
-    fn emit_const(&self, chunk: &mut Chunk, value: Value) {
+    fn emit_loop(&self, chunk: &mut Chunk, start: usize) -> Result<(), String> {
+        chunk.write(OpLoop);
+        let offset = chunk.len() + 2 - start;
+        if offset > u16::MAX as usize {
+            return Err("loop body is too large".to_owned());
+        }
+        chunk.write_u16(offset as u16);
+        Ok(())
+    }
+
+    fn patch_jump(&self, chunk: &mut Chunk, pos: usize) -> Result<(), String> {
+        let offset = chunk.len() - pos - 2;
+        if offset > u16::MAX as usize {
+            return Err("too much code to jump over".to_owned());
+        }
+        chunk.patch_u16(pos, offset as u16);
+        Ok(())
+    }
+
+    fn emit_const(&self, chunk: &mut Chunk, value: Value) -> Result<(), String> {
         let idx = chunk.add(value);
         chunk.write(OpConst);
-        chunk.write_byte(idx as u8);
+        chunk.write_operand(idx, "constants")?;
+        Ok(())
+    }
+}
+
+/// Matches the arguments of a call to the parameters of a function known at compile time,
+/// reporting unknown, repeated and missing arguments. If the arguments are in parameter order,
+/// returns the defaults to pass for the parameters left out. Positional arguments past the
+/// fixed parameters of a variadic function are left for the VM to collect.
+fn check_args(func: &Function, args: &[Arg]) -> Result<Option<Vec<Value>>, String> {
+    let params = func.fixed_params();
+    if !func.variadic && args.len() > params.len() {
+        return Err(func.arity_error(args.len()));
+    }
+    let mut given = vec![false; params.len()];
+    let mut in_order = true;
+    for (pos, arg) in args.iter().enumerate() {
+        let idx = match arg.name() {
+            None if pos >= params.len() => continue,
+            None => pos,
+            Some(name) => match params.iter().position(|param| param == name) {
+                Some(idx) => idx,
+                None => return Err(format!("unknown parameter '{}' in call to '{}'", name, func.name)),
+            },
+        };
+        if given[idx] {
+            return Err(format!("parameter '{}' given more than once in call to '{}'", params[idx], func.name));
+        }
+        given[idx] = true;
+        in_order = in_order && idx == pos;
+    }
+    if let Some(idx) = (0..func.min_arity()).find(|idx| !given[*idx]) {
+        return Err(format!("missing argument '{}' in call to '{}'", params[idx], func.name));
+    }
+    if !in_order {
+        return Ok(None);
+    }
+    let count = args.len().min(params.len());
+    Ok(Some(func.defaults[count - func.min_arity()..].to_vec()))
+}
+
+/// Whether a pattern only binds names, which is what loops and assignments accept.
+fn is_binding(pat: &Pattern) -> bool {
+    match pat {
+        Pattern::Wildcard | Pattern::Ident(_) => true,
+        Pattern::Tuple(pats) => pats.iter().all(is_binding),
+        Pattern::Lit(_) | Pattern::Variant(..) => false,
+    }
+}
+
+/// Reports destructuring of a tuple literal with the wrong number of elements at compile time,
+/// rather than when the assignment runs.
+fn check_destructure(pat: &Pattern, rhs: &Expr) -> Result<(), String> {
+    if let (Pattern::Tuple(pats), ExprKind::Tuple(elems)) = (pat, &rhs.kind) {
+        if pats.len() != elems.len() {
+            return Err(format!(
+                "cannot destructure a tuple of {} elements into {} patterns",
+                elems.len(),
+                pats.len(),
+            ));
+        }
+        for (pat, elem) in pats.iter().zip(elems) {
+            check_destructure(pat, elem)?;
+        }
+    }
+    Ok(())
+}
+
+/// Emits the code for an expression, which leaves its value on the stack. Expressions that take
+/// more than a few instructions are handed back to the `CodeGen` methods that lower them.
+struct ExprEmitter<'a> {
+    codegen: &'a mut CodeGen,
+    chunk: &'a mut Chunk,
+}
+
+impl ExprEmitter<'_> {
+    fn unary(&mut self, inner: &Expr, opcode: OpCode) -> Result<(), String> {
+        self.visit_expr(inner)?;
+        self.chunk.write(opcode);
+        Ok(())
+    }
+
+    fn binary(&mut self, lhs: &Expr, rhs: &Expr, opcode: OpCode) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        self.visit_expr(rhs)?;
+        self.chunk.write(opcode);
+        Ok(())
+    }
+}
+
+impl Visitor for ExprEmitter<'_> {
+    /// Marks the code of the expression with its line, and the code that follows with the line
+    /// of the enclosing expression again.
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), String> {
+        let outer = self.chunk.line(self.chunk.len());
+        self.chunk.set_line(expr.span.line);
+        walk_expr(self, expr)?;
+        if let Some(line) = outer {
+            self.chunk.set_line(line);
+        }
+        Ok(())
+    }
+
+    fn visit_int(&mut self, lit: i32) -> Result<(), String> {
+        self.codegen.emit_const(self.chunk, Value::Int(lit))?;
+        Ok(())
+    }
+
+    fn visit_real(&mut self, lit: f64) -> Result<(), String> {
+        self.codegen.emit_const(self.chunk, Value::Real(lit))?;
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, lit: bool) -> Result<(), String> {
+        self.chunk.write(if lit { OpTrue } else { OpFalse });
+        Ok(())
+    }
+
+    fn visit_str(&mut self, lit: &str) -> Result<(), String> {
+        self.codegen.emit_const(self.chunk, Value::Str(lit.to_owned()))?;
+        Ok(())
+    }
+
+    fn visit_ident(&mut self, name: &str) -> Result<(), String> {
+        self.codegen.emit_get(self.chunk, name)?;
+        Ok(())
+    }
+
+    fn visit_tuple(&mut self, elems: &[Expr]) -> Result<(), String> {
+        for elem in elems {
+            self.visit_expr(elem)?;
+        }
+        self.chunk.write(OpTuple);
+        self.chunk.write_operand(elems.len(), "tuple elements")?;
+        Ok(())
+    }
+
+    fn visit_block(&mut self, items: &[Item]) -> Result<(), String> {
+        self.codegen.emit_items(self.chunk, items)
+    }
+
+    fn visit_index(&mut self, tuple: &Expr, idx: usize) -> Result<(), String> {
+        self.visit_expr(tuple)?;
+        self.chunk.write(OpElem);
+        self.chunk.write_operand(idx, "tuple elements")?;
+        Ok(())
+    }
+
+    fn visit_field(&mut self, object: &Expr, name: &str) -> Result<(), String> {
+        self.visit_expr(object)?;
+        let idx = self.chunk.add(Value::Str(name.to_owned()));
+        self.chunk.write(OpField);
+        self.chunk.write_operand(idx, "constants")?;
+        Ok(())
+    }
+
+    fn visit_safe_field(&mut self, object: &Expr, name: &str) -> Result<(), String> {
+        self.visit_expr(object)?;
+        let skip = self.codegen.emit_jump(self.chunk, OpJumpIfUnit);
+        let idx = self.chunk.add(Value::Str(name.to_owned()));
+        self.chunk.write(OpField);
+        self.chunk.write_operand(idx, "constants")?;
+        self.codegen.patch_jump(self.chunk, skip)
+    }
+
+    fn visit_safe_index(&mut self, tuple: &Expr, idx: usize) -> Result<(), String> {
+        self.visit_expr(tuple)?;
+        let skip = self.codegen.emit_jump(self.chunk, OpJumpIfUnit);
+        self.chunk.write(OpElem);
+        self.chunk.write_operand(idx, "tuple elements")?;
+        self.codegen.patch_jump(self.chunk, skip)
+    }
+
+    fn visit_coalesce(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        let end = self.codegen.emit_jump(self.chunk, OpJumpNotUnit);
+        self.chunk.write(OpPop);
+        self.visit_expr(rhs)?;
+        self.codegen.patch_jump(self.chunk, end)
+    }
+
+    fn visit_power(&mut self, base: &Expr, exp: &Expr) -> Result<(), String> {
+        self.binary(base, exp, OpPow)
+    }
+
+    fn visit_negate(&mut self, inner: &Expr) -> Result<(), String> {
+        self.unary(inner, OpNeg)
+    }
+
+    fn visit_bit_not(&mut self, inner: &Expr) -> Result<(), String> {
+        self.unary(inner, OpBitNot)
+    }
+
+    fn visit_add(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpAdd)
+    }
+
+    fn visit_sub(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpSub)
+    }
+
+    fn visit_mul(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpMul)
+    }
+
+    fn visit_div(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpDiv)
+    }
+
+    fn visit_rem(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpRem)
+    }
+
+    fn visit_shl(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpShl)
+    }
+
+    fn visit_shr(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpShr)
+    }
+
+    fn visit_ushr(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpUShr)
+    }
+
+    fn visit_bit_and(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpBitAnd)
+    }
+
+    fn visit_bit_xor(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpBitXor)
+    }
+
+    fn visit_bit_or(&mut self, lhs: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.binary(lhs, rhs, OpBitOr)
+    }
+
+    /// Lowers `a < b < c` into `a < b` followed, only if it holds, by `b < c`. The operands
+    /// compared twice are evaluated once and kept in between in one hidden local for the chain.
+    fn visit_relation(&mut self, lhs: &Expr, ops: &[(RelOp, Expr)]) -> Result<(), String> {
+        self.visit_expr(lhs)?;
+        let mut fails = Vec::new();
+        let mut hidden = None;
+        for (idx, (op, rhs)) in ops.iter().enumerate() {
+            if let Some(slot) = hidden {
+                self.chunk.write(OpDup);
+                fails.push(self.codegen.emit_jump(self.chunk, OpBranch));
+                self.chunk.write(OpPop);
+                self.chunk.write(OpGetLocal);
+                self.chunk.write_operand(slot, "local variables")?;
+            }
+            self.visit_expr(rhs)?;
+            if idx + 1 < ops.len() {
+                let slot = *hidden.get_or_insert_with(|| self.codegen.hidden_local());
+                self.chunk.write(OpDup);
+                self.chunk.write(OpSetLocal);
+                self.chunk.write_operand(slot, "local variables")?;
+                self.chunk.write(OpPop);
+            }
+            self.chunk.write(rel_opcode(op));
+        }
+        for pos in fails {
+            self.codegen.patch_jump(self.chunk, pos)?;
+        }
+        Ok(())
+    }
+
+    fn visit_call(&mut self, callee: &Expr, args: &[Arg]) -> Result<(), String> {
+        self.codegen.emit_call(self.chunk, callee, args)
+    }
+
+    fn visit_assign(&mut self, pat: &Pattern, rhs: &Expr) -> Result<(), String> {
+        self.codegen.emit_assign(self.chunk, pat, rhs, false)
+    }
+
+    fn visit_set_field(&mut self, object: &Expr, name: &str, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(object)?;
+        self.visit_expr(rhs)?;
+        let idx = self.chunk.add(Value::Str(name.to_owned()));
+        self.chunk.write(OpSetField);
+        self.chunk.write_operand(idx, "constants")?;
+        Ok(())
+    }
+
+    fn visit_set_index(&mut self, object: &Expr, idx: usize, rhs: &Expr) -> Result<(), String> {
+        self.visit_expr(object)?;
+        self.visit_expr(rhs)?;
+        self.chunk.write(OpSetElem);
+        self.chunk.write_operand(idx, "tuple elements")?;
+        Ok(())
+    }
+
+    fn visit_op_assign(&mut self, op: &AssignOp, target: &Expr, rhs: &Expr) -> Result<(), String> {
+        self.codegen.emit_op_assign(self.chunk, op, target, rhs)
+    }
+
+    fn visit_match(&mut self, subject: &Expr, arms: &[MatchArm]) -> Result<(), String> {
+        self.codegen.emit_match(self.chunk, subject, arms)
+    }
+
+    fn visit_range(&mut self, start: &Expr, end: &Expr, inclusive: bool) -> Result<(), String> {
+        self.visit_expr(start)?;
+        self.visit_expr(end)?;
+        self.chunk.write(OpRange);
+        self.chunk.write_byte(u8::from(inclusive));
+        Ok(())
+    }
+
+    fn visit_for(&mut self, pat: &Pattern, iterable: &Expr, body: &[Item]) -> Result<(), String> {
+        self.codegen.emit_for(self.chunk, pat, iterable, body)
+    }
+
+    fn visit_propagate(&mut self, inner: &Expr) -> Result<(), String> {
+        if self.codegen.scopes.is_empty() {
+            return Err("'?' outside of a function".to_owned());
+        }
+        self.unary(inner, OpPropagate)
+    }
+
+    fn visit_try(&mut self, def: &TryCatch) -> Result<(), String> {
+        self.codegen.emit_try(self.chunk, def)
+    }
+
+    fn visit_yield(&mut self, value: &Expr) -> Result<(), String> {
+        if self.codegen.scopes.is_empty() {
+            return Err("'yield' outside of a function".to_owned());
+        }
+        self.unary(value, OpYield)?;
+        self.codegen.yields = true;
+        Ok(())
+    }
+}
+
+fn rel_opcode(op: &RelOp) -> OpCode {
+    match op {
+        RelOp::Lt => OpLt,
+        RelOp::Gt => OpGt,
+        RelOp::Le => OpLtEq,
+        RelOp::Ge => OpGtEq,
+        RelOp::Eq => OpEqual,
+        RelOp::Ne => OpNotEq,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tblang::parse::Parser;
+
+    use super::CodeGen;
+    use crate::bytecode::OpCode::*;
+    use crate::value::Value;
+
+    fn compile(src: &str) -> Result<(), String> {
+        let ast = Parser::new(src).ast()?;
+        CodeGen::new().compile(&ast).map(|_| ())
+    }
+
+    fn warnings(src: &str) -> Vec<String> {
+        let ast = Parser::new(src).ast().unwrap();
+        let mut codegen = CodeGen::new();
+        codegen.compile(&ast).unwrap();
+        codegen.warnings().to_vec()
+    }
+
+    #[test]
+    fn impl_missing_methods() {
+        let src = "trait T { fn a(self); fn b(self) }\ndef X\nimpl T for X { fn a(self) { 1 } }";
+        let err = compile(src).err().unwrap();
+        assert_eq!(err, "missing methods 'b' in impl of 'T' for 'X'");
+    }
+
+    #[test]
+    fn impl_default_methods() {
+        let src = "trait T { fn a(self); fn b(self) { 2 } }\ndef X\nimpl T for X { fn a(self) { 1 } }";
+        assert!(compile(src).is_ok());
+    }
+
+    #[test]
+    fn impl_unknown_method() {
+        let src = "trait T { fn a(self) }\ndef X\nimpl T for X { fn a(self) { 1 }; fn c(self) { 3 } }";
+        let err = compile(src).err().unwrap();
+        assert_eq!(err, "method 'c' is not a member of trait 'T'");
+    }
+
+    #[test]
+    fn impl_unknown_trait() {
+        let src = "def X\nimpl T for X { }";
+        let err = compile(src).err().unwrap();
+        assert_eq!(err, "unknown trait 'T'");
+    }
+
+    #[test]
+    fn match_non_exhaustive() {
+        let src = "enum E { A, B(x), C }\nmatch A { A => 1, B(1) => 2 }";
+        assert_eq!(warnings(src), vec!["non-exhaustive match on 'E', missing 'B', 'C'"]);
+    }
+
+    #[test]
+    fn match_exhaustive() {
+        let src = "enum E { A, B(x) }\nmatch A { A => 1, B(_) => 2 }\nmatch A { B(x) => 1, _ => 2 }";
+        assert!(warnings(src).is_empty());
+    }
+
+    #[test]
+    fn match_variant_arity() {
+        let src = "enum E { A, B(x) }\nmatch A { B(x, y) => 1 }";
+        let err = compile(src).err().unwrap();
+        assert_eq!(err, "pattern 'B' expects 1 fields but got 2");
+    }
+
+    #[test]
+    fn const_folded_into_pool() {
+        let ast = Parser::new("const LIMIT = 2 ^ 10\nLIMIT").ast().unwrap();
+        let chunk = CodeGen::new().compile(&ast).unwrap();
+        let code = (0..chunk.len()).map(|idx| chunk.code(idx)).collect::<Vec<_>>();
+        assert_eq!(code, vec![OpUnit as u8, OpPop as u8, OpConst as u8, 0]);
+        assert!(chunk.value(0).is_eq(&Value::Real(1024.0)));
+    }
+
+    fn disassemble(src: &str) -> String {
+        let ast = Parser::new(src).ast().unwrap();
+        CodeGen::new().compile(&ast).unwrap().disassemble("script")
+    }
+
+    #[test]
+    fn disassemble_constants_and_lines() {
+        let out = disassemble("a = \"x\"\n\nprintln(a)");
+        let expected = "== script ==
+0000    1 OpConst 0                ; \"x\"
+0002    | OpSet 1                  ; a
+0004    | OpPop
+0005    3 OpGet 2                  ; println
+0007    | OpGet 1                  ; a
+0009    | OpCall 1
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn disassemble_expression_lines() {
+        let out = disassemble("fn f(a) {\n    a +\n        1\n}");
+        let body = out.split("== fn f ==\n").nth(1).unwrap();
+        let expected = "0000    2 OpGetLocal 0
+0002    3 OpConst 0                ; 1
+0004    2 OpAdd
+0005    | OpRet
+";
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn disassemble_jumps_and_functions() {
+        let out = disassemble("fn f(n) { for i in 0..n { } }");
+        assert!(out.contains("== fn f ==\n"), "{}", out);
+        let next = out.lines().find(|line| line.contains("OpNext")).unwrap();
+        let target = next.rsplit(' ').next().unwrap();
+        assert!(out.contains(&format!("\n{} ", target)), "{}", out);
+        let back = out.lines().find(|line| line.contains("OpLoop")).unwrap();
+        assert!(back.ends_with(&format!("-> {}", &next[..4])), "{}", out);
+    }
+
+    #[test]
+    fn disassemble_trait_and_impl_methods() {
+        let out = disassemble("trait Shape { fn area(self); fn describe(self) { \"shape\" } }\ndef Square(side)\nimpl Shape for Square { fn area(self) { self.side * self.side } }");
+        assert!(out.contains("== fn Shape.describe ==\n"), "{}", out);
+        assert!(out.contains("== fn area ==\n"), "{}", out);
+    }
+
+    #[test]
+    fn too_many_constants() {
+        let src = (0..300).map(|n| format!("x = x + {}\n", n)).collect::<String>();
+        let err = compile(&src).err().unwrap();
+        assert_eq!(err, "too many constants, the limit is 256");
+    }
+
+    #[test]
+    fn const_bitwise_real() {
+        let err = compile("const X = 1.5 & 1").err().unwrap();
+        assert_eq!(err, "in const 'X': expected integers but got 1.5 and 1");
+    }
+
+    #[test]
+    fn const_runtime_value() {
+        let err = compile("a = 1\nconst X = a + 1").err().unwrap();
+        assert_eq!(err, "in const 'X': cannot use runtime value 'a' in a constant");
+    }
+
+    #[test]
+    fn const_assign() {
+        let err = compile("const X = 1\nX = 2").err().unwrap();
+        assert_eq!(err, "cannot assign to const 'X'");
+    }
+
+    #[test]
+    fn destructure_arity() {
+        let err = compile("(a, b) = (1, 2, 3)").err().unwrap();
+        assert_eq!(err, "cannot destructure a tuple of 3 elements into 2 patterns");
+        let err = compile("let (a, (b, c)) = (1, (2,))").err().unwrap();
+        assert_eq!(err, "cannot destructure a tuple of 1 elements into 2 patterns");
+    }
+
+    #[test]
+    fn destructure_refutable() {
+        let err = compile("let (a, 1) = (1, 2)").err().unwrap();
+        assert_eq!(err, "refutable pattern in assignment, use match instead");
+    }
+
+    #[test]
+    fn named_args_checked() {
+        let src = "fn f(a, b = 1) { a }\n";
+        let err = compile(&format!("{}f(1, c: 2)", src)).err().unwrap();
+        assert_eq!(err, "unknown parameter 'c' in call to 'f'");
+        let err = compile(&format!("{}f(1, a: 2)", src)).err().unwrap();
+        assert_eq!(err, "parameter 'a' given more than once in call to 'f'");
+        let err = compile(&format!("{}f(b: 2)", src)).err().unwrap();
+        assert_eq!(err, "missing argument 'a' in call to 'f'");
+        let err = compile("g(x: 1, x: 2)").err().unwrap();
+        assert_eq!(err, "duplicate argument 'x'");
+    }
+
+    #[test]
+    fn default_params() {
+        let err = compile("fn f(a = 1, b) { a }").err().unwrap();
+        assert_eq!(err, "parameter 'b' without a default follows parameters with defaults");
+        let err = compile("x = 1\nfn f(a = x) { a }").err().unwrap();
+        assert_eq!(err, "in default of parameter 'a': cannot use runtime value 'x' in a constant");
+    }
+
+    #[test]
+    fn variadic_params() {
+        let err = compile("fn f(...xs, y) { y }").err().unwrap();
+        assert_eq!(err, "variadic parameter 'xs' must be the last parameter");
+        let src = "fn f(a, ...rest) { a }\n";
+        assert!(compile(&format!("{}f(1, 2, 3)", src)).is_ok());
+        let err = compile(&format!("{}f()", src)).err().unwrap();
+        assert_eq!(err, "missing argument 'a' in call to 'f'");
+        let err = compile(&format!("{}f(1, 2, a: 3)", src)).err().unwrap();
+        assert_eq!(err, "parameter 'a' given more than once in call to 'f'");
+        let err = compile(&format!("{}f(1, rest: 2)", src)).err().unwrap();
+        assert_eq!(err, "unknown parameter 'rest' in call to 'f'");
+        let err = compile(&format!("{}f(...(1, 2), a: 3)", src)).err().unwrap();
+        assert_eq!(err, "cannot combine spread and named arguments");
+    }
+
+    #[test]
+    fn for_refutable() {
+        let err = compile("for 1 in 0..3 {}").err().unwrap();
+        assert_eq!(err, "refutable pattern in for loop, use match instead");
+        assert!(compile("for (_, (a, b)) in () { a }").is_ok());
+    }
+
+    #[test]
+    fn generators() {
+        let err = compile("yield 1").err().unwrap();
+        assert_eq!(err, "'yield' outside of a function");
+        let ast = Parser::new("fn outer() {\n fn inner() { yield 1 }\n inner\n}\nfn gen() { yield 2 }").ast().unwrap();
+        let mut codegen = CodeGen::new();
+        codegen.compile(&ast).unwrap();
+        assert!(!codegen.fns["outer"].generator);
+        assert!(codegen.fns["gen"].generator);
+    }
+
+    #[test]
+    fn try_catch() {
+        let err = compile("try { 1 }").err().unwrap();
+        assert_eq!(err, "expected 'catch' or 'finally' after try block");
+        let err = compile("try { 1 } catch 0 { 2 }").err().unwrap();
+        assert_eq!(err, "refutable pattern in catch, use match instead");
+        assert!(compile("try { 1 } catch _ { 2 } finally { 3 }").is_ok());
+    }
+
+    #[test]
+    fn propagate_outside_fn() {
+        let err = compile("x = Some(1)?").err().unwrap();
+        assert_eq!(err, "'?' outside of a function");
+        assert!(compile("fn f(x) { x? + 1 }").is_ok());
+    }
+
+    #[test]
+    fn alias_unknown_type() {
+        let err = compile("alias M = meters").err().unwrap();
+        assert_eq!(err, "unknown type 'meters'");
+        let err = compile("alias M = real\nfn f(x: N) { x }").err().unwrap();
+        assert_eq!(err, "unknown type 'N'");
     }
 }
@@ -1,4 +1,5 @@
 pub mod bytecode;
 pub mod codegen;
+pub mod module;
 pub mod value;
 pub mod vm;
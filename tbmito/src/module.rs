@@ -0,0 +1,181 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::value::Value;
+use crate::vm::MitoEnv;
+
+const MODULE_EXT: &str = "tb";
+
+/// The globals of a single source file or `mod` block. Functions compiled for a module keep a
+/// reference to it and resolve their globals here, so each module has its own namespace. Only
+/// the exported names are visible to importers.
+pub struct Module {
+    pub name: String,
+    pub env: RefCell<MitoEnv>,
+    exports: Vec<String>,
+    consts: RefCell<HashMap<String, Value>>,
+}
+
+impl Module {
+    pub fn new(name: &str, exports: Vec<String>) -> Self {
+        Self {
+            name: name.to_owned(),
+            env: RefCell::new(MitoEnv::with_builtins()),
+            exports,
+            consts: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn exports(&self) -> &[String] {
+        &self.exports
+    }
+
+    /// Records the value of an exported const, since consts are inlined by the compiler and
+    /// never bound in the module's environment.
+    pub fn set_const(&self, name: &str, value: Value) {
+        self.consts.borrow_mut().insert(name.to_owned(), value);
+    }
+
+    /// Looks up a public item, returning `None` for private or undefined names.
+    pub fn export(&self, name: &str) -> Option<Value> {
+        if !self.exports.iter().any(|export| export == name) {
+            return None;
+        }
+        if let Some(value) = self.consts.borrow().get(name) {
+            return Some(value.clone());
+        }
+        self.env.borrow().get(name)
+    }
+}
+
+// Module values hold functions, which hold their module, so printing the environment would
+// never terminate.
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Module({})", self.name)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(mod|{})", self.name)
+    }
+}
+
+/// Locates module files on the search path and keeps track of the modules that are loaded, so
+/// that each file is compiled once, and of those still loading, to detect import cycles.
+pub struct ModuleLoader {
+    paths: Vec<PathBuf>,
+    loaded: HashMap<PathBuf, Rc<Module>>,
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl Default for ModuleLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModuleLoader {
+    pub fn new() -> Self {
+        Self {
+            paths: Vec::new(),
+            loaded: HashMap::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    pub fn add_path(&mut self, dir: &Path) {
+        self.paths.push(dir.to_owned());
+    }
+
+    /// Finds the file for the longest prefix of a `use` path, so that `geometry.shapes` maps to
+    /// `geometry/shapes.tb`. Returns the file along with the length of the prefix, since the
+    /// rest of the path names an item within the module.
+    pub fn find(&self, path: &[String]) -> Option<(PathBuf, usize)> {
+        for len in (1..=path.len()).rev() {
+            let mut rel: PathBuf = path[..len].iter().collect();
+            rel.set_extension(MODULE_EXT);
+            for dir in &self.paths {
+                let file = dir.join(&rel);
+                if file.is_file() {
+                    let file = fs::canonicalize(&file).unwrap_or(file);
+                    return Some((file, len));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get(&self, file: &Path) -> Option<Rc<Module>> {
+        self.loaded.get(file).cloned()
+    }
+
+    /// Marks a module as loading, failing if it is already being loaded further up the chain of
+    /// imports.
+    pub fn begin(&mut self, file: &Path, name: &str) -> Result<(), String> {
+        if let Some(idx) = self.loading.iter().position(|(other, _)| other == file) {
+            let mut chain = self.loading[idx..].iter()
+                .map(|(_, name)| name.to_owned())
+                .collect::<Vec<_>>();
+            chain.push(name.to_owned());
+            return Err(format!("import cycle detected: {}", chain.join(" -> ")));
+        }
+        self.loading.push((file.to_owned(), name.to_owned()));
+        Ok(())
+    }
+
+    /// Ends loading the module most recently passed to `begin`, caching it unless loading
+    /// failed.
+    pub fn finish(&mut self, module: Option<Rc<Module>>) {
+        let (file, _) = self.loading.pop().unwrap();
+        if let Some(module) = module {
+            self.loaded.insert(file, module);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::ModuleLoader;
+
+    fn path(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn find_longest_prefix() {
+        let dir = std::env::temp_dir().join("tbmito-module-find");
+        fs::create_dir_all(dir.join("geometry")).unwrap();
+        fs::write(dir.join("geometry/shapes.tb"), "").unwrap();
+
+        let mut loader = ModuleLoader::new();
+        loader.add_path(&PathBuf::from("/nonexistent"));
+        loader.add_path(&dir);
+        let (file, len) = loader.find(&path(&["geometry", "shapes"])).unwrap();
+        assert!(file.ends_with("geometry/shapes.tb"));
+        assert_eq!(len, 2);
+        let (_, len) = loader.find(&path(&["geometry", "shapes", "area"])).unwrap();
+        assert_eq!(len, 2);
+        assert!(loader.find(&path(&["geometry"])).is_none());
+    }
+
+    #[test]
+    fn import_cycle() {
+        let mut loader = ModuleLoader::new();
+        let a = PathBuf::from("/a.tb");
+        let b = PathBuf::from("/b.tb");
+        loader.begin(&a, "a").unwrap();
+        loader.begin(&b, "b").unwrap();
+        let err = loader.begin(&a, "a").err().unwrap();
+        assert_eq!(err, "import cycle detected: a -> b -> a");
+    }
+}
@@ -1,7 +1,36 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt;
 use std::rc::Rc;
 
 use crate::bytecode::Chunk;
+use crate::bytecode::OpCode;
+use crate::bytecode::OpCode::*;
+use crate::module::Module;
+use crate::vm::Generator;
+
+thread_local! {
+    static OPTION_TYPE: Rc<TypeDef> = Rc::new(TypeDef::with_variants(
+        "Option",
+        vec![Variant::new("Some", vec!["value".to_owned()]), Variant::new("None", Vec::new())],
+        true,
+    ));
+    static RESULT_TYPE: Rc<TypeDef> = Rc::new(TypeDef::with_variants(
+        "Result",
+        vec![Variant::new("Ok", vec!["value".to_owned()]), Variant::new("Err", vec!["error".to_owned()])],
+        true,
+    ));
+}
+
+/// The built-in `Option` enum. It is shared by every environment, like `Result`, so that
+/// native functions can construct its values.
+pub fn option_type() -> Rc<TypeDef> {
+    OPTION_TYPE.with(Rc::clone)
+}
+
+pub fn result_type() -> Rc<TypeDef> {
+    RESULT_TYPE.with(Rc::clone)
+}
 
 #[derive(Debug, Clone)]
 pub enum Value {
@@ -10,11 +39,35 @@ pub enum Value {
     Int(i32),
     Real(f64),
     Str(String),
+    Tuple(Rc<Vec<Value>>),
+    Range(Range),
     Func(Rc<Function>),
     Native(Rc<FnNative>),
+    Type(Rc<TypeDef>),
+    Ctor(Rc<TypeDef>, usize),
+    Obj(Rc<Object>),
+    Trait(Rc<Trait>),
+    Module(Rc<Module>),
+    Gen(Rc<Generator>),
 }
 
 impl Value {
+    pub fn some(value: Value) -> Self {
+        Self::Obj(Rc::new(Object::new(option_type(), 0, vec![value])))
+    }
+
+    pub fn none() -> Self {
+        Self::Obj(Rc::new(Object::new(option_type(), 1, Vec::new())))
+    }
+
+    pub fn ok(value: Value) -> Self {
+        Self::Obj(Rc::new(Object::new(result_type(), 0, vec![value])))
+    }
+
+    pub fn err(error: Value) -> Self {
+        Self::Obj(Rc::new(Object::new(result_type(), 1, vec![error])))
+    }
+
     pub fn is_unit(&self) -> bool {
         matches!(self, Self::Unit)
     }
@@ -35,6 +88,14 @@ impl Value {
         matches!(self, Self::Str(..))
     }
 
+    pub fn is_tuple(&self) -> bool {
+        matches!(self, Self::Tuple(..))
+    }
+
+    pub fn is_range(&self) -> bool {
+        matches!(self, Self::Range(..))
+    }
+
     pub fn is_func(&self) -> bool {
         matches!(self, Self::Func(..))
     }
@@ -43,6 +104,30 @@ impl Value {
         matches!(self, Self::Native(..))
     }
 
+    pub fn is_type(&self) -> bool {
+        matches!(self, Self::Type(..))
+    }
+
+    pub fn is_ctor(&self) -> bool {
+        matches!(self, Self::Ctor(..))
+    }
+
+    pub fn is_obj(&self) -> bool {
+        matches!(self, Self::Obj(..))
+    }
+
+    pub fn is_trait(&self) -> bool {
+        matches!(self, Self::Trait(..))
+    }
+
+    pub fn is_module(&self) -> bool {
+        matches!(self, Self::Module(..))
+    }
+
+    pub fn is_gen(&self) -> bool {
+        matches!(self, Self::Gen(..))
+    }
+
     pub fn as_bool(self) -> bool {
         match self {
             Self::Bool(b) => b,
@@ -71,6 +156,20 @@ impl Value {
         }
     }
 
+    pub fn as_tuple(self) -> Rc<Vec<Value>> {
+        match self {
+            Self::Tuple(elems) => elems,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_range(self) -> Range {
+        match self {
+            Self::Range(range) => range,
+            _ => panic!(),
+        }
+    }
+
     pub fn as_func(self) -> Rc<Function> {
         match self {
             Self::Func(func) => func,
@@ -85,6 +184,49 @@ impl Value {
         }
     }
 
+    pub fn as_type(self) -> Rc<TypeDef> {
+        match self {
+            Self::Type(ty) => ty,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_obj(self) -> Rc<Object> {
+        match self {
+            Self::Obj(obj) => obj,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_trait(self) -> Rc<Trait> {
+        match self {
+            Self::Trait(tr) => tr,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_module(self) -> Rc<Module> {
+        match self {
+            Self::Module(module) => module,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_gen(self) -> Rc<Generator> {
+        match self {
+            Self::Gen(gen) => gen,
+            _ => panic!(),
+        }
+    }
+
+    pub fn to_float(&self) -> Option<f64> {
+        match self {
+            Self::Int(n) => Some(*n as f64),
+            Self::Real(n) => Some(*n),
+            _ => None,
+        }
+    }
+
     pub fn is_eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::Bool(b1), Self::Bool(b2)) => *b1 == *b2,
@@ -93,14 +235,122 @@ impl Value {
             (Self::Real(f), Self::Int(i)) => *f == (*i as f64),
             (Self::Real(f1), Self::Real(f2)) => *f1 == *f2,
             (Self::Str(s1), Self::Str(s2)) => *s1 == *s2,
+            (Self::Tuple(t1), Self::Tuple(t2)) => {
+                t1.len() == t2.len() && t1.iter().zip(t2.iter()).all(|(v1, v2)| v1.is_eq(v2))
+            }
+            (Self::Range(r1), Self::Range(r2)) => r1 == r2,
             (Self::Func(fn1), Self::Func(fn2)) => Rc::ptr_eq(fn1, fn2),
             (Self::Native(n1), Self::Native(n2)) => Rc::ptr_eq(n1, n2),
+            (Self::Type(t1), Self::Type(t2)) => Rc::ptr_eq(t1, t2),
+            (Self::Ctor(t1, v1), Self::Ctor(t2, v2)) => Rc::ptr_eq(t1, t2) && v1 == v2,
+            (Self::Obj(o1), Self::Obj(o2)) => o1.is_eq(o2),
+            (Self::Trait(t1), Self::Trait(t2)) => Rc::ptr_eq(t1, t2),
+            (Self::Module(m1), Self::Module(m2)) => Rc::ptr_eq(m1, m2),
+            (Self::Gen(g1), Self::Gen(g2)) => Rc::ptr_eq(g1, g2),
             (Self::Unit, Self::Unit) => true,
             _ => false,
         }
     }
 }
 
+/// Applies a built-in binary operator. Shared by the VM and compile-time constant folding so
+/// that both agree on the result.
+pub fn binary_op(opcode: OpCode, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    match opcode {
+        OpEqual => return Ok(Value::Bool(lhs.is_eq(rhs))),
+        OpNotEq => return Ok(Value::Bool(!lhs.is_eq(rhs))),
+        OpBitAnd | OpBitOr | OpBitXor | OpShl | OpShr | OpUShr => return bitwise_op(opcode, lhs, rhs),
+        OpDiv | OpRem if rhs.to_float() == Some(0.0) => return Err("division by zero".to_owned()),
+        _ => {}
+    }
+    if let (Value::Int(lhs), Value::Int(rhs)) = (lhs, rhs) {
+        let val = match opcode {
+            OpAdd => lhs.checked_add(*rhs),
+            OpSub => lhs.checked_sub(*rhs),
+            OpMul => lhs.checked_mul(*rhs),
+            OpRem => Some(lhs.wrapping_rem(*rhs)),
+            _ => None,
+        };
+        match val {
+            Some(val) => return Ok(Value::Int(val)),
+            None if matches!(opcode, OpAdd | OpSub | OpMul) => return Err("integer overflow".to_owned()),
+            None => {}
+        }
+    }
+    let (lhs, rhs) = match (lhs.to_float(), rhs.to_float()) {
+        (Some(lhs), Some(rhs)) => (lhs, rhs),
+        _ => return Err(format!("expected numbers but got {} and {}", lhs, rhs)),
+    };
+    let val = match opcode {
+        OpAdd => Value::Real(lhs + rhs),
+        OpSub => Value::Real(lhs - rhs),
+        OpMul => Value::Real(lhs * rhs),
+        OpDiv => Value::Real(lhs / rhs),
+        OpRem => Value::Real(lhs % rhs),
+        OpPow => Value::Real(lhs.powf(rhs)),
+        OpLt => Value::Bool(lhs < rhs),
+        OpGt => Value::Bool(lhs > rhs),
+        OpLtEq => Value::Bool(lhs <= rhs),
+        OpGtEq => Value::Bool(lhs >= rhs),
+        _ => unreachable!(),
+    };
+    Ok(val)
+}
+
+/// Builds a range from its bounds, which must be integers. Shared like `binary_op`.
+pub fn range_op(start: &Value, end: &Value, inclusive: bool) -> Result<Value, String> {
+    match (start, end) {
+        (Value::Int(start), Value::Int(end)) => Ok(Value::Range(Range::new(*start, *end, inclusive))),
+        _ => Err(format!("expected integers but got {} and {}", start, end)),
+    }
+}
+
+/// Bitwise operators and shifts only apply to integers, shifting by at most 31 bits.
+fn bitwise_op(opcode: OpCode, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    let (lhs, rhs) = match (lhs, rhs) {
+        (Value::Int(lhs), Value::Int(rhs)) => (*lhs, *rhs),
+        _ => return Err(format!("expected integers but got {} and {}", lhs, rhs)),
+    };
+    let shift = || match u32::try_from(rhs) {
+        Ok(shift) if shift < i32::BITS => Ok(shift),
+        _ => Err(format!("shift amount {} is out of range", rhs)),
+    };
+    let val = match opcode {
+        OpBitAnd => lhs & rhs,
+        OpBitOr => lhs | rhs,
+        OpBitXor => lhs ^ rhs,
+        OpShl => lhs << shift()?,
+        OpShr => lhs >> shift()?,
+        OpUShr => ((lhs as u32) >> shift()?) as i32,
+        _ => unreachable!(),
+    };
+    Ok(Value::Int(val))
+}
+
+/// Applies a built-in unary operator, see `binary_op`.
+pub fn unary_op(opcode: OpCode, val: &Value) -> Result<Value, String> {
+    if opcode == OpBitNot {
+        return match val {
+            Value::Int(int) => Ok(Value::Int(!int)),
+            _ => Err(format!("expected an integer but got {}", val)),
+        };
+    }
+    if let Value::Int(int) = val {
+        return match int.checked_neg() {
+            Some(neg) => Ok(Value::Int(neg)),
+            None => Err("integer overflow".to_owned()),
+        };
+    }
+    let num = match val.to_float() {
+        Some(num) => num,
+        None => return Err(format!("expected a number but got {}", val)),
+    };
+    match opcode {
+        OpNeg => Ok(Value::Real(-num)),
+        _ => unreachable!(),
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -109,43 +359,119 @@ impl fmt::Display for Value {
             Self::Int(n) => write!(f, "{}", n),
             Self::Real(n) => write!(f, "{}", n),
             Self::Str(s) => write!(f, "{}", s),
+            Self::Tuple(elems) => {
+                write!(f, "(")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Self::Range(range) => fmt::Display::fmt(range, f),
             Self::Func(func) => fmt::Display::fmt(func, f),
             Self::Native(native) => fmt::Display::fmt(native, f),
+            Self::Type(ty) => fmt::Display::fmt(ty, f),
+            Self::Ctor(ty, tag) => write!(f, "(ctor|{})", ty.variants[*tag].name),
+            Self::Obj(obj) => fmt::Display::fmt(obj, f),
+            Self::Trait(tr) => fmt::Display::fmt(tr, f),
+            Self::Module(module) => fmt::Display::fmt(module, f),
+            Self::Gen(gen) => fmt::Display::fmt(gen, f),
         }
     }
 }
 
+/// A range of integers, which is iterated without building the list of its elements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: i32,
+    pub end: i32,
+    pub inclusive: bool,
+}
+
+impl Range {
+    pub fn new(start: i32, end: i32, inclusive: bool) -> Self {
+        Self { start, end, inclusive }
+    }
+
+    pub fn contains(&self, n: i32) -> bool {
+        n >= self.start && (n < self.end || self.inclusive && n == self.end)
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.inclusive {
+            true => write!(f, "{}..={}", self.start, self.end),
+            false => write!(f, "{}..{}", self.start, self.end),
+        }
+    }
+}
+
+/// A compiled function. Functions from an imported module resolve globals in that module,
+/// while the rest use the environment the VM was run with. The defaults are the values of the
+/// trailing parameters that may be left out. A variadic function receives its remaining
+/// arguments as a tuple in its last parameter. Calling a generator, a function that contains
+/// `yield`, returns a generator object instead of running the body.
 #[derive(Debug)]
 pub struct Function {
     pub name: String,
     pub arity: usize,
+    pub params: Vec<String>,
+    pub defaults: Vec<Value>,
+    pub variadic: bool,
+    pub generator: bool,
     pub chunk: Chunk,
+    pub module: Option<Rc<Module>>,
 }
 
 impl Function {
-    pub fn new(name: &str, arity: usize, chunk: Chunk) -> Self {
+    pub fn new(name: &str, params: Vec<String>, chunk: Chunk) -> Self {
         Self {
             name: name.to_owned(),
-            arity,
+            arity: params.len(),
+            params,
+            defaults: Vec::new(),
+            variadic: false,
+            generator: false,
             chunk,
+            module: None,
         }
     }
 
     pub fn with_chunk(chunk: Chunk) -> Self {
-        Self {
-            name: String::new(),
-            arity: 0,
-            chunk,
-        }
+        Self::new("", Vec::new(), chunk)
     }
 
     pub fn empty() -> Self {
-        Self {
-            name: String::new(),
-            arity: 0,
-            chunk: Chunk::new(),
+        Self::with_chunk(Chunk::new())
+    }
+
+    /// The parameters that take a single argument, which excludes a variadic parameter.
+    pub fn fixed_params(&self) -> &[String] {
+        let count = self.arity - self.variadic as usize;
+        &self.params[..count]
+    }
+
+    /// The number of arguments without a default value.
+    pub fn min_arity(&self) -> usize {
+        self.fixed_params().len() - self.defaults.len()
+    }
+
+    pub fn max_arity(&self) -> Option<usize> {
+        match self.variadic {
+            true => None,
+            false => Some(self.arity),
         }
     }
+
+    pub fn arity_error(&self, count: usize) -> String {
+        arity_error(self.min_arity(), self.max_arity(), count)
+    }
 }
 
 impl fmt::Display for Function {
@@ -154,25 +480,59 @@ impl fmt::Display for Function {
     }
 }
 
-pub type NativeFnPtr = fn(Vec<Value>) -> Value;
+/// A native function returns its result, or a value to raise as an error.
+pub type NativeFnPtr = fn(Vec<Value>) -> Result<Value, Value>;
 
+/// Formats the error for a call with a number of arguments outside of the accepted range.
+pub fn arity_error(min_arity: usize, max_arity: Option<usize>, count: usize) -> String {
+    match max_arity {
+        Some(max_arity) if max_arity == min_arity => format!("expected {} arguments but got {}", max_arity, count),
+        Some(max_arity) => format!("expected {} to {} arguments but got {}", min_arity, max_arity, count),
+        None => format!("expected at least {} arguments but got {}", min_arity, count),
+    }
+}
+
+/// A function implemented in Rust, accepting between `min_arity` and `max_arity` arguments,
+/// where no maximum makes it variadic. Parameter names are optional and only needed to accept
+/// named arguments; a variadic native names its leading parameters. Natives that print or format their arguments set `formats_args` to have
+/// objects converted with their `Display` implementation first.
 #[derive(Debug)]
 pub struct FnNative {
     pub name: String,
-    pub arity: usize,
+    pub min_arity: usize,
+    pub max_arity: Option<usize>,
+    pub params: Vec<String>,
+    pub formats_args: bool,
     pub function: NativeFnPtr,
 }
 
 impl FnNative {
     pub fn new(name: &str, arity: usize, function: NativeFnPtr) -> Self {
+        Self::with_arity(name, arity, Some(arity), function)
+    }
+
+    pub fn with_arity(name: &str, min_arity: usize, max_arity: Option<usize>, function: NativeFnPtr) -> Self {
         Self {
             name: name.to_owned(),
-            arity,
+            min_arity,
+            max_arity,
+            params: Vec::new(),
+            formats_args: false,
             function,
         }
     }
 
-    pub fn invoke(&self, args: Vec<Value>) -> Value {
+    pub fn with_params(name: &str, params: &[&str], function: NativeFnPtr) -> Self {
+        let mut native = Self::new(name, params.len(), function);
+        native.params = params.iter().map(|param| param.to_string()).collect();
+        native
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min_arity && self.max_arity.is_none_or(|max_arity| count <= max_arity)
+    }
+
+    pub fn invoke(&self, args: Vec<Value>) -> Result<Value, Value> {
         (self.function)(args)
     }
 }
@@ -182,3 +542,194 @@ impl fmt::Display for FnNative {
         write!(f, "(fn-native|{})", self.name)
     }
 }
+
+/// A user-defined type made up of one or more tagged variants. A record type from `def` is a
+/// single variant sharing the type's name. The vtable maps method names to functions and is
+/// filled in at runtime as `impl` blocks are executed.
+#[derive(Debug)]
+pub struct TypeDef {
+    pub name: String,
+    pub variants: Vec<Variant>,
+    pub is_enum: bool,
+    vtable: RefCell<HashMap<String, Value>>,
+    traits: RefCell<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl Variant {
+    pub fn new(name: &str, fields: Vec<String>) -> Self {
+        Self {
+            name: name.to_owned(),
+            fields,
+        }
+    }
+}
+
+impl TypeDef {
+    pub fn new(name: &str, fields: Vec<String>) -> Self {
+        let variant = Variant::new(name, fields);
+        Self::with_variants(name, vec![variant], false)
+    }
+
+    pub fn with_variants(name: &str, variants: Vec<Variant>, is_enum: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            variants,
+            is_enum,
+            vtable: RefCell::new(HashMap::new()),
+            traits: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn variant_tag(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|variant| variant.name == name)
+    }
+
+    pub fn method(&self, name: &str) -> Option<Value> {
+        self.vtable.borrow().get(name).cloned()
+    }
+
+    /// The methods of the type so far, ordered by name.
+    pub fn methods(&self) -> Vec<(String, Value)> {
+        let mut methods = self.vtable.borrow().iter().map(|(name, method)| (name.to_owned(), method.clone())).collect::<Vec<_>>();
+        methods.sort_by(|(name, _), (other, _)| name.cmp(other));
+        methods
+    }
+
+    pub fn set_method(&self, name: &str, method: Value) {
+        self.vtable.borrow_mut().insert(name.to_owned(), method);
+    }
+
+    pub fn implements(&self, trait_name: &str) -> bool {
+        self.traits.borrow().iter().any(|name| name == trait_name)
+    }
+
+    /// Records that this type conforms to the trait, filling in any default methods that the
+    /// impl block did not override.
+    pub fn implement(&self, tr: &Trait) {
+        let mut vtable = self.vtable.borrow_mut();
+        for (name, method) in &tr.defaults {
+            if !vtable.contains_key(name) {
+                vtable.insert(name.to_owned(), method.clone());
+            }
+        }
+        if !self.implements(&tr.name) {
+            self.traits.borrow_mut().push(tr.name.to_owned());
+        }
+    }
+
+    /// Looks up a method that is only reachable when the type implements the given trait, used
+    /// for the well-known operator traits.
+    pub fn trait_method(&self, trait_name: &str, name: &str) -> Option<Value> {
+        if self.implements(trait_name) {
+            self.method(name)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for TypeDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(def|{})", self.name)
+    }
+}
+
+/// An instance of a user-defined type, tagged with the variant it was constructed from. Fields
+/// are mutable and shared by every reference to the object.
+#[derive(Debug)]
+pub struct Object {
+    pub ty: Rc<TypeDef>,
+    pub tag: usize,
+    fields: RefCell<Vec<Value>>,
+}
+
+impl Object {
+    pub fn new(ty: Rc<TypeDef>, tag: usize, fields: Vec<Value>) -> Self {
+        Self {
+            ty,
+            tag,
+            fields: RefCell::new(fields),
+        }
+    }
+
+    pub fn variant(&self) -> &Variant {
+        &self.ty.variants[self.tag]
+    }
+
+    pub fn field(&self, name: &str) -> Option<Value> {
+        let idx = self.variant().fields.iter().position(|field| field == name)?;
+        self.elem(idx)
+    }
+
+    pub fn set_field(&self, name: &str, value: Value) -> bool {
+        match self.variant().fields.iter().position(|field| field == name) {
+            Some(idx) => self.set_elem(idx, value),
+            None => false,
+        }
+    }
+
+    pub fn elem(&self, idx: usize) -> Option<Value> {
+        self.fields.borrow().get(idx).cloned()
+    }
+
+    pub fn set_elem(&self, idx: usize, value: Value) -> bool {
+        match self.fields.borrow_mut().get_mut(idx) {
+            Some(field) => {
+                *field = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.ty, &other.ty)
+            && self.tag == other.tag
+            && self.fields.borrow().iter().zip(other.fields.borrow().iter()).all(|(f1, f2)| f1.is_eq(f2))
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(obj|{})", self.variant().name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Trait {
+    pub name: String,
+    pub methods: Vec<String>,
+    pub defaults: HashMap<String, Value>,
+}
+
+impl Trait {
+    pub fn new(name: &str, methods: Vec<String>, defaults: HashMap<String, Value>) -> Self {
+        Self {
+            name: name.to_owned(),
+            methods,
+            defaults,
+        }
+    }
+
+    /// A trait without default methods, used for the built-in operator traits.
+    pub fn required(name: &str, methods: &[&str]) -> Self {
+        let methods = methods.iter().map(|name| name.to_string()).collect();
+        Self::new(name, methods, HashMap::new())
+    }
+
+    pub fn has_method(&self, name: &str) -> bool {
+        self.methods.iter().any(|method| method == name)
+    }
+}
+
+impl fmt::Display for Trait {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(trait|{})", self.name)
+    }
+}
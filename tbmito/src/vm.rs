@@ -1,25 +1,53 @@
 use std::collections::HashMap;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::Path;
 use std::rc::Rc;
 
+use tblang::ast::Ast;
+use tblang::ast::ItemKind;
 use tblang::parse::Parser;
 
+use crate::bytecode::Chunk;
 use crate::bytecode::OpCode;
 use crate::bytecode::OpCode::*;
 use crate::codegen::CodeGen;
+use crate::module::Module;
+use crate::module::ModuleLoader;
 use crate::value::FnNative;
 use crate::value::Function;
+use crate::value::Object;
+use crate::value::Trait;
+use crate::value::arity_error;
+use crate::value::binary_op;
+use crate::value::option_type;
+use crate::value::result_type;
+use crate::value::range_op;
+use crate::value::unary_op;
+use crate::value::TypeDef;
 use crate::value::Value;
 
+const PRELUDE: &str = include_str!("prelude.tb");
+
 pub enum MitoRes {
     Ok(Value),
     CompileErr(String),
     RuntimeErr(String),
 }
 
+#[derive(Clone)]
 pub struct MitoEnv {
     vals: HashMap<String, Value>,
 }
 
+impl Default for MitoEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MitoEnv {
     pub fn new() -> Self {
         Self {
@@ -29,8 +57,22 @@ impl MitoEnv {
 
     pub fn with_builtins() -> Self {
         let mut env = Self::new();
-        let native = FnNative::new("println", 1, native_println);
+        let mut native = FnNative::with_arity("println", 0, None, native_println);
+        native.params = vec!["value".to_owned()];
+        native.formats_args = true;
         env.set("println", Value::Native(Rc::new(native)));
+        let native = FnNative::with_params("error", &["value"], native_error);
+        env.set("error", Value::Native(Rc::new(native)));
+        let native = FnNative::with_params("parse_int", &["text"], native_parse_int);
+        env.set("parse_int", Value::Native(Rc::new(native)));
+        env.set_enum(option_type());
+        env.set_enum(result_type());
+        env.set_trait(Trait::required("Add", &["add"]));
+        env.set_trait(Trait::required("Sub", &["sub"]));
+        env.set_trait(Trait::required("Mul", &["mul"]));
+        env.set_trait(Trait::required("Div", &["div"]));
+        env.set_trait(Trait::required("Eq", &["eq"]));
+        env.set_trait(Trait::required("Display", &["fmt"]));
         env
     }
 
@@ -39,47 +81,233 @@ impl MitoEnv {
     }
 
     pub fn get(&self, name: &str) -> Option<Value> {
-        return self.vals.get(name).map(|val| val.clone());
+        self.vals.get(name).cloned()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.vals.values()
+    }
+
+    /// Binds a built-in enum along with its variants, as an `enum` item would.
+    fn set_enum(&mut self, ty: Rc<TypeDef>) {
+        for (tag, variant) in ty.variants.iter().enumerate() {
+            let value = if variant.fields.is_empty() {
+                Value::Obj(Rc::new(Object::new(ty.clone(), tag, Vec::new())))
+            } else {
+                Value::Ctor(ty.clone(), tag)
+            };
+            self.set(&variant.name, value);
+        }
+        self.set(&ty.name.to_owned(), Value::Type(ty));
+    }
+
+    fn set_trait(&mut self, tr: Trait) {
+        let name = tr.name.to_owned();
+        self.set(&name, Value::Trait(Rc::new(tr)));
     }
 }
 
+/// Whether loading a module runs its top level, or only compiles it to find what it exports.
+#[derive(Clone, Copy, PartialEq)]
+enum Link {
+    Run,
+    Resolve,
+}
+
+/// The VM runs one fiber at a time on `stack` and `frames`. Resuming a generator swaps its
+/// fiber in, and a `yield` ends the run so that the caller's fiber is swapped back. Errors are
+/// passed around as messages, with the value given to `error()` kept aside in `raised` until
+/// the error is caught.
 pub struct MitoVM {
     stack: Vec<Value>,
     frames: Vec<CallFrame>,
+    yielded: Option<Value>,
+    raised: Option<Value>,
+    error_ty: Rc<TypeDef>,
+    codegen: CodeGen,
+    loader: ModuleLoader,
+}
+
+impl Default for MitoVM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MitoVM {
     pub fn new() -> Self {
-        Self {
+        let fields = ["message", "value", "trace"].iter().map(|field| field.to_string()).collect();
+        let mut vm = Self {
             stack: Vec::new(),
             frames: Vec::new(),
+            yielded: None,
+            raised: None,
+            error_ty: Rc::new(TypeDef::new("Error", fields)),
+            codegen: CodeGen::new(),
+            loader: ModuleLoader::new(),
+        };
+        vm.load_prelude();
+        vm
+    }
+
+    /// Runs the prelude, which implements the methods of the built-in enums in script code.
+    fn load_prelude(&mut self) {
+        let ast = Parser::new(PRELUDE).ast().unwrap();
+        if self.load(&mut MitoEnv::new(), "prelude", &ast, Link::Run).is_err() {
+            panic!("failed to load the prelude");
         }
     }
 
+    /// Adds a directory to search for the files of imported modules, in the order added.
+    pub fn add_path(&mut self, dir: &Path) {
+        self.loader.add_path(dir);
+    }
+
     pub fn run(&mut self, env: &mut MitoEnv, source: &str) -> MitoRes {
-        let ast = match Parser::new(source).ast() {
-            Ok(ls) => ls,
-            Err(msg) => return MitoRes::CompileErr(msg),
-        };
-        let chunk = match CodeGen::new().compile(&ast) {
-            Ok(ch) => ch,
-            Err(msg) => return MitoRes::CompileErr(msg),
+        let chunk = match self.compile(env, source) {
+            Ok(chunk) => chunk,
+            Err(res) => return res,
         };
         let func = Function::with_chunk(chunk);
         self.execute(env, Rc::new(func))
     }
 
-    fn execute(&mut self, env: &mut MitoEnv, func: Rc<Function>) -> MitoRes {
-        self.frames.push(CallFrame::new(func));
-        while !self.frames.is_empty() {
-            let frame = self.frames.last().unwrap();
-            if !frame.is_eof() {
-                self.dispatch(env);
-            } else {
-                self.frames.pop();
+    /// Compiles source to be run next, loading the modules it imports and binding them in `env`.
+    /// Loading a module runs its top level.
+    pub fn compile(&mut self, env: &mut MitoEnv, source: &str) -> Result<Chunk, MitoRes> {
+        let ast = parse(source)?;
+        for (name, val) in self.link(env, &ast, Link::Run)? {
+            env.set(&name, val);
+        }
+        if env.get("Error").is_none() {
+            env.set("Error", Value::Type(self.error_ty.clone()));
+        }
+        generate(&mut self.codegen, env, &ast)
+    }
+
+    /// Compiles source for inspection only. Nothing is run and later runs are unaffected: the
+    /// code is generated with copies of the environment and code generator, and modules that are
+    /// not loaded yet are compiled just to resolve what they export.
+    pub fn emit(&mut self, env: &MitoEnv, source: &str) -> Result<Chunk, MitoRes> {
+        let ast = parse(source)?;
+        let mut env = env.clone();
+        for (name, val) in self.link(&mut env, &ast, Link::Resolve)? {
+            env.set(&name, val);
+        }
+        if env.get("Error").is_none() {
+            env.set("Error", Value::Type(self.error_ty.clone()));
+        }
+        generate(&mut self.codegen.clone(), &env, &ast)
+    }
+
+    /// Loads the modules imported by the top-level `use` and `mod` items, returning the values
+    /// they bind. This happens before compiling so that imported types and traits are known.
+    fn link(&mut self, env: &mut MitoEnv, ast: &Ast, mode: Link) -> Result<Vec<(String, Value)>, MitoRes> {
+        let mut imports = Vec::new();
+        for item in &ast.nodes {
+            match &item.kind {
+                ItemKind::Use(def) => imports.push(self.import(env, &def.path, mode)?),
+                ItemKind::Mod(def) => {
+                    let module = self.load(env, &def.name, &def.body, mode)?;
+                    imports.push((def.name.to_owned(), Value::Module(module)));
+                }
+                _ => {}
             }
         }
-        let res = if !self.stack.is_empty() {
+        Ok(imports)
+    }
+
+    /// Resolves a `use` path. Modules that were only resolved are not cached, so that a later
+    /// run still loads them.
+    fn import(&mut self, env: &mut MitoEnv, path: &[String], mode: Link) -> Result<(String, Value), MitoRes> {
+        let (file, len) = match self.loader.find(path) {
+            Some(found) => found,
+            None => return Err(MitoRes::CompileErr(format!("cannot find module '{}'", path.join(".")))),
+        };
+        let name = path[..len].join(".");
+        let module = match self.loader.get(&file) {
+            Some(module) => module,
+            None => {
+                self.loader.begin(&file, &name).map_err(MitoRes::CompileErr)?;
+                let res = self.load_file(env, &file, &name, mode);
+                self.loader.finish(res.as_ref().ok().filter(|_| mode == Link::Run).cloned());
+                res?
+            }
+        };
+        match &path[len..] {
+            [] => Ok((path[len - 1].to_owned(), Value::Module(module))),
+            [item] => match module.export(item) {
+                Some(val) => Ok((item.to_owned(), val)),
+                None => Err(MitoRes::CompileErr(format!("'{}' is not a public item of module '{}'", item, name))),
+            },
+            _ => Err(MitoRes::CompileErr(format!("cannot find module '{}'", path[..=len].join(".")))),
+        }
+    }
+
+    fn load_file(&mut self, env: &mut MitoEnv, file: &Path, name: &str, mode: Link) -> Result<Rc<Module>, MitoRes> {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => return Err(MitoRes::CompileErr(format!("cannot read module '{}': {}", name, e))),
+        };
+        let ast = match Parser::new(&source).ast() {
+            Ok(ast) => ast,
+            Err(msg) => return Err(MitoRes::CompileErr(format!("in module '{}': {}", name, msg))),
+        };
+        self.load(env, name, &ast, mode)
+    }
+
+    /// Compiles a module with its own code generator and runs its top level in its own
+    /// environment. When only resolving, its exports are bound to what the compiler knows of
+    /// them instead, which is unit for plain variables.
+    fn load(&mut self, env: &mut MitoEnv, name: &str, ast: &Ast, mode: Link) -> Result<Rc<Module>, MitoRes> {
+        let module = Rc::new(Module::new(name, ast.exports.clone()));
+        module.env.borrow_mut().set("Error", Value::Type(self.error_ty.clone()));
+        for (name, val) in self.link(env, ast, mode)? {
+            module.env.borrow_mut().set(&name, val);
+        }
+
+        let mut codegen = CodeGen::with_module(module.clone());
+        for val in module.env.borrow().values() {
+            codegen.declare(val);
+        }
+        let chunk = match codegen.compile(ast) {
+            Ok(ch) => ch,
+            Err(msg) => return Err(MitoRes::CompileErr(format!("in module '{}': {}", name, msg))),
+        };
+        for msg in codegen.warnings() {
+            eprintln!("[W] in module '{}': {}", name, msg);
+        }
+        for export in module.exports() {
+            if let Some(val) = codegen.const_value(export) {
+                module.set_const(export, val);
+            }
+        }
+        if mode == Link::Resolve {
+            for export in module.exports() {
+                let val = codegen.definition(export).unwrap_or(Value::Unit);
+                module.env.borrow_mut().set(export, val);
+            }
+            return Ok(module);
+        }
+
+        let mut func = Function::with_chunk(chunk);
+        func.module = Some(module.clone());
+        match self.execute(env, Rc::new(func)) {
+            MitoRes::RuntimeErr(msg) => Err(MitoRes::RuntimeErr(format!("in module '{}': {}", name, msg))),
+            _ => Ok(module),
+        }
+    }
+
+    fn execute(&mut self, env: &mut MitoEnv, func: Rc<Function>) -> MitoRes {
+        let base = self.stack.len();
+        self.frames.push(CallFrame::new(func, base));
+        if let Err(msg) = self.run_frames(env, 0) {
+            self.frames.clear();
+            self.stack.truncate(base);
+            self.raised = None;
+            return MitoRes::RuntimeErr(msg);
+        }
+        let res = if self.stack.len() > base {
             self.stack.pop().unwrap()
         } else {
             Value::Unit
@@ -87,10 +315,64 @@ impl MitoVM {
         MitoRes::Ok(res)
     }
 
-    fn dispatch(&mut self, env: &mut MitoEnv) {
+    fn run_frames(&mut self, env: &mut MitoEnv, depth: usize) -> Result<(), String> {
+        while self.frames.len() > depth && self.yielded.is_none() {
+            let frame = self.frames.last().unwrap();
+            if frame.is_eof() {
+                self.frames.pop();
+            } else if let Err(msg) = self.dispatch(env) {
+                self.unwind(depth, msg)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes control to the innermost `try` handler among the frames of this run, dropping the
+    /// frames above it and restoring the stack height it was installed at, with the error value
+    /// pushed in its place. The trace lists every function on the call stack, innermost first.
+    /// Without a handler the error is passed on to the caller.
+    fn unwind(&mut self, depth: usize, msg: String) -> Result<(), String> {
+        let idx = match self.frames[depth..].iter().rposition(|frame| !frame.handlers.is_empty()) {
+            Some(pos) => depth + pos,
+            None => return Err(msg),
+        };
+        let trace = self.frames.iter()
+            .rev()
+            .map(|frame| Value::Str(frame.name().to_owned()))
+            .collect();
+        self.frames.truncate(idx + 1);
+        let frame = self.frames.last_mut().unwrap();
+        let handler = frame.handlers.pop().unwrap();
+        frame.ip = handler.ip;
+        self.stack.truncate(handler.height);
+
+        let err = match self.raised.take() {
+            Some(Value::Obj(obj)) if Rc::ptr_eq(&obj.ty, &self.error_ty) => Value::Obj(obj),
+            raised => {
+                let fields = vec![Value::Str(msg), raised.unwrap_or(Value::Unit), Value::Tuple(Rc::new(trace))];
+                Value::Obj(Rc::new(Object::new(self.error_ty.clone(), 0, fields)))
+            }
+        };
+        self.stack.push(err);
+        Ok(())
+    }
+
+    /// Raises a value as an error, returning the message it is reported with. Raising a caught
+    /// error again keeps its message and trace.
+    fn raise(&mut self, val: Value) -> String {
+        let msg = match &val {
+            Value::Str(msg) => msg.to_owned(),
+            Value::Obj(obj) if Rc::ptr_eq(&obj.ty, &self.error_ty) => obj.field("message").unwrap().to_string(),
+            val => val.to_string(),
+        };
+        self.raised = Some(val);
+        msg
+    }
+
+    fn dispatch(&mut self, env: &mut MitoEnv) -> Result<(), String> {
         let frame = self.frames.last_mut().unwrap();
         match frame.read_opcode() {
-            OpNop => return,
+            OpNop => return Ok(()),
             OpUnit => self.stack.push(Value::Unit),
             OpTrue => self.stack.push(Value::Bool(true)),
             OpFalse => self.stack.push(Value::Bool(false)),
@@ -100,157 +382,733 @@ impl MitoVM {
                 self.stack.push(val);
             }
             OpAdd => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs + rhs;
-                self.stack.push(Value::Real(val));
+                if self.overload(env, "Add", "add")? {
+                    return Ok(());
+                }
+                self.binary(OpAdd)?;
             }
             OpSub => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs - rhs;
-                self.stack.push(Value::Real(val));
+                if self.overload(env, "Sub", "sub")? {
+                    return Ok(());
+                }
+                self.binary(OpSub)?;
             }
             OpMul => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs * rhs;
-                self.stack.push(Value::Real(val));
+                if self.overload(env, "Mul", "mul")? {
+                    return Ok(());
+                }
+                self.binary(OpMul)?;
             }
             OpDiv => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs / rhs;
-                self.stack.push(Value::Real(val));
-            }
-            OpRem => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs % rhs;
-                self.stack.push(Value::Real(val));
-            }
-            OpPow => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs.powf(rhs);
-                self.stack.push(Value::Real(val));
+                if self.overload(env, "Div", "div")? {
+                    return Ok(());
+                }
+                self.binary(OpDiv)?;
             }
+            OpRem => self.binary(OpRem)?,
+            OpPow => self.binary(OpPow)?,
             OpNeg => {
-                let val = self.pop_as_float();
-                self.stack.push(Value::Real(-val));
-            }
-            OpLt => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs < rhs;
-                self.stack.push(Value::Bool(val));
-            }
-            OpGt => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs > rhs;
-                self.stack.push(Value::Bool(val));
-            }
-            OpLtEq => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs <= rhs;
-                self.stack.push(Value::Bool(val));
-            }
-            OpGtEq => {
-                let rhs = self.pop_as_float();
-                let lhs = self.pop_as_float();
-                let val = lhs >= rhs;
-                self.stack.push(Value::Bool(val));
+                let val = self.stack.pop().unwrap();
+                self.stack.push(unary_op(OpNeg, &val)?);
+            }
+            OpBitAnd => self.binary(OpBitAnd)?,
+            OpBitOr => self.binary(OpBitOr)?,
+            OpBitXor => self.binary(OpBitXor)?,
+            OpBitNot => {
+                let val = self.stack.pop().unwrap();
+                self.stack.push(unary_op(OpBitNot, &val)?);
             }
+            OpShl => self.binary(OpShl)?,
+            OpShr => self.binary(OpShr)?,
+            OpUShr => self.binary(OpUShr)?,
+            OpLt => self.binary(OpLt)?,
+            OpGt => self.binary(OpGt)?,
+            OpLtEq => self.binary(OpLtEq)?,
+            OpGtEq => self.binary(OpGtEq)?,
             OpEqual => {
-                let rhs = self.stack.pop().unwrap();
-                let lhs = self.stack.pop().unwrap();
-                let is_eq = lhs.is_eq(&rhs);
+                let is_eq = self.pop_is_eq(env)?;
                 self.stack.push(Value::Bool(is_eq));
             }
             OpNotEq => {
-                let rhs = self.stack.pop().unwrap();
-                let lhs = self.stack.pop().unwrap();
-                let is_eq = lhs.is_eq(&rhs);
+                let is_eq = self.pop_is_eq(env)?;
                 self.stack.push(Value::Bool(!is_eq));
             }
-            OpLoop => todo!(),
-            OpJump => todo!(),
-            OpBranch => todo!(),
+            OpLoop => {
+                let offset = frame.read_u16();
+                frame.ip -= offset;
+            }
+            OpJump => {
+                let offset = frame.read_u16();
+                frame.ip += offset;
+            }
+            OpBranch => {
+                let offset = frame.read_u16();
+                match self.stack.pop().unwrap() {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => frame.ip += offset,
+                    val => return Err(format!("expected a bool condition but got {}", val)),
+                }
+            }
+            OpJumpIfUnit => {
+                let offset = frame.read_u16();
+                if self.stack.last().unwrap().is_unit() {
+                    frame.ip += offset;
+                }
+            }
+            OpJumpNotUnit => {
+                let offset = frame.read_u16();
+                if !self.stack.last().unwrap().is_unit() {
+                    frame.ip += offset;
+                }
+            }
             OpGet => {
                 let idx = frame.read_usize();
                 let name = frame.value(idx).as_str();
-                let val = env.get(&name).unwrap();
-                self.stack.push(val);
+                let val = match &frame.func.module {
+                    Some(module) => module.env.borrow().get(&name),
+                    None => env.get(&name),
+                };
+                match val {
+                    Some(val) => self.stack.push(val),
+                    None => return Err(format!("undefined name '{}'", name)),
+                }
             }
             OpSet => {
                 let idx = frame.read_usize();
                 let name = frame.value(idx).as_str();
                 let val = self.stack.pop().unwrap();
                 self.stack.push(Value::Unit);
-                env.set(&name, val);
+                match &frame.func.module {
+                    Some(module) => module.env.borrow_mut().set(&name, val),
+                    None => env.set(&name, val),
+                }
+            }
+            OpGetLocal => {
+                let slot = frame.read_usize();
+                let val = frame.local(slot);
+                self.stack.push(val);
+            }
+            OpSetLocal => {
+                let slot = frame.read_usize();
+                let val = self.stack.pop().unwrap();
+                frame.set_local(slot, val);
+                self.stack.push(Value::Unit);
+            }
+            OpField => {
+                let idx = frame.read_usize();
+                let name = frame.value(idx).as_str();
+                let val = self.stack.pop().unwrap();
+                let obj = match val {
+                    Value::Obj(obj) => obj,
+                    Value::Module(module) => {
+                        self.stack.push(module_export(&module, &name)?);
+                        return Ok(());
+                    }
+                    _ => return Err(format!("cannot access field '{}' of {}", name, val)),
+                };
+                match obj.field(&name) {
+                    Some(field) => self.stack.push(field),
+                    None => return Err(format!("undefined field '{}' for '{}'", name, obj.ty.name)),
+                }
+            }
+            OpSetField => {
+                let idx = frame.read_usize();
+                let name = frame.value(idx).as_str();
+                let val = self.stack.pop().unwrap();
+                match self.stack.pop().unwrap() {
+                    Value::Obj(obj) => {
+                        if !obj.set_field(&name, val) {
+                            return Err(format!("undefined field '{}' for '{}'", name, obj.ty.name));
+                        }
+                    }
+                    obj => return Err(format!("cannot assign to field '{}' of {}", name, obj)),
+                }
+                self.stack.push(Value::Unit);
             }
             OpCall => {
                 let count = frame.read_usize();
                 let idx = self.stack.len() - count - 1;
                 let callee = self.stack[idx].clone();
-                self.dispatch_call(callee, count);
+                self.dispatch_call(env, callee, count)?;
+            }
+            OpCallNamed => {
+                let count = frame.read_usize();
+                let names = read_names(frame);
+                let idx = self.stack.len() - count - 1;
+                let callee = self.stack[idx].clone();
+                let count = self.resolve_named(&callee, count, &names)?;
+                self.dispatch_call(env, callee, count)?;
+            }
+            OpCallSpread => {
+                let count = frame.read_usize();
+                let positions = frame.read_usize();
+                let positions = frame.value(positions);
+                let count = self.expand_spread(count, &positions)?;
+                let idx = self.stack.len() - count - 1;
+                let callee = self.stack[idx].clone();
+                self.dispatch_call(env, callee, count)?;
+            }
+            OpInvoke => {
+                let idx = frame.read_usize();
+                let count = frame.read_usize();
+                let name = frame.value(idx).as_str();
+                self.invoke_method(env, &name, count, &[])?;
+            }
+            OpInvokeNamed => {
+                let idx = frame.read_usize();
+                let count = frame.read_usize();
+                let names = read_names(frame);
+                let name = frame.value(idx).as_str();
+                self.invoke_method(env, &name, count, &names)?;
+            }
+            OpInvokeSpread => {
+                let idx = frame.read_usize();
+                let count = frame.read_usize();
+                let positions = frame.read_usize();
+                let name = frame.value(idx).as_str();
+                let positions = frame.value(positions);
+                let count = self.expand_spread(count, &positions)?;
+                self.invoke_method(env, &name, count, &[])?;
+            }
+            OpRet => {
+                let res = self.stack.pop().unwrap();
+                self.ret(res);
+            }
+            OpPropagate => {
+                let val = self.stack.pop().unwrap();
+                match propagate(&val)? {
+                    Some(inner) => self.stack.push(inner),
+                    None => self.ret(val),
+                }
+            }
+            OpMethod => {
+                let idx = frame.read_usize();
+                let name = frame.value(idx).as_str();
+                let method = self.stack.pop().unwrap();
+                match self.stack.last().unwrap() {
+                    Value::Type(ty) => ty.set_method(&name, method),
+                    val => return Err(format!("cannot implement methods for {}", val)),
+                }
+            }
+            OpImpl => {
+                let tr = match self.stack.pop().unwrap() {
+                    Value::Trait(tr) => tr,
+                    val => return Err(format!("expected a trait but got {}", val)),
+                };
+                match self.stack.last().unwrap() {
+                    Value::Type(ty) => ty.implement(&tr),
+                    val => return Err(format!("cannot implement '{}' for {}", tr.name, val)),
+                }
+            }
+            OpElem => {
+                let idx = frame.read_usize();
+                let val = self.stack.pop().unwrap();
+                let elem = match &val {
+                    Value::Obj(obj) => obj.elem(idx),
+                    Value::Tuple(elems) => elems.get(idx).cloned(),
+                    _ => None,
+                };
+                match elem {
+                    Some(elem) => self.stack.push(elem),
+                    None => return Err(format!("cannot get element {} of {}", idx, val)),
+                }
+            }
+            OpSetElem => {
+                let idx = frame.read_usize();
+                let val = self.stack.pop().unwrap();
+                match self.stack.pop().unwrap() {
+                    Value::Obj(obj) => {
+                        if !obj.set_elem(idx, val) {
+                            return Err(format!("cannot assign to element {} of {}", idx, obj));
+                        }
+                    }
+                    tuple @ Value::Tuple(_) => {
+                        return Err(format!("cannot assign to element {} of {}, tuples are immutable", idx, tuple));
+                    }
+                    obj => return Err(format!("cannot assign to element {} of {}", idx, obj)),
+                }
+                self.stack.push(Value::Unit);
+            }
+            OpIsVariant => {
+                let idx = frame.read_usize();
+                let name = frame.value(idx).as_str();
+                let is_variant = match self.stack.pop().unwrap() {
+                    Value::Obj(obj) => obj.variant().name == name,
+                    _ => false,
+                };
+                self.stack.push(Value::Bool(is_variant));
+            }
+            OpTuple => {
+                let count = frame.read_usize();
+                let elems = self.stack.split_off(self.stack.len() - count);
+                self.stack.push(Value::Tuple(Rc::new(elems)));
+            }
+            OpUnpack => {
+                let count = frame.read_usize();
+                let elems = match self.stack.pop().unwrap() {
+                    Value::Tuple(elems) if elems.len() == count => elems,
+                    val => return Err(format!("cannot destructure {} into {} elements", val, count)),
+                };
+                self.stack.extend(elems.iter().rev().cloned());
+            }
+            OpIsTuple => {
+                let count = frame.read_usize();
+                let is_tuple = match self.stack.pop().unwrap() {
+                    Value::Tuple(elems) => elems.len() == count,
+                    _ => false,
+                };
+                self.stack.push(Value::Bool(is_tuple));
+            }
+            OpRange => {
+                let inclusive = frame.read_byte() != 0;
+                let end = self.stack.pop().unwrap();
+                let start = self.stack.pop().unwrap();
+                self.stack.push(range_op(&start, &end, inclusive)?);
+            }
+            OpIter => {
+                let cursor = match self.stack.last().unwrap() {
+                    Value::Range(range) => Value::Int(range.start),
+                    Value::Tuple(_) | Value::Str(_) => Value::Int(0),
+                    Value::Obj(obj) if obj.ty.method("next").is_some() => Value::Int(0),
+                    Value::Gen(_) => Value::Int(0),
+                    val => return Err(format!("cannot iterate over {}", val)),
+                };
+                self.stack.push(cursor);
+            }
+            OpNext => {
+                let offset = frame.read_u16();
+                match self.iter_next(env)? {
+                    Some(val) => self.stack.push(val),
+                    None => self.frames.last_mut().unwrap().ip += offset,
+                }
+            }
+            OpYield => {
+                let val = self.stack.pop().unwrap();
+                self.stack.push(Value::Unit);
+                self.yielded = Some(val);
+            }
+            OpTry => {
+                let offset = frame.read_u16();
+                let handler = Handler { ip: frame.ip + offset, height: self.stack.len() };
+                frame.handlers.push(handler);
+            }
+            OpEndTry => {
+                frame.handlers.pop();
+            }
+            OpRaise => {
+                let val = self.stack.pop().unwrap();
+                return Err(self.raise(val));
+            }
+            OpDup => {
+                let val = self.stack.last().unwrap().clone();
+                self.stack.push(val);
             }
             OpPop => {
                 self.stack.pop();
             }
         }
+        Ok(())
+    }
+
+    fn ret(&mut self, res: Value) {
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base);
+        self.stack.push(res);
     }
 
-    fn dispatch_call(&mut self, callee: Value, count: usize) {
+    fn dispatch_call(&mut self, env: &mut MitoEnv, callee: Value, count: usize) -> Result<(), String> {
         match callee {
             Value::Func(_) => self.call_func(callee.as_func(), count),
-            Value::Native(_) => self.call_native(callee.as_native(), count),
-            _ => panic!(),
+            Value::Native(_) => self.call_native(env, callee.as_native(), count),
+            Value::Type(ty) if ty.is_enum => Err(format!("cannot construct enum '{}' directly", ty.name)),
+            Value::Type(ty) => self.call_ctor(ty, 0, count),
+            Value::Ctor(ty, tag) => self.call_ctor(ty, tag, count),
+            _ => Err(format!("can only call functions but got {}", callee)),
         }
     }
 
-    fn call_func(&mut self, func: Rc<Function>, count: usize) {
-        if func.arity != count {
-            eprintln!("expected {} arguments but got {}", func.arity, count);
-            return;
+    /// Sets up a frame for a script function. Defaults fill in the parameters left out, and the
+    /// arguments past the fixed parameters of a variadic function are collected into a tuple.
+    /// The frame of a generator starts out suspended in a fiber of its own.
+    fn call_func(&mut self, func: Rc<Function>, count: usize) -> Result<(), String> {
+        let fixed = func.fixed_params().len();
+        if count < func.min_arity() || (!func.variadic && count > func.arity) {
+            return Err(func.arity_error(count));
         }
+        if count > fixed {
+            let rest = self.stack.split_off(self.stack.len() - (count - fixed));
+            self.stack.push(Value::Tuple(Rc::new(rest)));
+        } else {
+            self.stack.extend_from_slice(&func.defaults[count - func.min_arity()..]);
+            if func.variadic {
+                self.stack.push(Value::Tuple(Rc::new(Vec::new())));
+            }
+        }
+        let count = func.arity;
         // TODO: check call stack overflow
-        let frame = CallFrame::new(func);
+        let base = self.stack.len() - count - 1;
+        let locals = self.stack.split_off(base + 1);
+        if func.generator {
+            let callee = self.stack.pop().unwrap();
+            let mut frame = CallFrame::new(func.clone(), 0);
+            frame.locals = locals;
+            let fiber = Fiber { frames: vec![frame], stack: vec![callee] };
+            self.stack.push(Value::Gen(Rc::new(Generator::new(&func.name, fiber))));
+            return Ok(());
+        }
+        let mut frame = CallFrame::new(func, base);
+        frame.locals = locals;
         self.frames.push(frame);
+        Ok(())
     }
 
-    fn call_native(&mut self, native: Rc<FnNative>, count: usize) {
-        if native.arity != count {
-            eprintln!("expected {} arguments but got {}", native.arity, count);
-            return;
+    /// Runs a generator on its own fiber until it yields, returning the value it yields, or
+    /// `None` once its function has returned.
+    fn resume(&mut self, env: &mut MitoEnv, gen: &Generator) -> Result<Option<Value>, String> {
+        let fiber = match gen.state.replace(GenState::Running) {
+            GenState::Suspended(fiber) => fiber,
+            GenState::Running => return Err(format!("generator '{}' is already running", gen.name)),
+            GenState::Done => {
+                gen.state.replace(GenState::Done);
+                return Ok(None);
+            }
+        };
+        let frames = mem::replace(&mut self.frames, fiber.frames);
+        let stack = mem::replace(&mut self.stack, fiber.stack);
+        let res = self.run_frames(env, 0);
+        let fiber = Fiber {
+            frames: mem::replace(&mut self.frames, frames),
+            stack: mem::replace(&mut self.stack, stack),
+        };
+        let yielded = self.yielded.take();
+        match (res, yielded) {
+            (Ok(_), Some(val)) => {
+                gen.state.replace(GenState::Suspended(fiber));
+                Ok(Some(val))
+            }
+            (res, _) => {
+                gen.state.replace(GenState::Done);
+                res.map(|_| None)
+            }
+        }
+    }
+
+    fn call_native(&mut self, env: &mut MitoEnv, native: Rc<FnNative>, count: usize) -> Result<(), String> {
+        if !native.accepts(count) {
+            return Err(arity_error(native.min_arity, native.max_arity, count));
         }
         let idx = self.stack.len() - count;
-        let args = self.stack.split_off(idx);
-        let result = native.invoke(args);
+        let mut args = self.stack.split_off(idx);
+        if native.formats_args {
+            for arg in args.iter_mut() {
+                *arg = self.display(env, arg.clone())?;
+            }
+        }
+        let result = match native.invoke(args) {
+            Ok(result) => result,
+            Err(val) => return Err(self.raise(val)),
+        };
         self.stack.pop();
         self.stack.push(result);
+        Ok(())
     }
 
-    fn pop_as_float(&mut self) -> f64 {
-        let val = self.stack.pop().unwrap();
-        if val.is_int() {
-            val.as_int() as f64
-        } else {
-            val.as_real()
+    fn call_ctor(&mut self, ty: Rc<TypeDef>, tag: usize, count: usize) -> Result<(), String> {
+        let arity = ty.variants[tag].fields.len();
+        if arity != count {
+            return Err(format!("expected {} fields but got {}", arity, count));
+        }
+        let idx = self.stack.len() - count;
+        let fields = self.stack.split_off(idx);
+        self.stack.pop();
+        self.stack.push(Value::Obj(Rc::new(Object::new(ty, tag, fields))));
+        Ok(())
+    }
+
+    /// Reorders arguments whose trailing `names.len()` values were passed by name into
+    /// positional order, filling in defaults for parameters left out. Only the fixed parameters
+    /// of a variadic function can be named. Returns the new number of arguments on the stack.
+    fn resolve_named(&mut self, callee: &Value, count: usize, names: &[String]) -> Result<usize, String> {
+        if names.is_empty() {
+            return Ok(count);
+        }
+        let (name, params, defaults) = match callee {
+            Value::Func(func) => (&func.name, func.fixed_params(), func.defaults.as_slice()),
+            Value::Native(native) if !native.params.is_empty() => (&native.name, &native.params[..], &[][..]),
+            Value::Native(native) => return Err(format!("'{}' does not take named arguments", native.name)),
+            Value::Type(ty) if !ty.is_enum => (&ty.name, &ty.variants[0].fields[..], &[][..]),
+            Value::Ctor(ty, tag) => (&ty.variants[*tag].name, &ty.variants[*tag].fields[..], &[][..]),
+            _ => return Err(format!("can only call functions but got {}", callee)),
+        };
+
+        let variadic = match callee {
+            Value::Func(func) => func.variadic,
+            Value::Native(native) => native.max_arity.is_none(),
+            _ => false,
+        };
+        let positional = count - names.len();
+        if positional > params.len() && !variadic {
+            return Err(format!("expected {} arguments but got {}", params.len(), count));
         }
+        let named = self.stack.split_off(self.stack.len() - names.len());
+        let mut rest: Vec<Option<Value>> = vec![None; params.len().saturating_sub(positional)];
+        for (param, val) in names.iter().zip(named) {
+            let idx = match params.iter().position(|other| other == param) {
+                Some(idx) => idx,
+                None => return Err(format!("unknown parameter '{}' in call to '{}'", param, name)),
+            };
+            if idx < positional || rest[idx - positional].is_some() {
+                return Err(format!("parameter '{}' given more than once in call to '{}'", param, name));
+            }
+            rest[idx - positional] = Some(val);
+        }
+
+        let min_arity = params.len() - defaults.len();
+        for (idx, val) in rest.into_iter().enumerate() {
+            let idx = idx + positional;
+            let val = match val {
+                Some(val) => val,
+                None if idx >= min_arity => defaults[idx - min_arity].clone(),
+                None => return Err(format!("missing argument '{}' in call to '{}'", params[idx], name)),
+            };
+            self.stack.push(val);
+        }
+        Ok(params.len())
+    }
+
+    /// Advances the loop whose iterable and cursor are on top of the stack, returning the next
+    /// element or `None` once it is exhausted. A unit cursor marks a range that ran up to the
+    /// largest integer, and objects are iterated by calling their `next()` method until it
+    /// returns unit.
+    fn iter_next(&mut self, env: &mut MitoEnv) -> Result<Option<Value>, String> {
+        let len = self.stack.len();
+        if let Value::Gen(gen) = &self.stack[len - 2] {
+            let gen = gen.clone();
+            return self.resume(env, &gen);
+        }
+        if let Value::Obj(obj) = &self.stack[len - 2] {
+            let obj = obj.clone();
+            let method = obj.ty.method("next").unwrap();
+            let next = self.invoke(env, method, vec![Value::Obj(obj)])?;
+            return Ok(if next.is_unit() { None } else { Some(next) });
+        }
+
+        let (next, cursor) = match (&self.stack[len - 2], &self.stack[len - 1]) {
+            (_, Value::Unit) => return Ok(None),
+            (Value::Range(range), Value::Int(idx)) if range.contains(*idx) => {
+                let cursor = idx.checked_add(1).map_or(Value::Unit, Value::Int);
+                (Value::Int(*idx), cursor)
+            }
+            (Value::Tuple(elems), Value::Int(idx)) if (*idx as usize) < elems.len() => {
+                (elems[*idx as usize].clone(), Value::Int(idx + 1))
+            }
+            (Value::Str(s), Value::Int(idx)) => match s[*idx as usize..].chars().next() {
+                Some(c) => (Value::Str(c.to_string()), Value::Int(idx + c.len_utf8() as i32)),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.stack[len - 1] = cursor;
+        Ok(Some(next))
+    }
+
+    /// Replaces the spread arguments among the top `count` values, at the positions listed in a
+    /// tuple constant, with the elements of the tuples they hold. Returns the new number of
+    /// arguments on the stack.
+    fn expand_spread(&mut self, count: usize, positions: &Value) -> Result<usize, String> {
+        let positions = match positions {
+            Value::Tuple(positions) => positions,
+            _ => unreachable!(),
+        };
+        let args = self.stack.split_off(self.stack.len() - count);
+        let mut count = 0;
+        for (pos, arg) in args.into_iter().enumerate() {
+            if !positions.iter().any(|other| matches!(other, Value::Int(idx) if *idx as usize == pos)) {
+                self.stack.push(arg);
+                count += 1;
+                continue;
+            }
+            match arg {
+                Value::Tuple(elems) => {
+                    self.stack.extend(elems.iter().cloned());
+                    count += elems.len();
+                }
+                val => return Err(format!("cannot spread {}", val)),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Dispatches a method call through the vtable of the receiver's type. The method is slotted
+    /// in below the receiver so the call proceeds like a function call with `self` as the first
+    /// argument.
+    fn invoke_method(&mut self, env: &mut MitoEnv, name: &str, count: usize, names: &[String]) -> Result<(), String> {
+        let idx = self.stack.len() - count - 1;
+        if let Value::Gen(gen) = &self.stack[idx] {
+            let gen = gen.clone();
+            if name != "next" {
+                return Err(format!("undefined method '{}' for {}", name, gen));
+            }
+            if count != 0 {
+                return Err(format!("expected 0 arguments but got {}", count));
+            }
+            let val = self.resume(env, &gen)?.unwrap_or(Value::Unit);
+            self.stack.truncate(idx);
+            self.stack.push(val);
+            return Ok(());
+        }
+        if let Value::Module(module) = &self.stack[idx] {
+            let callee = module_export(module, name)?;
+            self.stack[idx] = callee.clone();
+            let count = self.resolve_named(&callee, count, names)?;
+            return self.dispatch_call(env, callee, count);
+        }
+        let method = match &self.stack[idx] {
+            Value::Obj(obj) => match obj.ty.method(name) {
+                Some(method) => method,
+                None => return Err(format!("undefined method '{}' for '{}'", name, obj.ty.name)),
+            },
+            val => return Err(format!("cannot call method '{}' on {}", name, val)),
+        };
+        self.stack.insert(idx, method.clone());
+        let count = self.resolve_named(&method, count + 1, names)?;
+        self.dispatch_call(env, method, count)
+    }
+
+    /// Calls a value and runs it to completion, so that the VM itself can call back into script
+    /// code for operator overloads.
+    fn invoke(&mut self, env: &mut MitoEnv, callee: Value, args: Vec<Value>) -> Result<Value, String> {
+        let depth = self.frames.len();
+        let count = args.len();
+        self.stack.push(callee.clone());
+        self.stack.extend(args);
+        self.dispatch_call(env, callee, count)?;
+        self.run_frames(env, depth)?;
+        Ok(self.stack.pop().unwrap())
+    }
+
+    /// Applies a binary operator through its well-known trait when the left operand is an
+    /// object implementing it. Returns false if the operator should use the built-in behavior.
+    fn overload(&mut self, env: &mut MitoEnv, trait_name: &str, name: &str) -> Result<bool, String> {
+        let method = match &self.stack[self.stack.len() - 2] {
+            Value::Obj(obj) => obj.ty.trait_method(trait_name, name),
+            _ => None,
+        };
+        let method = match method {
+            Some(method) => method,
+            None => return Ok(false),
+        };
+        let rhs = self.stack.pop().unwrap();
+        let lhs = self.stack.pop().unwrap();
+        let res = self.invoke(env, method, vec![lhs, rhs])?;
+        self.stack.push(res);
+        Ok(true)
+    }
+
+    fn pop_is_eq(&mut self, env: &mut MitoEnv) -> Result<bool, String> {
+        if self.overload(env, "Eq", "eq")? {
+            let res = self.stack.pop().unwrap();
+            return Ok(res.is_bool() && res.as_bool());
+        }
+        let rhs = self.stack.pop().unwrap();
+        let lhs = self.stack.pop().unwrap();
+        Ok(lhs.is_eq(&rhs))
+    }
+
+    /// Converts objects implementing `Display` into their formatted value, since native
+    /// functions cannot call back into script code.
+    fn display(&mut self, env: &mut MitoEnv, val: Value) -> Result<Value, String> {
+        let method = match &val {
+            Value::Obj(obj) => obj.ty.trait_method("Display", "fmt"),
+            _ => None,
+        };
+        match method {
+            Some(method) => self.invoke(env, method, vec![val]),
+            None => Ok(val),
+        }
+    }
+
+    fn binary(&mut self, opcode: OpCode) -> Result<(), String> {
+        let rhs = self.stack.pop().unwrap();
+        let lhs = self.stack.pop().unwrap();
+        let val = binary_op(opcode, &lhs, &rhs)?;
+        self.stack.push(val);
+        Ok(())
+    }
+}
+
+/// The call frames and operand stack of a suspended generator.
+struct Fiber {
+    frames: Vec<CallFrame>,
+    stack: Vec<Value>,
+}
+
+enum GenState {
+    Suspended(Fiber),
+    Running,
+    Done,
+}
+
+/// The state of a call to a generator function. Each call to `next()` resumes it up to its
+/// next `yield`, and returns unit once the function has returned.
+pub struct Generator {
+    pub name: String,
+    state: RefCell<GenState>,
+}
+
+impl Generator {
+    fn new(name: &str, fiber: Fiber) -> Self {
+        Self {
+            name: name.to_owned(),
+            state: RefCell::new(GenState::Suspended(fiber)),
+        }
+    }
+}
+
+// The suspended frames may hold the generator itself, so it is never printed in full.
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Generator({})", self.name)
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(gen|{})", self.name)
     }
 }
 
+/// An active `try`, which resumes at `ip` with the stack cut back to `height`.
+struct Handler {
+    ip: usize,
+    height: usize,
+}
+
 struct CallFrame {
     func: Rc<Function>,
     ip: usize,
+    base: usize,
+    locals: Vec<Value>,
+    handlers: Vec<Handler>,
 }
 
 impl CallFrame {
-    fn new(func: Rc<Function>) -> Self {
-        Self { func, ip: 0 }
+    fn new(func: Rc<Function>, base: usize) -> Self {
+        Self {
+            func,
+            ip: 0,
+            base,
+            locals: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+
+    /// The name of the function shown in the trace of an error.
+    fn name(&self) -> &str {
+        match self.func.name.as_str() {
+            "" => "<main>",
+            name => name,
+        }
     }
 
     fn is_eof(&self) -> bool {
@@ -261,6 +1119,17 @@ impl CallFrame {
         self.func.chunk.value(idx)
     }
 
+    fn local(&self, slot: usize) -> Value {
+        self.locals.get(slot).cloned().unwrap_or(Value::Unit)
+    }
+
+    fn set_local(&mut self, slot: usize, val: Value) {
+        if slot >= self.locals.len() {
+            self.locals.resize(slot + 1, Value::Unit);
+        }
+        self.locals[slot] = val;
+    }
+
     fn read_byte(&mut self) -> u8 {
         let byte = self.func.chunk.code(self.ip);
         self.ip += 1;
@@ -274,9 +1143,72 @@ impl CallFrame {
     fn read_usize(&mut self) -> usize {
         self.read_byte() as usize
     }
+
+    fn read_u16(&mut self) -> usize {
+        let hi = self.read_byte() as usize;
+        let lo = self.read_byte() as usize;
+        (hi << 8) | lo
+    }
+}
+
+fn read_names(frame: &mut CallFrame) -> Vec<String> {
+    let idx = frame.read_usize();
+    frame.value(idx).as_tuple().iter().map(|name| name.clone().as_str()).collect()
+}
+
+fn module_export(module: &Module, name: &str) -> Result<Value, String> {
+    match module.export(name) {
+        Some(val) => Ok(val),
+        None => Err(format!("'{}' is not a public item of module '{}'", name, module.name)),
+    }
 }
 
-fn native_println(args: Vec<Value>) -> Value {
-    println!("{}", args[0]);
-    Value::Unit
+/// Splits a value for the `?` operator into the value wrapped by `Ok` or `Some`, or `None` if
+/// it is an `Err` or `None` to return as is.
+fn propagate(val: &Value) -> Result<Option<Value>, String> {
+    let obj = match val {
+        Value::Obj(obj) if Rc::ptr_eq(&obj.ty, &option_type()) || Rc::ptr_eq(&obj.ty, &result_type()) => obj,
+        _ => return Err(format!("cannot use '?' on {}, expected an Option or a Result", val)),
+    };
+    match obj.tag {
+        0 => Ok(obj.elem(0)),
+        _ => Ok(None),
+    }
+}
+
+fn parse(source: &str) -> Result<Ast, MitoRes> {
+    Parser::new(source).ast().map_err(MitoRes::CompileErr)
+}
+
+/// Generates the code for a script, with the types and traits bound in `env` declared first.
+fn generate(codegen: &mut CodeGen, env: &MitoEnv, ast: &Ast) -> Result<Chunk, MitoRes> {
+    for val in env.values() {
+        codegen.declare(val);
+    }
+    let chunk = codegen.compile(ast).map_err(MitoRes::CompileErr)?;
+    for msg in codegen.warnings() {
+        eprintln!("[W] {}", msg);
+    }
+    Ok(chunk)
+}
+
+fn native_println(args: Vec<Value>) -> Result<Value, Value> {
+    let line = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    println!("{}", line.join(" "));
+    Ok(Value::Unit)
+}
+
+fn native_error(mut args: Vec<Value>) -> Result<Value, Value> {
+    Err(args.pop().unwrap())
+}
+
+fn native_parse_int(args: Vec<Value>) -> Result<Value, Value> {
+    let text = match &args[0] {
+        Value::Str(text) => text,
+        val => return Err(Value::Str(format!("expected a string but got {}", val))),
+    };
+    match text.trim().parse::<i32>() {
+        Ok(int) => Ok(Value::ok(Value::Int(int))),
+        Err(_) => Ok(Value::err(Value::Str(format!("invalid integer '{}'", text)))),
+    }
 }
@@ -1,6 +1,13 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
 use rustyline as rl;
 use rustyline::error::ReadlineError;
 
+use tblang::lex::Lexer;
+use tblang::parse::Parser;
+use tblang::token::TKind;
 use tbmito::value::Value;
 use tbmito::vm::MitoEnv;
 use tbmito::vm::MitoRes;
@@ -13,12 +20,44 @@ const MULTI_START: &str = "\\;";
 const MULTI_END: &str = ";;";
 const RES_VAR: &str = "_";
 
-pub fn start() -> Result<(), String> {
-    Repl::new().start()
+/// Starts an interactive session. Modules are searched for in the given directories, in order.
+pub fn start(paths: &[PathBuf]) -> Result<(), String> {
+    Repl::with_paths(paths).start()
+}
+
+/// Runs a script. Modules are searched for in the given directories, in order.
+pub fn run(source: &str, paths: &[PathBuf]) -> Result<Value, String> {
+    Repl::with_paths(paths).run(source)
+}
+
+/// Prints a stage of compiling a script instead of running it. Modules are searched for in the
+/// given directories, in order.
+pub fn emit(source: &str, stage: Emit, paths: &[PathBuf]) -> Result<String, String> {
+    Repl::with_paths(paths).emit(source, stage)
+}
+
+/// A stage of compilation that can be printed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Emit {
+    /// The tokens of the source, one per line.
+    Tokens,
+    /// The syntax tree of each item.
+    Ast,
+    /// The bytecode of the compiled script.
+    Bytecode,
 }
 
-pub fn run(source: &str) -> Result<Value, String> {
-    Repl::new().run(source)
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "bytecode" => Ok(Emit::Bytecode),
+            _ => Err("expected one of tokens, ast, bytecode".to_owned()),
+        }
+    }
 }
 
 struct Repl {
@@ -40,6 +79,14 @@ impl Repl {
         }
     }
 
+    pub fn with_paths(paths: &[PathBuf]) -> Self {
+        let mut repl = Self::new();
+        for path in paths {
+            repl.vm.add_path(path);
+        }
+        repl
+    }
+
     pub fn start(&mut self) -> Result<(), String> {
         println!("{}", INTRO);
         loop {
@@ -51,6 +98,14 @@ impl Repl {
             if src.is_empty() {
                 continue;
             }
+            // `:tokens`, `:ast` and `:bytecode` print the input instead of running it.
+            if let Some((stage, src)) = src.strip_prefix(':').and_then(|cmd| cmd.split_once(' ')) {
+                match stage.parse().and_then(|stage| self.emit(src, stage)) {
+                    Ok(out) => print!("{}", out),
+                    Err(msg) => eprintln!("[E] {}", msg),
+                }
+                continue;
+            }
             match self.run(&src) {
                 Ok(val) => {
                     println!("{}", val);
@@ -69,6 +124,35 @@ impl Repl {
         }
     }
 
+    pub fn emit(&mut self, source: &str, stage: Emit) -> Result<String, String> {
+        let mut out = String::new();
+        match stage {
+            Emit::Tokens => {
+                let mut lexer = Lexer::new(source);
+                loop {
+                    let token = lexer.scan();
+                    writeln!(out, "{:?}", token).unwrap();
+                    if token.kind == TKind::TkEof {
+                        break;
+                    }
+                }
+            }
+            Emit::Ast => {
+                let ast = Parser::new(source).ast().map_err(|msg| format!("compile error: {}", msg))?;
+                for item in &ast.nodes {
+                    writeln!(out, "{:#?}", item).unwrap();
+                }
+            }
+            Emit::Bytecode => match self.vm.emit(&self.env, source) {
+                Ok(chunk) => out = chunk.disassemble("script"),
+                Err(MitoRes::CompileErr(msg)) => return Err(format!("compile error: {}", msg)),
+                Err(MitoRes::RuntimeErr(msg)) => return Err(format!("runtime error: {}", msg)),
+                Err(MitoRes::Ok(_)) => unreachable!(),
+            },
+        }
+        Ok(out)
+    }
+
     fn read_input(&mut self) -> Result<Option<String>, String> {
         let line = self.read_line(PROMPT_LINE)?;
         let line = match line {
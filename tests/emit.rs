@@ -0,0 +1,57 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+const EXE_PATH: &str = "./target/debug/table";
+
+fn emit(stage: &str, src: &str) -> (String, bool) {
+    emit_file(&format!("table-emit-{}", stage), stage, src)
+}
+
+fn emit_file(name: &str, stage: &str, src: &str) -> (String, bool) {
+    let path = env::temp_dir().join(format!("{}.tb", name));
+    fs::write(&path, src).unwrap();
+    let out = Command::new(EXE_PATH)
+        .arg("run")
+        .arg(format!("--emit={}", stage))
+        .arg(&path)
+        .output()
+        .expect("failed to run script");
+    (String::from_utf8(out.stdout).unwrap(), out.status.success())
+}
+
+#[test]
+fn tokens() {
+    let (out, _) = emit("tokens", "x = 1\nprintln(x)\n");
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "1:1 TkIdent \"x\"");
+    assert_eq!(lines[2], "1:5 TkInt \"1\"");
+    assert_eq!(lines.last().unwrap(), &"3:1 TkEof \"\"");
+}
+
+#[test]
+fn ast() {
+    let (out, _) = emit("ast", "x < 2\n");
+    assert!(out.starts_with("Item {\n    id: #0,\n    span: 1:1 0..5,\n    kind: Expr(\n"));
+    assert!(out.contains("kind: Relation(\n"));
+    assert!(out.contains("Lt"));
+}
+
+#[test]
+fn bytecode_does_not_run() {
+    let (out, ok) = emit("bytecode", "println(1)\n");
+    assert!(ok);
+    assert!(out.starts_with("== script ==\n0000    1 OpGet 0"));
+    assert!(out.contains("0004    | OpCall 1\n"));
+    assert!(!out.contains("\n1\n"));
+}
+
+#[test]
+fn bytecode_does_not_run_modules() {
+    let module = env::temp_dir().join("table_emit_effect.tb");
+    fs::write(&module, "println(\"module side effect\")\npub fn f() { 1 }\n").unwrap();
+    let (out, ok) = emit_file("table-emit-modules", "bytecode", "use table_emit_effect.f\nprintln(f())\n");
+    assert!(ok);
+    assert!(out.starts_with("== script ==\n"));
+    assert!(!out.contains("module side effect"));
+}
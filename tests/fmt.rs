@@ -0,0 +1,36 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+const EXE_PATH: &str = "./target/debug/table";
+
+fn fmt(args: &[&str]) -> bool {
+    let status = Command::new(EXE_PATH)
+        .arg("fmt")
+        .args(args)
+        .status()
+        .expect("failed to run formatter");
+    status.success()
+}
+
+#[test]
+fn check_then_format() {
+    let path = env::temp_dir().join("table-fmt-check.tb");
+    let path = path.to_str().unwrap();
+    fs::write(path, "x=1;y=x+2\n").unwrap();
+
+    assert!(!fmt(&["--check", path]));
+    assert_eq!(fs::read_to_string(path).unwrap(), "x=1;y=x+2\n");
+    assert!(fmt(&[path]));
+    assert_eq!(fs::read_to_string(path).unwrap(), "x = 1\ny = x + 2\n");
+    assert!(fmt(&["--check", path]));
+}
+
+#[test]
+fn invalid_source() {
+    let path = env::temp_dir().join("table-fmt-invalid.tb");
+    let path = path.to_str().unwrap();
+    fs::write(path, "x = (1,\n").unwrap();
+    assert!(!fmt(&[path]));
+    assert_eq!(fs::read_to_string(path).unwrap(), "x = (1,\n");
+}